@@ -0,0 +1,103 @@
+use std::{
+    io, mem,
+    net::TcpListener,
+    os::fd::{AsRawFd, OwnedFd, RawFd},
+};
+
+/// Adopts the listening TCP socket `fd`, as passed in by e.g. systemd
+/// socket activation, instead of binding one.
+///
+/// Fails unless `fd` is an IPv4 or IPv6 stream socket that is already
+/// listening, closing it.
+pub fn adopt(fd: OwnedFd) -> io::Result<TcpListener> {
+    let raw = fd.as_raw_fd();
+    if !matches!(
+        sock_opt(raw, libc::SO_DOMAIN)?,
+        libc::AF_INET | libc::AF_INET6
+    ) {
+        return Err(invalid(raw, "not an internet socket"));
+    }
+    if sock_opt(raw, libc::SO_TYPE)? != libc::SOCK_STREAM {
+        return Err(invalid(raw, "not a stream socket"));
+    }
+    if sock_opt(raw, libc::SO_ACCEPTCONN)? == 0 {
+        return Err(invalid(raw, "not listening"));
+    }
+
+    let listener = TcpListener::from(fd);
+    // The activating process may have handed it over in either mode.
+    listener.set_nonblocking(false)?;
+    Ok(listener)
+}
+
+/// Reads the integer socket option `name` of `fd`.
+fn sock_opt(fd: RawFd, name: libc::c_int) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: `value` and `len` outlive the call and `len` is the size of
+    // `value`; an invalid `fd` is reported as an error.
+    let res = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            name,
+            (&raw mut value).cast(),
+            &mut len,
+        )
+    };
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+fn invalid(fd: RawFd, reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("fd {fd} can't be listened on: {reason}"),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::{self, File},
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        os::{fd::OwnedFd, unix::net::UnixListener},
+    };
+
+    use crate::{activation, test::temp_path};
+
+    #[test]
+    fn adopted_listener_accepts_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let listener = activation::adopt(OwnedFd::from(listener)).unwrap();
+        let mut client = TcpStream::connect(addr).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        client.write_all(b"hi").unwrap();
+
+        let mut buf = [0; 2];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hi");
+    }
+
+    #[test]
+    fn non_socket_is_refused() {
+        let file = File::open("/dev/null").unwrap();
+
+        assert!(activation::adopt(OwnedFd::from(file)).is_err());
+    }
+
+    #[test]
+    fn unix_listener_is_refused() {
+        let path = temp_path("activation");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let err = activation::adopt(OwnedFd::from(listener)).unwrap_err();
+        assert!(err.to_string().ends_with("not an internet socket"), "{err}");
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+/// A set of blocked peer IP addresses.
+///
+/// When backed by a file, the blocklist is stored with one address per
+/// line and every change made through [`Blocklist::block`] and
+/// [`Blocklist::unblock`] is written back, so blocks survive restarts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Blocklist {
+    path: Option<PathBuf>,
+    ips: BTreeSet<IpAddr>,
+}
+
+impl Blocklist {
+    /// Loads the blocklist stored at `path`.
+    ///
+    /// A missing file is treated as an empty blocklist, which is created
+    /// on the first change. Duplicate entries are merged.
+    pub fn load(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let ips = match fs::read_to_string(&path) {
+            Ok(contents) => parse(&contents)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            path: Some(path),
+            ips,
+        })
+    }
+
+    /// Returns `true` if `ip` is blocked.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.ips.contains(&ip)
+    }
+
+    /// Blocks `ip`, returning `false` if it was already blocked.
+    pub fn block(&mut self, ip: IpAddr) -> io::Result<bool> {
+        let inserted = self.ips.insert(ip);
+        if inserted {
+            self.save()?;
+        }
+        Ok(inserted)
+    }
+
+    /// Unblocks `ip`, returning `false` if it wasn't blocked.
+    pub fn unblock(&mut self, ip: IpAddr) -> io::Result<bool> {
+        let removed = self.ips.remove(&ip);
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        match &self.path {
+            Some(path) => write(path, &self.ips),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse(contents: &str) -> io::Result<BTreeSet<IpAddr>> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.parse()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        })
+        .collect()
+}
+
+fn write(path: &Path, ips: &BTreeSet<IpAddr>) -> io::Result<()> {
+    let contents: String = ips.iter().map(|ip| format!("{ip}\n")).collect();
+    fs::write(path, contents)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, net::IpAddr};
+
+    use crate::{blocklist::Blocklist, test::temp_path};
+
+    #[test]
+    fn missing_file_is_empty() {
+        let path = temp_path("blocklist");
+        let blocklist = Blocklist::load(&path).unwrap();
+
+        assert!(!blocklist.contains("127.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn block_persists_deduplicated() {
+        let path = temp_path("blocklist");
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+        let mut blocklist = Blocklist::load(&path).unwrap();
+        assert!(blocklist.block(ip).unwrap());
+        assert!(!blocklist.block(ip).unwrap());
+
+        let reloaded = Blocklist::load(&path).unwrap();
+        assert!(reloaded.contains(ip));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "10.0.0.1\n");
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    net::{self, SocketAddr},
+    num,
+};
+
+/// Number of messages `bench` sends unless told otherwise.
+const BENCH_COUNT: usize = 1000;
+
+/// How to invoke the binary, shown when its arguments don't parse.
+pub const USAGE: &str = "\
+usage: dust run --listen <addr> [--connect <addr>]... [--seen-capacity <n>] [options]
+       dust send <addr> <text> [options]
+       dust recv <addr> [--count <n>] [options]
+       dust bench [--count <n>] [options]";
+
+/// What the binary was asked to do, as given by its subcommand.
+///
+/// Options for the node itself, like `--nick`, may follow any subcommand
+/// and are parsed separately into the node's configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// `run --listen <addr>`: runs the interactive node on `addr`.
+    ///
+    /// Plain `<addr>` is accepted as well, as before there were subcommands.
+    Run(SocketAddr),
+    /// `send <addr> <text>`: sends a single message to the peer at `addr`.
+    Send { to: SocketAddr, text: String },
+    /// `recv <addr> [--count N]`: listens on `addr` until `count` messages
+    /// were received, one by default.
+    Recv { listen: SocketAddr, count: usize },
+    /// `bench [--count N]`: measures how fast `count` messages pass between
+    /// two local nodes.
+    Bench { count: usize },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CliError {
+    #[error("missing subcommand, expected `run`, `send`, `recv` or `bench`")]
+    MissingSubcommand,
+    #[error("unknown subcommand `{0}`")]
+    UnknownSubcommand(String),
+    #[error("missing {0}")]
+    MissingArgument(&'static str),
+    #[error(transparent)]
+    AddrParseError(#[from] net::AddrParseError),
+    #[error(transparent)]
+    ParseIntError(#[from] num::ParseIntError),
+}
+
+impl Mode {
+    /// Parses the subcommand and its arguments from `args`, which exclude
+    /// the program name.
+    pub fn from_args(args: &[String]) -> Result<Self, CliError> {
+        let (subcommand, rest) = args.split_first().ok_or(CliError::MissingSubcommand)?;
+        let positional = |at: usize, name| {
+            rest.get(at)
+                .filter(|arg| !arg.starts_with("--"))
+                .ok_or(CliError::MissingArgument(name))
+        };
+
+        match subcommand.as_str() {
+            "run" => {
+                let listen =
+                    flag(rest, "--listen")?.ok_or(CliError::MissingArgument("--listen"))?;
+                Ok(Mode::Run(listen.parse()?))
+            }
+            "send" => Ok(Mode::Send {
+                to: positional(0, "peer address")?.parse()?,
+                text: positional(1, "message text")?.clone(),
+            }),
+            "recv" => Ok(Mode::Recv {
+                listen: positional(0, "listening address")?.parse()?,
+                count: count(rest, 1)?,
+            }),
+            "bench" => Ok(Mode::Bench {
+                count: count(rest, BENCH_COUNT)?,
+            }),
+            other => match other.parse() {
+                Ok(addr) => Ok(Mode::Run(addr)),
+                Err(_) => Err(CliError::UnknownSubcommand(other.to_string())),
+            },
+        }
+    }
+}
+
+/// Returns the value given for `name` in `args`, if it is given.
+fn flag<'a>(args: &'a [String], name: &'static str) -> Result<Option<&'a String>, CliError> {
+    match args.iter().position(|arg| arg == name) {
+        Some(at) => args
+            .get(at + 1)
+            .map(Some)
+            .ok_or(CliError::MissingArgument(name)),
+        None => Ok(None),
+    }
+}
+
+fn count(args: &[String], default: usize) -> Result<usize, CliError> {
+    match flag(args, "--count")? {
+        Some(count) => Ok(count.parse()?),
+        None => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cli::{CliError, Mode};
+
+    fn parse(args: &str) -> Result<Mode, CliError> {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        Mode::from_args(&args)
+    }
+
+    #[test]
+    fn run_listens_on_address() {
+        let addr = "127.0.0.1:9000".parse().unwrap();
+
+        assert_eq!(
+            parse("run --nick bob --listen 127.0.0.1:9000").unwrap(),
+            Mode::Run(addr)
+        );
+        assert_eq!(parse("127.0.0.1:9000 --nick bob").unwrap(), Mode::Run(addr));
+        assert!(matches!(
+            parse("run"),
+            Err(CliError::MissingArgument("--listen"))
+        ));
+    }
+
+    #[test]
+    fn send_takes_peer_and_text() {
+        assert_eq!(
+            parse("send 127.0.0.1:9000 hello --nick bob").unwrap(),
+            Mode::Send {
+                to: "127.0.0.1:9000".parse().unwrap(),
+                text: String::from("hello"),
+            }
+        );
+        assert!(parse("send 127.0.0.1:9000").is_err());
+    }
+
+    #[test]
+    fn recv_counts_default_to_one() {
+        let listen = "127.0.0.1:9000".parse().unwrap();
+
+        assert_eq!(
+            parse("recv 127.0.0.1:9000").unwrap(),
+            Mode::Recv { listen, count: 1 }
+        );
+        assert_eq!(
+            parse("recv 127.0.0.1:9000 --count 5").unwrap(),
+            Mode::Recv { listen, count: 5 }
+        );
+        assert!(parse("recv 127.0.0.1:9000 --count").is_err());
+    }
+
+    #[test]
+    fn bench_takes_optional_count() {
+        assert_eq!(parse("bench").unwrap(), Mode::Bench { count: 1000 });
+        assert_eq!(
+            parse("bench --count 10").unwrap(),
+            Mode::Bench { count: 10 }
+        );
+        assert!(matches!(
+            parse("serve"),
+            Err(CliError::UnknownSubcommand(_))
+        ));
+        assert!(matches!(parse(""), Err(CliError::MissingSubcommand)));
+    }
+}
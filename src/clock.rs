@@ -0,0 +1,75 @@
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+use std::{cell::Cell, rc::Rc, time::Duration};
+
+/// A source of the current time.
+///
+/// The node reads the time through a [`Clock`] rather than asking the
+/// system directly, so tests can drive timeouts without waiting on them.
+pub trait Clock {
+    /// Returns the current monotonic time.
+    fn now_instant(&self) -> Instant;
+    /// Returns the current wall-clock time in milliseconds since the Unix
+    /// epoch.
+    fn now_unix_millis(&self) -> u64;
+}
+
+/// The clock of the system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct System;
+
+impl Clock for System {
+    fn now_instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn now_unix_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64)
+    }
+}
+
+/// A clock that only moves when advanced, for tests.
+///
+/// Clones share the same time, so a test can keep one to advance a clock
+/// handed to a node.
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct Manual {
+    start: Instant,
+    start_unix_millis: u64,
+    elapsed: Rc<Cell<Duration>>,
+}
+
+#[cfg(test)]
+impl Default for Manual {
+    /// Creates a [`Manual`] clock stopped at the current time.
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            start_unix_millis: System.now_unix_millis(),
+            elapsed: Rc::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Manual {
+    /// Moves the clock forward by `by`.
+    pub fn advance(&self, by: Duration) {
+        self.elapsed.set(self.elapsed.get() + by);
+    }
+}
+
+#[cfg(test)]
+impl Clock for Manual {
+    fn now_instant(&self) -> Instant {
+        self.start + self.elapsed.get()
+    }
+
+    fn now_unix_millis(&self) -> u64 {
+        self.start_unix_millis + self.elapsed.get().as_millis() as u64
+    }
+}
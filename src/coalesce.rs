@@ -0,0 +1,166 @@
+use std::{
+    io::{self, Write},
+    mem,
+    time::{Duration, Instant},
+};
+
+/// Outbound frames held back for a short window, so that each receiver
+/// gets all of them in a single write.
+///
+/// A longer window saves writes when many small messages are sent in quick
+/// succession, at the cost of delaying each of them by up to the window.
+/// With a threshold, frames are also written as soon as that many bytes
+/// are buffered for any receiver, so bursts don't wait out the window.
+#[derive(Debug, Clone)]
+pub struct Coalesce<K> {
+    window: Duration,
+    threshold: Option<usize>,
+    /// When the first of the pending frames was buffered.
+    since: Option<Instant>,
+    pending: Vec<(K, Vec<u8>)>,
+}
+
+impl<K: PartialEq> Coalesce<K> {
+    /// Creates an empty [`Coalesce`] holding frames back for `window`.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            threshold: None,
+            since: None,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Writes the frames once `threshold` bytes are buffered for a single
+    /// receiver, even before the window has passed.
+    pub fn with_threshold(self, threshold: usize) -> Self {
+        Self {
+            threshold: Some(threshold),
+            ..self
+        }
+    }
+
+    /// Buffers `frame` to be written to `to`, after any frames already
+    /// buffered for it.
+    pub fn push(&mut self, to: K, frame: &[u8], now: Instant) {
+        self.since.get_or_insert(now);
+        match self.pending.iter_mut().find(|(key, _)| *key == to) {
+            Some((_, buf)) => buf.extend_from_slice(frame),
+            None => self.pending.push((to, frame.to_vec())),
+        }
+    }
+
+    /// Returns `true` once the window has passed for the oldest buffered
+    /// frame, or once the threshold is reached.
+    pub fn is_due(&self, now: Instant) -> bool {
+        let full = self
+            .threshold
+            .is_some_and(|threshold| self.pending.iter().any(|(_, buf)| buf.len() >= threshold));
+        full || self.since.is_some_and(|since| now >= since + self.window)
+    }
+
+    /// Writes everything buffered, one write per writer, returning the number
+    /// of bytes written to each.
+    ///
+    /// Writers are matched up with their frames by `key`; frames for which
+    /// no writer is left are dropped.
+    pub fn flush<W: Write>(
+        &mut self,
+        writers: &mut [W],
+        key: impl Fn(&W) -> Option<K>,
+    ) -> Vec<(K, io::Result<usize>)> {
+        self.since = None;
+        let mut pending = mem::take(&mut self.pending);
+
+        writers
+            .iter_mut()
+            .filter_map(|writer| {
+                let to = key(writer)?;
+                let at = pending.iter().position(|(key, _)| *key == to)?;
+                let (to, buf) = pending.swap_remove(at);
+                Some((to, writer.write_all(&buf).map(|()| buf.len())))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{self, Write},
+        time::{Duration, Instant},
+    };
+
+    use crate::coalesce::Coalesce;
+
+    #[derive(Debug, Default)]
+    struct Counting {
+        id: usize,
+        writes: usize,
+        bytes: Vec<u8>,
+    }
+
+    impl Write for Counting {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            self.bytes.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn frames_within_window_are_written_at_once() {
+        let start = Instant::now();
+        let window = Duration::from_millis(10);
+        let mut coalesce = Coalesce::new(window);
+        let mut writers = [
+            Counting {
+                id: 0,
+                ..Counting::default()
+            },
+            Counting {
+                id: 1,
+                ..Counting::default()
+            },
+        ];
+
+        for frame in [b"one", b"two", b"six"] {
+            coalesce.push(0, frame, start);
+            coalesce.push(1, frame, start);
+        }
+        assert!(!coalesce.is_due(start + window / 2));
+        assert!(coalesce.is_due(start + window));
+
+        let written = coalesce.flush(&mut writers, |writer| Some(writer.id));
+
+        assert_eq!(written.len(), 2);
+        for writer in &writers {
+            assert_eq!(writer.writes, 1);
+            assert_eq!(writer.bytes, b"onetwosix");
+        }
+        assert!(!coalesce.is_due(start + window));
+    }
+
+    #[test]
+    fn reaching_the_threshold_writes_in_order_before_the_window() {
+        let start = Instant::now();
+        let mut coalesce = Coalesce::new(Duration::from_secs(60)).with_threshold(6);
+        let mut writers = [Counting::default()];
+
+        coalesce.push(0, b"one", start);
+        assert!(!coalesce.is_due(start));
+        coalesce.push(0, b"two", start);
+        assert!(coalesce.is_due(start));
+        coalesce.flush(&mut writers, |writer| Some(writer.id));
+        coalesce.push(0, b"six", start);
+        coalesce.flush(&mut writers, |writer| Some(writer.id));
+
+        assert_eq!(writers[0].writes, 2);
+        assert_eq!(writers[0].bytes, b"onetwosix");
+        assert!(!coalesce.is_due(start));
+    }
+}
@@ -0,0 +1,44 @@
+/// Thresholds past which an interactive broadcast is only sent once confirmed.
+///
+/// Each threshold is off unless set; a broadcast needs confirming as soon as
+/// it exceeds either of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Confirm {
+    /// Number of peers a broadcast may reach without confirming.
+    pub peers: Option<usize>,
+    /// Number of text bytes a broadcast may carry without confirming.
+    pub bytes: Option<usize>,
+}
+
+impl Confirm {
+    /// Returns `true` if broadcasting `bytes` of text to `peers` peers
+    /// should be confirmed first.
+    pub fn required(&self, peers: usize, bytes: usize) -> bool {
+        self.peers.is_some_and(|max| peers > max) || self.bytes.is_some_and(|max| bytes > max)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::confirm::Confirm;
+
+    #[test]
+    fn confirmation_required_only_past_a_threshold() {
+        let confirm = Confirm {
+            peers: Some(3),
+            bytes: Some(100),
+        };
+
+        assert!(!confirm.required(3, 100));
+        assert!(confirm.required(4, 100));
+        assert!(confirm.required(3, 101));
+        assert!(!Confirm::default().required(1000, 1000));
+        assert!(
+            Confirm {
+                peers: None,
+                bytes: Some(10)
+            }
+            .required(0, 11)
+        );
+    }
+}
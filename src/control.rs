@@ -0,0 +1,74 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader},
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    sync::mpsc,
+    thread::spawn,
+    time::Duration,
+};
+
+use crate::{Command, Input, InputError, Origin};
+
+/// Longest a reply waits for the client to take it, see [`listen`].
+const REPLY_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Listens on a Unix socket at `path` for commands, sending them over `tx`.
+///
+/// Every connection may issue any number of commands, one per line, in
+/// the same syntax as on stdin, with messages held to frames of `capacity`
+/// bytes. The notices a command leads to are written back over the
+/// connection it came from, one per line. A stale socket left at `path` by
+/// an earlier run is replaced, but any other file there is left alone.
+pub fn listen(path: &Path, tx: mpsc::Sender<Input>, capacity: usize) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+        Err(err) => return Err(err),
+    }
+
+    let listener = UnixListener::bind(path)?;
+
+    spawn(move || -> io::Result<()> {
+        loop {
+            let (stream, _) = listener.accept()?;
+            let tx = tx.clone();
+            spawn(move || serve(stream, tx, capacity));
+        }
+    });
+
+    Ok(())
+}
+
+/// Forwards every command read from `stream` until it is closed, along
+/// with a handle to reply over.
+fn serve(stream: UnixStream, tx: mpsc::Sender<Input>, capacity: usize) {
+    // A client that doesn't read its replies only holds up the node briefly.
+    if stream.set_write_timeout(Some(REPLY_TIMEOUT)).is_err() {
+        return;
+    }
+    let Ok(reply) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines() {
+        let command = line
+            .map_err(InputError::from)
+            .and_then(|line| Command::parse(&line, capacity).map_err(InputError::from));
+        let Ok(reply) = reply.try_clone() else {
+            return;
+        };
+
+        if tx.send((command, Origin::Control(reply))).is_err() {
+            return;
+        }
+    }
+}
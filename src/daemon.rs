@@ -0,0 +1,34 @@
+use std::{fs::File, io, os::fd::AsRawFd, process};
+
+/// Detaches the process from its terminal to keep running in the background.
+///
+/// The process forks, and the parent exits straight away. The child starts
+/// a new session and has its standard streams redirected to `/dev/null`,
+/// so it must be commanded through the control socket instead.
+///
+/// Must be called before any threads are spawned, since only the calling
+/// thread survives the fork.
+pub fn detach() -> io::Result<()> {
+    // SAFETY: no other threads exist yet, so the child can safely continue
+    // running arbitrary code.
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()),
+        0 => (),
+        _ => process::exit(0),
+    }
+
+    // SAFETY: `setsid` has no preconditions; the child isn't a group leader.
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let null = File::options().read(true).write(true).open("/dev/null")?;
+    for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        // SAFETY: both descriptors are open for the duration of the call.
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use std::net::SocketAddr;
+
+use crate::{
+    clock::{self, Clock},
+    msg::{self, Msg},
+};
+
+/// Renders received messages for the terminal.
+///
+/// Every rendered message is assigned a local receive-sequence number,
+/// starting at `1` and increasing by one per displayed message. The number
+/// is purely local and unrelated to anything sent over the wire; it is only
+/// included in the output when `show_seq` is set.
+///
+/// With `show_age`, stamped messages also show how long ago they were
+/// created, which is the time they took to arrive as far as the clocks
+/// of sender and receiver agree.
+///
+/// Messages carrying a nick are shown as from that nick, which unlike the
+/// address stays the same when the sender reconnects. Messages spanning
+/// several lines are rendered with the same prefix on every line, so
+/// continuation lines can't be mistaken for a new message. Any other
+/// control characters are escaped, see [`msg::sanitize`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Printer {
+    show_seq: bool,
+    show_age: bool,
+    seq: u64,
+}
+
+impl Printer {
+    /// Creates a [`Printer`] that has not displayed any messages yet.
+    pub fn new(show_seq: bool) -> Self {
+        Self {
+            show_seq,
+            show_age: false,
+            seq: 0,
+        }
+    }
+
+    /// Sets whether messages are shown with their age.
+    pub fn with_age(self, show_age: bool) -> Self {
+        Self { show_age, ..self }
+    }
+
+    /// Renders a message received from `addr`, advancing the sequence number.
+    pub fn render(&mut self, addr: SocketAddr, msg: &Msg) -> String {
+        self.render_at(addr, msg, clock::System.now_unix_millis())
+    }
+
+    /// Renders a message like [`Printer::render`], as received at `now` in
+    /// milliseconds since the Unix epoch.
+    fn render_at(&mut self, addr: SocketAddr, msg: &Msg, now: u64) -> String {
+        self.seq += 1;
+
+        let sender = match msg.nick() {
+            Some(nick) => msg::sanitize(nick),
+            None => addr.to_string(),
+        };
+        let sender = match msg.created_at() {
+            Some(created_at) if self.show_age => {
+                format!("{sender}, {}ms old", now.saturating_sub(created_at))
+            }
+            _ => sender,
+        };
+        let prefix = if self.show_seq {
+            format!("#{} {sender}: ", self.seq)
+        } else {
+            format!("{sender}: ")
+        };
+
+        msg.text()
+            .split('\n')
+            .map(|line| format!("{prefix}{}", msg::sanitize(line)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use crate::{display::Printer, msg::Msg};
+
+    #[test]
+    fn consecutive_messages_get_consecutive_seq() {
+        let addr: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        let first: Msg = String::from("first").try_into().unwrap();
+        let second: Msg = String::from("second").try_into().unwrap();
+        let mut printer = Printer::new(true);
+
+        assert_eq!(printer.render(addr, &first), "#1 127.0.0.1:9000: first");
+        assert_eq!(printer.render(addr, &second), "#2 127.0.0.1:9000: second");
+    }
+
+    #[test]
+    fn seq_hidden_by_default() {
+        let addr: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        let msg: Msg = String::from("hello").try_into().unwrap();
+        let mut printer = Printer::new(false);
+
+        assert_eq!(printer.render(addr, &msg), "127.0.0.1:9000: hello");
+    }
+
+    #[test]
+    fn multi_line_messages_prefix_every_line() {
+        let addr: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        let msg = Msg::new("one\ntwo").unwrap();
+        let mut printer = Printer::new(true);
+
+        assert_eq!(
+            printer.render(addr, &msg),
+            "#1 127.0.0.1:9000: one\n#1 127.0.0.1:9000: two"
+        );
+    }
+
+    #[test]
+    fn age_is_shown_when_asked_for() {
+        let addr: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        let msg = Msg::new("hello").unwrap();
+        let now = msg.created_at().unwrap() + 250;
+
+        let mut printer = Printer::new(false).with_age(true);
+        assert_eq!(
+            printer.render_at(addr, &msg, now),
+            "127.0.0.1:9000, 250ms old: hello"
+        );
+        let mut printer = Printer::new(false);
+        assert_eq!(printer.render_at(addr, &msg, now), "127.0.0.1:9000: hello");
+    }
+
+    #[test]
+    fn control_characters_are_printed_inert() {
+        let addr: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        let msg = Msg::new("\x1b[2Jgone\rbob: fake\x08!\nnext").unwrap();
+        let mut printer = Printer::new(false);
+
+        assert_eq!(
+            printer.render(addr, &msg),
+            "127.0.0.1:9000: \\u{1b}[2Jgone\\rbob: fake\\u{8}!\n127.0.0.1:9000: next"
+        );
+    }
+
+    #[test]
+    fn nick_is_shown_instead_of_address() {
+        let addr: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        let msg = Msg::new("hello").unwrap().with_nick("alice").unwrap();
+        let mut printer = Printer::new(false);
+
+        assert_eq!(printer.render(addr, &msg), "alice: hello");
+    }
+}
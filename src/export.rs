@@ -0,0 +1,109 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{msg::Msg, queue::DedupQueue};
+
+/// A single line of an exported seen-message log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub uuid: Uuid,
+    pub text: String,
+    /// When the message was created, in milliseconds since the Unix epoch,
+    /// if it was stamped. Missing from exports that predate the stamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+}
+
+impl From<&Msg> for Entry {
+    fn from(msg: &Msg) -> Self {
+        Self {
+            uuid: msg.uuid(),
+            text: msg.text().to_string(),
+            created_at: msg.created_at(),
+        }
+    }
+}
+
+/// Writes every message in `seen` to `path` as one JSON object per line,
+/// oldest first, and returns the number of messages written.
+pub fn write_seen(path: &Path, seen: &DedupQueue<Msg>) -> io::Result<usize> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let mut count = 0;
+
+    for msg in seen.iter() {
+        serde_json::to_writer(&mut file, &Entry::from(msg))?;
+        file.write_all(b"\n")?;
+        count += 1;
+    }
+
+    file.flush()?;
+    Ok(count)
+}
+
+/// Reads the messages exported to `path` by [`write_seen`], oldest first.
+///
+/// Blank lines are skipped; any other line that isn't an entry fails the
+/// whole read.
+pub fn read_seen(path: &Path) -> io::Result<Vec<Entry>> {
+    BufReader::new(File::open(path)?)
+        .lines()
+        .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use uuid::Uuid;
+
+    use crate::{
+        export::{Entry, read_seen, write_seen},
+        msg::Msg,
+        queue::DedupQueue,
+        test::temp_path,
+    };
+
+    #[test]
+    fn export_writes_one_line_per_message() {
+        let path = temp_path("seen");
+        let first = Msg::new("hello").unwrap().with_created_at(1_000).unwrap();
+        let second = Msg::from_parts("world", Uuid::new_v4(), None).unwrap();
+        let mut seen = DedupQueue::new(16);
+        seen.push(first.clone());
+        seen.push(second.clone());
+
+        assert_eq!(write_seen(&path, &seen).unwrap(), 2);
+
+        let expected = format!(
+            "{{\"uuid\":\"{}\",\"text\":\"hello\",\"created_at\":1000}}\n{{\"uuid\":\"{}\",\"text\":\"world\"}}\n",
+            first.uuid(),
+            second.uuid()
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn exported_messages_read_back_in_order() {
+        let path = temp_path("seen");
+        let mut seen = DedupQueue::new(16);
+        seen.push(Msg::new("hello").unwrap());
+        seen.push(Msg::new("world").unwrap());
+        write_seen(&path, &seen).unwrap();
+
+        let entries = read_seen(&path).unwrap();
+
+        let expected: Vec<_> = seen.iter().map(Entry::from).collect();
+        assert_eq!(entries, expected);
+        fs::remove_file(path).unwrap();
+    }
+}
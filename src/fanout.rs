@@ -0,0 +1,39 @@
+use std::net::SocketAddr;
+
+use crate::{coalesce::Coalesce, faults::Faults, msg::CAPACITY, rng::Rng, stats::Delivery};
+
+/// How messages are fanned out to peers.
+#[derive(Debug, Clone, Default)]
+pub struct Fanout {
+    /// Hold a message back from every peer that delivered it, rather than
+    /// only from the first.
+    pub split_horizon: bool,
+    /// Shuffles the order peers are written to in every round, if set.
+    pub shuffle: Option<Rng>,
+    /// Faults injected into every write.
+    pub faults: Faults,
+    /// Report a hex dump of every frame written, and of every frame read.
+    pub hexdump: bool,
+    /// Holds frames back to write them to each peer together, if set.
+    pub coalesce: Option<Coalesce<SocketAddr>>,
+    /// Size of the frames messages are split into, `CAPACITY` if unset.
+    pub capacity: Option<usize>,
+    /// Every delivery made so far, across all messages.
+    pub deliveries: Delivery,
+}
+
+impl Fanout {
+    /// Returns the size of the frames messages are split into.
+    pub fn capacity(&self) -> usize {
+        self.capacity.unwrap_or(CAPACITY)
+    }
+
+    /// Returns the order in which to write to the peers at the indices
+    /// `targets`, shuffled when `shuffle` is set.
+    pub fn order(&mut self, mut targets: Vec<usize>) -> Vec<usize> {
+        if let Some(rng) = &mut self.shuffle {
+            rng.shuffle(&mut targets);
+        }
+        targets
+    }
+}
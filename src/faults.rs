@@ -0,0 +1,118 @@
+use std::{thread, time::Duration};
+
+use crate::rng::Rng;
+
+/// Faults injected into the write path to test resilience.
+///
+/// Each outbound frame is dropped with probability `loss`, and frames
+/// that are sent are held back by `delay` first. The delay blocks the
+/// node, so this is strictly a testing aid, never for production use.
+#[derive(Debug, Clone)]
+pub struct Faults {
+    loss: f64,
+    delay: Duration,
+    rng: Rng,
+    lost: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum FaultsError {
+    #[error("invalid fault `{0}`, expected `loss=<0..1>` or `delay=<n>ms`")]
+    InvalidFault(String),
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        Self {
+            loss: 0.0,
+            delay: Duration::ZERO,
+            rng: Rng::from_entropy(),
+            lost: 0,
+        }
+    }
+}
+
+impl Faults {
+    /// Replaces the injected faults.
+    pub fn set(&mut self, loss: f64, delay: Duration) {
+        self.loss = loss;
+        self.delay = delay;
+    }
+
+    /// Decides whether the next outbound frame gets through, sleeping
+    /// for the configured delay if it does.
+    pub fn pass(&mut self) -> bool {
+        if self.loss > 0.0 && self.rng.next_f64() < self.loss {
+            self.lost += 1;
+            return false;
+        }
+
+        if !self.delay.is_zero() {
+            thread::sleep(self.delay);
+        }
+
+        true
+    }
+
+    /// Returns the number of frames dropped so far.
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+}
+
+/// Parses space-separated `loss=<fraction>` and `delay=<n>ms` settings.
+/// Settings left out default to no faults.
+pub fn parse(args: &str) -> Result<(f64, Duration), FaultsError> {
+    let mut loss = 0.0;
+    let mut delay = Duration::ZERO;
+
+    for setting in args.split_whitespace() {
+        let invalid = || FaultsError::InvalidFault(setting.to_string());
+
+        match setting.split_once('=').ok_or_else(invalid)? {
+            ("loss", value) => {
+                loss = value.parse().map_err(|_| invalid())?;
+                if !(0.0..=1.0).contains(&loss) {
+                    return Err(invalid());
+                }
+            }
+            ("delay", value) => {
+                let millis = value.strip_suffix("ms").ok_or_else(invalid)?;
+                delay = Duration::from_millis(millis.parse().map_err(|_| invalid())?);
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok((loss, delay))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::faults::{self, Faults};
+
+    #[test]
+    fn parse_settings() {
+        assert_eq!(
+            faults::parse("loss=0.1 delay=50ms"),
+            Ok((0.1, Duration::from_millis(50)))
+        );
+        assert_eq!(faults::parse(""), Ok((0.0, Duration::ZERO)));
+        assert!(faults::parse("loss=2").is_err());
+        assert!(faults::parse("delay=5s").is_err());
+        assert!(faults::parse("jitter=1").is_err());
+    }
+
+    #[test]
+    fn loss_bounds() {
+        let mut faults = Faults::default();
+
+        faults.set(1.0, Duration::ZERO);
+        assert!((0..100).all(|_| !faults.pass()));
+
+        faults.set(0.0, Duration::ZERO);
+        assert!((0..100).all(|_| faults.pass()));
+    }
+}
@@ -0,0 +1,252 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use uuid::Uuid;
+
+use crate::{
+    handshake::{FRAGMENT, MARKER},
+    msg::{MAX_CAPACITY, Msg, TEXT_LEN_SIZE, UUID_SIZE},
+};
+
+const INDEX_SIZE: usize = 2;
+const TOTAL_SIZE: usize = 2;
+const CHUNK_LEN_SIZE: usize = 2;
+/// Number of bytes of a fragment before its chunk of the message.
+pub const HEADER: usize = TEXT_LEN_SIZE + 1 + UUID_SIZE + INDEX_SIZE + TOTAL_SIZE + CHUNK_LEN_SIZE;
+
+/// Number of messages being reassembled at once before the oldest is given
+/// up on, so peers can't make a node hold on to unbounded fragments.
+const MAX_PARTIAL: usize = 64;
+
+/// One frame's worth of a message too long for a single frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fragment {
+    pub uuid: Uuid,
+    pub index: u16,
+    pub total: u16,
+    pub chunk: Vec<u8>,
+}
+
+/// Returns the frames of `capacity` bytes that carry `msg`.
+///
+/// A message that fits is a single frame as usual. A longer one is split
+/// into fragments, each holding the marker of a hello, see
+/// [`crate::handshake::hello`], the fragment kind, the UUID of the message,
+/// the index of the fragment and the number of fragments as big-endian
+/// `u16`s, and a chunk of the serialized message after its length as a
+/// big-endian `u16`, padded with `0`s to `capacity`.
+pub fn split(msg: Msg, capacity: usize) -> Vec<Vec<u8>> {
+    let len = msg.encoded_len();
+    if len <= capacity {
+        // Fits as is, so the creation time isn't dropped.
+        let msg = msg.with_capacity(capacity).expect("message fits the frame");
+        return vec![msg.into_bytes()];
+    }
+
+    let uuid = msg.uuid();
+    let mut bytes = msg.into_bytes();
+    bytes.truncate(len);
+    let chunks: Vec<_> = bytes.chunks(capacity - HEADER).collect();
+    // Messages are at most `MAX_CAPACITY` long, and every chunk holds at
+    // least one byte, so their count fits a `u16`.
+    let total = chunks.len() as u16;
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut frame = vec![0; capacity];
+            frame[..TEXT_LEN_SIZE].copy_from_slice(&MARKER);
+            frame[TEXT_LEN_SIZE] = FRAGMENT;
+            let mut at = TEXT_LEN_SIZE + 1;
+            frame[at..at + UUID_SIZE].copy_from_slice(uuid.as_bytes());
+            at += UUID_SIZE;
+            frame[at..at + INDEX_SIZE].copy_from_slice(&(index as u16).to_be_bytes());
+            at += INDEX_SIZE;
+            frame[at..at + TOTAL_SIZE].copy_from_slice(&total.to_be_bytes());
+            at += TOTAL_SIZE;
+            frame[at..at + CHUNK_LEN_SIZE].copy_from_slice(&(chunk.len() as u16).to_be_bytes());
+            frame[HEADER..HEADER + chunk.len()].copy_from_slice(chunk);
+            frame
+        })
+        .collect()
+}
+
+/// Returns the fragment in `frame`, if it is one, see [`split`].
+pub fn read(frame: &[u8]) -> Option<Fragment> {
+    if frame.get(..TEXT_LEN_SIZE)? != MARKER || *frame.get(TEXT_LEN_SIZE)? != FRAGMENT {
+        return None;
+    }
+
+    let header = frame.get(TEXT_LEN_SIZE + 1..HEADER)?;
+    let (uuid, rest) = header.split_at(UUID_SIZE);
+    let u16_at = |at: usize| u16::from_be_bytes([rest[at], rest[at + 1]]);
+    let index = u16_at(0);
+    let total = u16_at(INDEX_SIZE);
+    let chunk_len = usize::from(u16_at(INDEX_SIZE + TOTAL_SIZE));
+    let chunk = frame.get(HEADER..HEADER + chunk_len)?.to_vec();
+    Some(Fragment {
+        uuid: Uuid::from_slice(uuid).ok()?,
+        index,
+        total,
+        chunk,
+    })
+}
+
+/// The fragments of a message received so far.
+#[derive(Debug)]
+struct Partial {
+    chunks: Vec<Option<Vec<u8>>>,
+    started: Instant,
+}
+
+/// Fragments received, put back together into messages by UUID.
+///
+/// Fragments may arrive in any order. A message whose fragments don't all
+/// arrive within the timeout is dropped, see [`Reassembly::expire`].
+#[derive(Debug)]
+pub struct Reassembly {
+    partial: HashMap<Uuid, Partial>,
+    timeout: Duration,
+    /// Most fragments a message can be split into, see [`split`].
+    max_total: usize,
+}
+
+impl Reassembly {
+    /// Creates a reassembly of fragments in frames of `capacity` bytes.
+    pub fn new(timeout: Duration, capacity: usize) -> Self {
+        Self {
+            partial: HashMap::new(),
+            timeout,
+            max_total: MAX_CAPACITY.div_ceil(capacity - HEADER),
+        }
+    }
+
+    /// Adds `fragment`, received at `now`, returning the serialized message
+    /// once it is complete.
+    ///
+    /// Fragments that contradict the others of their message, or that would
+    /// make it longer than `MAX_CAPACITY`, are ignored, as are those of more
+    /// fragments than a message that long is split into.
+    pub fn add(&mut self, fragment: Fragment, now: Instant) -> Option<Vec<u8>> {
+        let total = usize::from(fragment.total);
+        let index = usize::from(fragment.index);
+        if index >= total || total > self.max_total {
+            return None;
+        }
+        if !self.partial.contains_key(&fragment.uuid) && self.partial.len() >= MAX_PARTIAL {
+            let oldest = self
+                .partial
+                .iter()
+                .min_by_key(|(_, partial)| partial.started)
+                .map(|(uuid, _)| *uuid)?;
+            self.partial.remove(&oldest);
+        }
+
+        let partial = self
+            .partial
+            .entry(fragment.uuid)
+            .or_insert_with(|| Partial {
+                chunks: vec![None; total],
+                started: now,
+            });
+        if partial.chunks.len() != total {
+            return None;
+        }
+        partial.chunks[index] = Some(fragment.chunk);
+        let received: usize = partial.chunks.iter().flatten().map(Vec::len).sum();
+        if received > MAX_CAPACITY {
+            self.partial.remove(&fragment.uuid);
+            return None;
+        }
+        if partial.chunks.iter().any(Option::is_none) {
+            return None;
+        }
+
+        let partial = self.partial.remove(&fragment.uuid)?;
+        Some(partial.chunks.into_iter().flatten().flatten().collect())
+    }
+
+    /// Drops the messages that are still incomplete a timeout after their
+    /// first fragment arrived, as of `now`, returning how many were dropped.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.partial.len();
+        let timeout = self.timeout;
+        self.partial
+            .retain(|_, partial| now.saturating_duration_since(partial.started) <= timeout);
+        before - self.partial.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use uuid::Uuid;
+
+    use crate::{
+        fragment::{self, Fragment, Reassembly},
+        msg::{self, CAPACITY, Msg},
+    };
+
+    #[test]
+    fn shuffled_fragments_are_reassembled() {
+        let text = "fragmented ".repeat(100);
+        let msg = Msg::new_in(text, msg::MAX_CAPACITY).unwrap();
+        let frames = fragment::split(msg.clone(), CAPACITY);
+        assert_eq!(frames.len(), 3);
+        assert!(frames.iter().all(|frame| frame.len() == CAPACITY));
+
+        let mut reassembly = Reassembly::new(Duration::from_secs(30), CAPACITY);
+        let now = Instant::now();
+        let mut complete = Vec::new();
+        for i in [2, 0, 1] {
+            assert!(complete.is_empty());
+            let fragment = fragment::read(&frames[i]).unwrap();
+            complete.extend(reassembly.add(fragment, now));
+        }
+
+        assert_eq!(complete.len(), 1);
+        assert_eq!(Msg::try_from(complete.remove(0)).unwrap(), msg);
+    }
+
+    #[test]
+    fn short_message_is_a_single_plain_frame() {
+        let msg = Msg::new("short").unwrap();
+        let frames = fragment::split(msg.clone(), CAPACITY);
+
+        assert_eq!(frames, vec![msg.into_bytes()]);
+        assert_eq!(fragment::read(&frames[0]), None);
+    }
+
+    #[test]
+    fn incomplete_message_expires() {
+        let msg = Msg::new_in("x".repeat(2 * CAPACITY), msg::MAX_CAPACITY).unwrap();
+        let frames = fragment::split(msg, CAPACITY);
+        let mut reassembly = Reassembly::new(Duration::from_secs(30), CAPACITY);
+        let now = Instant::now();
+        for frame in &frames[1..] {
+            assert_eq!(reassembly.add(fragment::read(frame).unwrap(), now), None);
+        }
+
+        assert_eq!(reassembly.expire(now + Duration::from_secs(10)), 0);
+        assert_eq!(reassembly.expire(now + Duration::from_secs(31)), 1);
+        let first = fragment::read(&frames[0]).unwrap();
+        assert_eq!(reassembly.add(first, now), None);
+    }
+
+    #[test]
+    fn more_fragments_than_any_message_has_are_ignored() {
+        let mut reassembly = Reassembly::new(Duration::from_secs(30), CAPACITY);
+        let fragment = Fragment {
+            uuid: Uuid::new_v4(),
+            index: 0,
+            total: u16::MAX,
+            chunk: vec![1],
+        };
+
+        assert_eq!(reassembly.add(fragment, Instant::now()), None);
+        assert!(reassembly.partial.is_empty());
+    }
+}
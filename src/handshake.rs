@@ -0,0 +1,193 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::{msg::TEXT_LEN_SIZE, peer::Framing};
+
+/// Marks a frame as a hello, heartbeat, fragment or switch rather than a
+/// message.
+///
+/// It takes the place of a message's text length, which never gets this
+/// large, so nodes that don't know about hellos reject them as malformed.
+/// The byte after it tells the kinds of frame apart.
+pub const MARKER: [u8; TEXT_LEN_SIZE] = [0xff, 0xff];
+const HEARTBEAT: u8 = 0;
+/// The kind of a fragment of a message, see [`crate::fragment::split`].
+pub const FRAGMENT: u8 = 1;
+const SWITCH: u8 = 2;
+const FAMILY_V4: u8 = 4;
+const FAMILY_V6: u8 = 6;
+const NONCE_SIZE: usize = 8;
+
+/// Returns the frame a node sends first on every connection, advertising
+/// the address it listens on.
+///
+/// Without it, a node accepting a connection only knows the ephemeral port
+/// the other side dialed from, which it can't connect back to. The frame
+/// holds the marker, the address family as `4` or `6`, the IP, the port
+/// as a big-endian `u16`, a `1` if the node offers length-prefixed frames
+/// and the node's `nonce` as a big-endian `u64`, padded with `0`s to
+/// `capacity` like a message. Nodes that don't know about framings take the
+/// `0` of the padding there.
+///
+/// The nonce is drawn at random by every node, so a hello carrying our own
+/// tells a link back to ourselves apart, whatever address it was dialed on.
+pub fn hello(listening: SocketAddr, nonce: u64, capacity: usize, framing: Framing) -> Vec<u8> {
+    let mut frame = vec![0; capacity];
+    frame[..TEXT_LEN_SIZE].copy_from_slice(&MARKER);
+    let ip_start = TEXT_LEN_SIZE + 1;
+    let ip_end = match listening.ip() {
+        IpAddr::V4(ip) => {
+            frame[TEXT_LEN_SIZE] = FAMILY_V4;
+            frame[ip_start..ip_start + 4].copy_from_slice(&ip.octets());
+            ip_start + 4
+        }
+        IpAddr::V6(ip) => {
+            frame[TEXT_LEN_SIZE] = FAMILY_V6;
+            frame[ip_start..ip_start + 16].copy_from_slice(&ip.octets());
+            ip_start + 16
+        }
+    };
+    frame[ip_end..ip_end + 2].copy_from_slice(&listening.port().to_be_bytes());
+    frame[ip_end + 2] = u8::from(framing == Framing::LengthPrefixed);
+    frame[ip_end + 3..ip_end + 3 + NONCE_SIZE].copy_from_slice(&nonce.to_be_bytes());
+    frame
+}
+
+/// Returns the frame a node sends every peer periodically, so peers that
+/// went silent can be told from ones that are gone.
+///
+/// The frame holds the marker and a `0`, padded with `0`s to `capacity`
+/// like a message.
+pub fn heartbeat(capacity: usize) -> Vec<u8> {
+    let mut frame = vec![0; capacity];
+    frame[..TEXT_LEN_SIZE].copy_from_slice(&MARKER);
+    frame[TEXT_LEN_SIZE] = HEARTBEAT;
+    frame
+}
+
+/// Returns `true` if `frame` is a heartbeat, see [`heartbeat`].
+pub fn is_heartbeat(frame: &[u8]) -> bool {
+    frame[..TEXT_LEN_SIZE] == MARKER && frame[TEXT_LEN_SIZE] == HEARTBEAT
+}
+
+/// Returns the frame a node sends a peer that offered length-prefixed
+/// frames in its hello, if it offers them too, right before it starts
+/// sending them, see [`hello`].
+///
+/// The frame still has the size of any other, so the peer knows where the
+/// frames it reads switch to the other framing. It holds the marker and a
+/// `2`, padded with `0`s to `capacity`.
+pub fn switch(capacity: usize) -> Vec<u8> {
+    let mut frame = vec![0; capacity];
+    frame[..TEXT_LEN_SIZE].copy_from_slice(&MARKER);
+    frame[TEXT_LEN_SIZE] = SWITCH;
+    frame
+}
+
+/// Returns `true` if `frame` is a switch, see [`switch`].
+pub fn is_switch(frame: &[u8]) -> bool {
+    frame[..TEXT_LEN_SIZE] == MARKER && frame[TEXT_LEN_SIZE] == SWITCH
+}
+
+/// Returns the address advertised by `frame` if it is a hello, see [`hello`].
+pub fn read_hello(frame: &[u8]) -> Option<SocketAddr> {
+    read_address(frame).map(|(addr, _)| addr)
+}
+
+/// Returns the framing offered by `frame` if it is a hello, see [`hello`].
+pub fn read_framing(frame: &[u8]) -> Option<Framing> {
+    let (_, end) = read_address(frame)?;
+    match frame.get(end) {
+        Some(1) => Some(Framing::LengthPrefixed),
+        _ => Some(Framing::Fixed),
+    }
+}
+
+/// Returns the nonce of the node that sent `frame` if it is a hello, see
+/// [`hello`], or [`None`] if the node sent none.
+pub fn read_nonce(frame: &[u8]) -> Option<u64> {
+    let (_, end) = read_address(frame)?;
+    let nonce = frame.get(end + 1..end + 1 + NONCE_SIZE)?;
+    let nonce = u64::from_be_bytes(nonce.try_into().ok()?);
+    (nonce != 0).then_some(nonce)
+}
+
+/// Returns the address advertised by the hello in `frame`, along with
+/// where it ends.
+fn read_address(frame: &[u8]) -> Option<(SocketAddr, usize)> {
+    if frame[..TEXT_LEN_SIZE] != MARKER {
+        return None;
+    }
+
+    let ip_start = TEXT_LEN_SIZE + 1;
+    let (ip, ip_end) = match frame[TEXT_LEN_SIZE] {
+        FAMILY_V4 => {
+            let octets: [u8; 4] = frame[ip_start..ip_start + 4].try_into().ok()?;
+            (IpAddr::from(Ipv4Addr::from(octets)), ip_start + 4)
+        }
+        FAMILY_V6 => {
+            let octets: [u8; 16] = frame[ip_start..ip_start + 16].try_into().ok()?;
+            (IpAddr::from(Ipv6Addr::from(octets)), ip_start + 16)
+        }
+        _ => return None,
+    };
+    let port = frame.get(ip_end..ip_end + 2)?;
+    let port = u16::from_be_bytes([port[0], port[1]]);
+    Some((SocketAddr::new(ip, port), ip_end + 2))
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use crate::{
+        handshake::{
+            heartbeat, hello, is_heartbeat, is_switch, read_framing, read_hello, read_nonce, switch,
+        },
+        msg::{CAPACITY, Msg},
+        peer::Framing,
+    };
+
+    #[test]
+    fn hello_round_trips_and_is_not_a_message() {
+        for addr in ["10.0.0.1:4000", "[::1]:4000"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            let frame = hello(addr, 42, CAPACITY, Framing::Fixed);
+
+            assert_eq!(read_hello(&frame), Some(addr));
+            assert_eq!(read_framing(&frame), Some(Framing::Fixed));
+            assert_eq!(read_nonce(&frame), Some(42));
+            assert!(Msg::try_from(frame).is_err());
+        }
+        assert_eq!(read_hello(&Msg::new("hi").unwrap().into_bytes()), None);
+        assert_eq!(read_nonce(&Msg::new("hi").unwrap().into_bytes()), None);
+    }
+
+    #[test]
+    fn heartbeat_is_neither_hello_nor_message() {
+        let frame = heartbeat(CAPACITY);
+
+        assert!(is_heartbeat(&frame));
+        assert_eq!(read_hello(&frame), None);
+        assert!(Msg::try_from(frame).is_err());
+        assert!(!is_heartbeat(&hello(
+            "10.0.0.1:4000".parse().unwrap(),
+            1,
+            CAPACITY,
+            Framing::Fixed
+        )));
+    }
+
+    #[test]
+    fn hello_offers_framing_and_switch_is_no_message() {
+        let addr = "10.0.0.1:4000".parse().unwrap();
+        let frame = hello(addr, 1, CAPACITY, Framing::LengthPrefixed);
+        assert_eq!(read_hello(&frame), Some(addr));
+        assert_eq!(read_framing(&frame), Some(Framing::LengthPrefixed));
+
+        let frame = switch(CAPACITY);
+        assert!(is_switch(&frame));
+        assert!(!is_heartbeat(&frame));
+        assert_eq!(read_framing(&frame), None);
+        assert!(Msg::try_from(frame).is_err());
+    }
+}
@@ -0,0 +1,103 @@
+use std::fmt::Write;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum HexError {
+    #[error("odd number of hex digits")]
+    OddLength,
+    #[error("invalid hex digit `{0}`")]
+    InvalidDigit(char),
+}
+
+/// Encodes bytes as lowercase hex digits, two per byte.
+pub fn encode(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, b| {
+        let _ = write!(hex, "{b:02x}");
+        hex
+    })
+}
+
+/// Decodes a string of hex digits, ignoring surrounding whitespace.
+pub fn decode(hex: &str) -> Result<Vec<u8>, HexError> {
+    let digits = hex
+        .trim()
+        .chars()
+        .map(|c| {
+            c.to_digit(16)
+                .map(|d| d as u8)
+                .ok_or(HexError::InvalidDigit(c))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if digits.len() % 2 != 0 {
+        return Err(HexError::OddLength);
+    }
+
+    Ok(digits
+        .chunks(2)
+        .map(|pair| pair[0] << 4 | pair[1])
+        .collect())
+}
+
+/// Number of bytes shown on each line of [`dump`].
+const DUMP_WIDTH: usize = 16;
+
+/// Renders bytes as a hex and ASCII dump, [`DUMP_WIDTH`] bytes per line.
+///
+/// Each line starts with the offset of its first byte, followed by the
+/// bytes in hex and then as ASCII, with `.` for unprintable bytes.
+pub fn dump(bytes: &[u8]) -> String {
+    bytes
+        .chunks(DUMP_WIDTH)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let hex: Vec<_> = chunk.iter().map(|b| format!("{b:02x}")).collect();
+            let ascii: String = chunk
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            format!(
+                "{:08x}  {:<width$}  |{ascii}|",
+                i * DUMP_WIDTH,
+                hex.join(" "),
+                width = DUMP_WIDTH * 3 - 1
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::hex::{self, HexError};
+
+    #[test]
+    fn encode_decode_involution() {
+        let bytes = [0x00, 0x0f, 0xa5, 0xff];
+
+        assert_eq!(hex::encode(&bytes), "000fa5ff");
+        assert_eq!(hex::decode("000FA5ff").unwrap(), bytes);
+    }
+
+    #[test]
+    fn decode_rejects_bad_hex() {
+        assert_eq!(hex::decode("abc"), Err(HexError::OddLength));
+        assert_eq!(hex::decode("zz"), Err(HexError::InvalidDigit('z')));
+    }
+
+    #[test]
+    fn dump_shows_offsets_hex_and_ascii() {
+        let bytes = b"hello, dust!\0\x01\x02\x03\xffok";
+
+        assert_eq!(
+            hex::dump(bytes),
+            "00000000  68 65 6c 6c 6f 2c 20 64 75 73 74 21 00 01 02 03  |hello, dust!....|\n\
+             00000010  ff 6f 6b                                         |.ok|"
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    ffi::CStr,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ptr,
+};
+
+/// A network interface address as reported by the operating system.
+///
+/// Interfaces with several addresses are listed once per address, and
+/// interfaces without any IP address are listed with `addr` set to [`None`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interface {
+    pub name: String,
+    pub addr: Option<IpAddr>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum InterfaceError {
+    #[error("no interface named `{0}`")]
+    NotFound(String),
+    #[error("interface `{0}` has no IP address")]
+    NoAddress(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Looks up the address of the interface called `name` on this host.
+pub fn lookup(name: &str) -> Result<IpAddr, InterfaceError> {
+    resolve(name, &interfaces()?)
+}
+
+/// Resolves the interface called `name` among `interfaces` to an address
+/// to bind to, preferring IPv4 over IPv6.
+pub fn resolve(name: &str, interfaces: &[Interface]) -> Result<IpAddr, InterfaceError> {
+    let mut addrs = interfaces
+        .iter()
+        .filter(|interface| interface.name == name)
+        .peekable();
+
+    if addrs.peek().is_none() {
+        return Err(InterfaceError::NotFound(name.to_string()));
+    }
+
+    addrs
+        .filter_map(|interface| interface.addr)
+        .min_by_key(|addr| addr.is_ipv6())
+        .ok_or_else(|| InterfaceError::NoAddress(name.to_string()))
+}
+
+/// Lists the addresses of all network interfaces on this host.
+pub fn interfaces() -> io::Result<Vec<Interface>> {
+    let mut addrs: *mut libc::ifaddrs = ptr::null_mut();
+
+    // SAFETY: `addrs` is a valid out-pointer and is freed below.
+    if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut interfaces = Vec::new();
+    let mut cursor = addrs;
+    while !cursor.is_null() {
+        // SAFETY: `cursor` is a non-null node of the list returned by
+        // `getifaddrs`, which stays alive until `freeifaddrs`.
+        let ifa = unsafe { &*cursor };
+        // SAFETY: `ifa_name` is a nul-terminated string owned by the list.
+        let name = unsafe { CStr::from_ptr(ifa.ifa_name) };
+        interfaces.push(Interface {
+            name: name.to_string_lossy().into_owned(),
+            // SAFETY: `ifa_addr` is either null or points to a sockaddr
+            // whose concrete type is given by its family.
+            addr: unsafe { ip_of(ifa.ifa_addr) },
+        });
+        cursor = ifa.ifa_next;
+    }
+
+    // SAFETY: `addrs` came from `getifaddrs` and is not used afterwards.
+    unsafe { libc::freeifaddrs(addrs) };
+
+    Ok(interfaces)
+}
+
+/// # Safety
+///
+/// `addr` must be null or point to a valid socket address matching its family.
+unsafe fn ip_of(addr: *const libc::sockaddr) -> Option<IpAddr> {
+    if addr.is_null() {
+        return None;
+    }
+
+    // SAFETY: guaranteed by the caller.
+    unsafe {
+        match i32::from((*addr).sa_family) {
+            libc::AF_INET => {
+                let addr = &*(addr as *const libc::sockaddr_in);
+                Some(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)).into())
+            }
+            libc::AF_INET6 => {
+                let addr = &*(addr as *const libc::sockaddr_in6);
+                Some(Ipv6Addr::from(addr.sin6_addr.s6_addr).into())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use crate::iface::{Interface, InterfaceError, resolve};
+
+    fn interface(name: &str, addr: Option<&str>) -> Interface {
+        Interface {
+            name: name.to_string(),
+            addr: addr.map(|addr| addr.parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn resolve_prefers_ipv4() {
+        let interfaces = [
+            interface("lo", Some("127.0.0.1")),
+            interface("eth0", None),
+            interface("eth0", Some("fe80::1")),
+            interface("eth0", Some("192.168.1.2")),
+        ];
+
+        let addr: IpAddr = "192.168.1.2".parse().unwrap();
+        assert_eq!(resolve("eth0", &interfaces).unwrap(), addr);
+    }
+
+    #[test]
+    fn resolve_reports_missing_interface_or_address() {
+        let interfaces = [interface("lo", Some("127.0.0.1")), interface("tun0", None)];
+
+        assert!(matches!(
+            resolve("eth0", &interfaces),
+            Err(InterfaceError::NotFound(_))
+        ));
+        assert!(matches!(
+            resolve("tun0", &interfaces),
+            Err(InterfaceError::NoAddress(_))
+        ));
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    os::fd::{AsFd, AsRawFd, BorrowedFd},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread::{JoinHandle, spawn},
+    time::Duration,
+};
+
+use crate::{Command, Input, InputError, Origin};
+
+/// How long the input thread waits for input before checking for shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Reads commands from `input`, one per line, sending them over `tx`, with
+/// messages held to frames of `capacity` bytes.
+///
+/// Rather than blocking in a read, the thread waits for input in short
+/// polls, so it ends promptly once `stop` is set, without waiting for
+/// another line. It also ends when `input` is closed.
+pub fn read(
+    mut input: File,
+    tx: mpsc::Sender<Input>,
+    stop: Arc<AtomicBool>,
+    capacity: usize,
+) -> JoinHandle<()> {
+    spawn(move || {
+        let mut pending = Vec::new();
+        let mut buf = [0; 1024];
+
+        while !stop.load(Ordering::Relaxed) {
+            match wait_readable(input.as_fd(), POLL_INTERVAL) {
+                Ok(true) => (),
+                Ok(false) => continue,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    let _ = tx.send((Err(InputError::from(err)), Origin::Stdin));
+                    return;
+                }
+            }
+            let n = match input.read(&mut buf) {
+                Ok(0) => return,
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    let _ = tx.send((Err(InputError::from(err)), Origin::Stdin));
+                    return;
+                }
+            };
+
+            pending.extend_from_slice(&buf[..n]);
+            while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+                let line: Vec<_> = pending.drain(..=end).collect();
+                let command = Command::parse(&String::from_utf8_lossy(&line), capacity)
+                    .map_err(InputError::from);
+
+                if tx.send((command, Origin::Stdin)).is_err() {
+                    return;
+                }
+            }
+        }
+    })
+}
+
+/// Waits up to `timeout` for `fd` to become readable, returning whether it did.
+///
+/// A closed `fd` counts as readable, so the following read sees the end.
+fn wait_readable(fd: BorrowedFd, timeout: Duration) -> io::Result<bool> {
+    let mut pollfd = libc::pollfd {
+        fd: fd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout = libc::c_int::try_from(timeout.as_millis()).unwrap_or(libc::c_int::MAX);
+
+    // SAFETY: `pollfd` is a single valid entry, matching the count of 1.
+    match unsafe { libc::poll(&mut pollfd, 1, timeout) } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::File,
+        io::Write,
+        os::{fd::OwnedFd, unix::net::UnixStream},
+        sync::{
+            Arc,
+            atomic::{AtomicBool, Ordering},
+            mpsc,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use crate::{Command, InputError, input, msg::CAPACITY};
+
+    #[test]
+    fn stops_without_a_final_line() {
+        let (mut writer, reader) = UnixStream::pair().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = input::read(
+            File::from(OwnedFd::from(reader)),
+            tx,
+            stop.clone(),
+            CAPACITY,
+        );
+
+        writer.write_all(b"flush-all\n").unwrap();
+        let (cmd, _) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(cmd, Ok(Command::FlushAll)));
+
+        stop.store(true, Ordering::Relaxed);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !handle.is_finished() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(handle.is_finished());
+    }
+
+    #[test]
+    fn survives_invalid_command() {
+        let (mut writer, reader) = UnixStream::pair().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        input::read(
+            File::from(OwnedFd::from(reader)),
+            tx,
+            stop.clone(),
+            CAPACITY,
+        );
+
+        writer.write_all(b"brodcast typo\nflush-all\n").unwrap();
+        let (bad, _) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        let (good, _) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        stop.store(true, Ordering::Relaxed);
+
+        assert!(matches!(bad, Err(InputError::Parse(_))));
+        assert!(matches!(good, Ok(Command::FlushAll)));
+    }
+}
@@ -0,0 +1,47 @@
+use std::{
+    io,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_signal: libc::c_int) {
+    // Only an atomic store, which is safe to do in a signal handler.
+    REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Makes `SIGINT` and `SIGTERM` request a shutdown, see [`requested`],
+/// rather than ending the process straight away.
+///
+/// That way, a node stopped by Ctrl-C still closes the connection to every
+/// peer, instead of leaving them half-open.
+pub fn install() -> io::Result<()> {
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        // SAFETY: the handler only stores to an atomic.
+        if unsafe { libc::signal(signal, handler) } == libc::SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Returns `true` once a shutdown was requested by a signal, see [`install`].
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interrupt::{install, requested};
+
+    #[test]
+    fn interrupt_requests_shutdown() {
+        install().unwrap();
+        assert!(!requested());
+
+        // SAFETY: the handler for `SIGINT` was just installed.
+        assert_eq!(unsafe { libc::raise(libc::SIGINT) }, 0);
+        assert!(requested());
+    }
+}
@@ -0,0 +1,2390 @@
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    env, fmt,
+    fs::File,
+    io::{self, Write},
+    mem,
+    net::{self, IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    os::{
+        fd::{AsFd, FromRawFd, OwnedFd},
+        unix::net::UnixStream,
+    },
+    path::PathBuf,
+    process,
+    rc::Rc,
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread::spawn,
+    time::{Duration, Instant},
+};
+
+use audit::{AuditLog, Direction, Outcome};
+use blocklist::Blocklist;
+use cli::Mode;
+use clock::Clock;
+use display::Printer;
+use fanout::Fanout;
+use msg::Msg;
+use node::{Config, Node};
+use peer::{Framing, Peer, Recovery};
+use queue::DedupQueue;
+use resolve::Resolve;
+use seenlog::SeenLog;
+use sink::{Counted, Discard, DisconnectReason, Event, MessageSink, PeerEvent};
+use stats::{Delivery, DropReason, Drops};
+use transport::Transport;
+use uuid::Uuid;
+
+mod activation;
+pub mod audit;
+mod blocklist;
+mod cli;
+pub mod clock;
+mod coalesce;
+mod confirm;
+mod control;
+mod daemon;
+mod display;
+mod export;
+pub mod fanout;
+mod faults;
+mod fragment;
+mod handshake;
+mod hex;
+mod iface;
+mod input;
+mod interrupt;
+pub mod msg;
+pub mod node;
+pub mod peer;
+pub mod queue;
+mod ratelimit;
+mod redial;
+mod resolve;
+mod rng;
+pub mod seenlog;
+pub mod sink;
+mod snapshot;
+mod socks;
+mod state;
+pub mod stats;
+mod throughput;
+pub mod transport;
+
+/// Accepts incoming connections on `listener` and returns its address along
+/// with a channel over which these are sent, once greeted with our hello
+/// carrying `nonce`. Frames on these connections are `capacity` bytes, and
+/// `framing` is offered on them, see [`Peer::with_framing`].
+///
+/// If accepting fails, the error is sent last and the listener is closed.
+pub fn listen(
+    listener: TcpListener,
+    nonce: u64,
+    capacity: usize,
+    framing: Framing,
+) -> io::Result<(SocketAddr, mpsc::Receiver<io::Result<Peer>>)> {
+    let local_addr = listener.local_addr()?;
+    let (tx, rx) = mpsc::channel();
+
+    spawn(move || {
+        loop {
+            let (socket, addr) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    let _ = tx.send(Err(err));
+                    return;
+                }
+            };
+            socket
+                .set_nonblocking(true)
+                .expect("setting nonblocking failed");
+            let mut peer = Peer::new(socket, addr)
+                .with_frame_size(capacity)
+                .with_framing(framing);
+            if peer.greet(local_addr, nonce).is_ok() && tx.send(Ok(peer)).is_err() {
+                return;
+            }
+        }
+    });
+
+    Ok((local_addr, rx))
+}
+
+#[derive(Debug, Clone)]
+enum Command {
+    /// Connects to the first of the addresses that accepts, remembering the
+    /// host name they were resolved from, if any.
+    Connect(Vec<SocketAddr>, Option<String>),
+    /// Closes the link to the peer and dials it again, resolving its host
+    /// name afresh if it was dialed by name.
+    Reconnect(SocketAddr),
+    /// Shows the addresses a host name resolves to.
+    Resolve(String),
+    /// Sets the nick sent along with our messages, or clears it if empty.
+    Nick(String),
+    /// Shows only the received messages containing the text, or all of
+    /// them if unset. Every message is still propagated.
+    Filter(Option<String>),
+    Broadcast(String, Option<Duration>),
+    /// Sends the message to the peer known by the address only.
+    Whisper(SocketAddr, Box<Msg>),
+    Disconnect,
+    Block(IpAddr),
+    Unblock(IpAddr),
+    ExportSeen(PathBuf),
+    /// Broadcasts the messages exported to the file again, under their
+    /// original UUIDs if the first flag is set, and back to back rather
+    /// than at their original relative timing if the second is.
+    Replay(PathBuf, bool, bool),
+    /// Forgets the seen message with the id, given as lowercase hex digits
+    /// of its UUID, all of them or only the first.
+    Forget(String),
+    /// Writes the state of the node, without its connections, to the file.
+    Snapshot(PathBuf),
+    /// Replaces the seen messages and counters with those snapshotted to
+    /// the file.
+    Restore(PathBuf),
+    /// Shows the most recently seen message.
+    Last,
+    /// Shows every seen message kept in memory, oldest first.
+    History,
+    /// Forgets every seen message kept in memory.
+    ClearSeen,
+    /// Changes how many seen messages are kept in memory.
+    ResizeSeen(usize),
+    FlushAll,
+    Encode(Msg),
+    Decode(Vec<u8>),
+    Explain(Msg),
+    Faults(f64, Duration),
+    Pause,
+    Resume,
+    Drain,
+    Stats,
+    Throughput,
+    Confirm,
+    Cancel,
+    List,
+    /// Shows only the events of at least the level from now on.
+    Log(sink::Level),
+}
+
+#[derive(Debug, thiserror::Error)]
+enum ParseCommandError {
+    #[error("invalid command `{0}`")]
+    InvalidCommand(String),
+    #[error("missing argument to `{0}`")]
+    MissingArgument(String),
+    #[error("invalid ttl `{0}`, expected e.g. `30s`, `5m` or `1h`")]
+    InvalidTtl(String),
+    #[error("invalid capacity `{0}`")]
+    InvalidCapacity(String),
+    #[error("address too long, {0} bytes where at most {MAX_ADDRESS} fit")]
+    AddressTooLong(usize),
+    #[error(
+        "message exceeds max, {bytes} bytes ({chars} characters) where at most {max} bytes fit"
+    )]
+    MessageTooLong {
+        bytes: usize,
+        chars: usize,
+        max: usize,
+    },
+    #[error(transparent)]
+    TryFromStringToMsgError(#[from] msg::TryFromStringToMsgError),
+    #[error(transparent)]
+    AddrParseError(#[from] net::AddrParseError),
+    #[error("failed to resolve `{0}`: {1}")]
+    ResolveError(String, #[source] io::Error),
+    #[error("invalid message id `{0}`, expected a UUID or its first hex digits")]
+    InvalidId(String),
+    #[error(transparent)]
+    HexError(#[from] hex::HexError),
+    #[error("frame longer than {0} bytes")]
+    FrameTooLong(usize),
+    #[error(transparent)]
+    FaultsError(#[from] faults::FaultsError),
+    #[error(transparent)]
+    ParseLevelError(#[from] sink::ParseLevelError),
+}
+
+impl FromStr for Command {
+    type Err = ParseCommandError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Command::parse(s, msg::CAPACITY)
+    }
+}
+
+impl Command {
+    /// Parses a command, holding messages to frames of `capacity` bytes.
+    fn parse(s: &str, capacity: usize) -> Result<Self, ParseCommandError> {
+        let s = s.trim();
+        // Everything after the command word is its arguments, which many
+        // commands don't take at all.
+        let (cmd, args) = match s.split_once(char::is_whitespace) {
+            Some((cmd, args)) => (cmd, args.trim_start()),
+            None => (s, ""),
+        };
+
+        match cmd {
+            "broadcast" => {
+                let (ttl, text) = match args.strip_prefix("--ttl ") {
+                    Some(rest) => {
+                        let (ttl, text) = rest.split_once(" ").unwrap_or((rest, ""));
+                        (Some(parse_ttl(ttl)?), text)
+                    }
+                    None => (None, args),
+                };
+                Ok(Command::Broadcast(
+                    text_arg(unescape(required(cmd, text)?), msg::MAX_CAPACITY)?,
+                    ttl,
+                ))
+            }
+            "whisper" => {
+                let args = required(cmd, args)?;
+                let (to, text) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+                let text = text_arg(
+                    unescape(required(cmd, text.trim_start())?),
+                    msg::MAX_CAPACITY,
+                )?;
+                Ok(Command::Whisper(
+                    address_arg(to)?.parse()?,
+                    Box::new(Msg::new_in(text, msg::MAX_CAPACITY)?),
+                ))
+            }
+            "connect" => {
+                let args = address_arg(required(cmd, args)?)?;
+                let addrs = resolve::System
+                    .resolve(args)
+                    .map_err(|err| ParseCommandError::ResolveError(args.to_string(), err))?;
+                let host = args
+                    .parse::<SocketAddr>()
+                    .is_err()
+                    .then(|| args.to_string());
+                Ok(Command::Connect(addrs, host))
+            }
+            "reconnect" => Ok(Command::Reconnect(address_arg(args)?.parse()?)),
+            "nick" => Ok(Command::Nick(args.to_string())),
+            "filter" => match required(cmd, args)? {
+                "off" => Ok(Command::Filter(None)),
+                pattern => Ok(Command::Filter(Some(pattern.to_string()))),
+            },
+            "resolve" => Ok(Command::Resolve(
+                address_arg(required(cmd, args)?)?.to_string(),
+            )),
+            "disconnect" => Ok(Command::Disconnect),
+            "block" => Ok(Command::Block(address_arg(args)?.parse()?)),
+            "unblock" => Ok(Command::Unblock(address_arg(args)?.parse()?)),
+            "export-seen" => Ok(Command::ExportSeen(PathBuf::from(required(cmd, args)?))),
+            "snapshot" => Ok(Command::Snapshot(PathBuf::from(required(cmd, args)?))),
+            "restore" => Ok(Command::Restore(PathBuf::from(required(cmd, args)?))),
+            "replay" => {
+                let (mut path, mut keep_uuids, mut fast) = (args, false, false);
+                loop {
+                    if let Some(rest) = path.strip_prefix("--keep-uuids") {
+                        keep_uuids = true;
+                        path = rest.trim_start();
+                    } else if let Some(rest) = path.strip_prefix("--as-fast-as-possible") {
+                        fast = true;
+                        path = rest.trim_start();
+                    } else {
+                        break;
+                    }
+                }
+                Ok(Command::Replay(
+                    PathBuf::from(required(cmd, path)?),
+                    keep_uuids,
+                    fast,
+                ))
+            }
+            "forget" => Ok(Command::Forget(id_arg(required(cmd, args)?)?)),
+            "last" => Ok(Command::Last),
+            "history" => Ok(Command::History),
+            "clear-seen" => Ok(Command::ClearSeen),
+            "resize-seen" => {
+                let capacity = required(cmd, args)?;
+                match capacity.parse() {
+                    Ok(capacity) => Ok(Command::ResizeSeen(capacity)),
+                    Err(_) => Err(ParseCommandError::InvalidCapacity(capacity.to_string())),
+                }
+            }
+            "flush-all" => Ok(Command::FlushAll),
+            "pause" => Ok(Command::Pause),
+            "resume" => Ok(Command::Resume),
+            "drain" => Ok(Command::Drain),
+            "stats" => Ok(Command::Stats),
+            "throughput" => Ok(Command::Throughput),
+            "confirm" => Ok(Command::Confirm),
+            "cancel" => Ok(Command::Cancel),
+            "list" => Ok(Command::List),
+            "log" => Ok(Command::Log(required(cmd, args)?.parse()?)),
+            "encode" => Ok(Command::Encode(Msg::new_in(
+                text_arg(args.to_string(), capacity)?,
+                capacity,
+            )?)),
+            "faults" => {
+                let (loss, delay) = faults::parse(args)?;
+                Ok(Command::Faults(loss, delay))
+            }
+            "explain" => Ok(Command::Explain(Msg::new_in(
+                text_arg(unescape(args), capacity)?,
+                capacity,
+            )?)),
+            "decode" => {
+                let bytes = hex::decode(args)?;
+                if bytes.len() > capacity {
+                    return Err(ParseCommandError::FrameTooLong(capacity));
+                }
+                Ok(Command::Decode(bytes))
+            }
+            c => Err(ParseCommandError::InvalidCommand(c.to_string())),
+        }
+    }
+}
+
+/// Renders the command on a single line as it would be typed, so that
+/// parsing it gives the same command back.
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Connect(addrs, host) => match (host, addrs.first()) {
+                (Some(host), _) => write!(f, "connect {host}"),
+                (None, Some(addr)) => write!(f, "connect {addr}"),
+                (None, None) => write!(f, "connect"),
+            },
+            Command::Reconnect(addr) => write!(f, "reconnect {addr}"),
+            Command::Resolve(host) => write!(f, "resolve {host}"),
+            Command::Nick(nick) if nick.is_empty() => write!(f, "nick"),
+            Command::Nick(nick) => write!(f, "nick {nick}"),
+            Command::Filter(None) => write!(f, "filter off"),
+            Command::Filter(Some(pattern)) => write!(f, "filter {pattern}"),
+            Command::Broadcast(text, None) => write!(f, "broadcast {}", msg::escape(text)),
+            Command::Broadcast(text, Some(ttl)) => {
+                write!(
+                    f,
+                    "broadcast --ttl {} {}",
+                    format_ttl(*ttl),
+                    msg::escape(text)
+                )
+            }
+            Command::Whisper(to, msg) => write!(f, "whisper {to} {}", msg::escape(msg.text())),
+            Command::Disconnect => write!(f, "disconnect"),
+            Command::Block(ip) => write!(f, "block {ip}"),
+            Command::Unblock(ip) => write!(f, "unblock {ip}"),
+            Command::ExportSeen(path) => write!(f, "export-seen {}", path.display()),
+            Command::Replay(path, keep_uuids, fast) => {
+                write!(f, "replay ")?;
+                if *keep_uuids {
+                    write!(f, "--keep-uuids ")?;
+                }
+                if *fast {
+                    write!(f, "--as-fast-as-possible ")?;
+                }
+                write!(f, "{}", path.display())
+            }
+            Command::Forget(id) => write!(f, "forget {id}"),
+            Command::Snapshot(path) => write!(f, "snapshot {}", path.display()),
+            Command::Restore(path) => write!(f, "restore {}", path.display()),
+            Command::Last => write!(f, "last"),
+            Command::History => write!(f, "history"),
+            Command::ClearSeen => write!(f, "clear-seen"),
+            Command::ResizeSeen(capacity) => write!(f, "resize-seen {capacity}"),
+            Command::FlushAll => write!(f, "flush-all"),
+            // Unlike the others, the text to encode is taken as typed.
+            Command::Encode(msg) => write!(f, "encode {}", msg.text()),
+            Command::Decode(bytes) => write!(f, "decode {}", hex::encode(bytes)),
+            Command::Explain(msg) => write!(f, "explain {}", msg::escape(msg.text())),
+            Command::Faults(loss, delay) => {
+                write!(f, "faults loss={loss} delay={}ms", delay.as_millis())
+            }
+            Command::Pause => write!(f, "pause"),
+            Command::Resume => write!(f, "resume"),
+            Command::Drain => write!(f, "drain"),
+            Command::Stats => write!(f, "stats"),
+            Command::Throughput => write!(f, "throughput"),
+            Command::Confirm => write!(f, "confirm"),
+            Command::Cancel => write!(f, "cancel"),
+            Command::List => write!(f, "list"),
+            Command::Log(level) => write!(f, "log {level}"),
+        }
+    }
+}
+
+/// The longest address argument: a host name of at most 253 bytes and a port.
+const MAX_ADDRESS: usize = 253 + ":65535".len();
+
+/// Returns `args` as an address argument, failing if no address is that long.
+fn address_arg(args: &str) -> Result<&str, ParseCommandError> {
+    if args.len() > MAX_ADDRESS {
+        Err(ParseCommandError::AddressTooLong(args.len()))
+    } else {
+        Ok(args)
+    }
+}
+
+/// Returns `args` as a message id, a full UUID or the first hex digits of
+/// one, normalized to the lowercase hex digits given.
+fn id_arg(args: &str) -> Result<String, ParseCommandError> {
+    if let Ok(uuid) = Uuid::parse_str(args) {
+        return Ok(uuid.simple().to_string());
+    }
+    let is_prefix = args.len() <= 32 && args.chars().all(|c| c.is_ascii_hexdigit());
+    if is_prefix {
+        Ok(args.to_ascii_lowercase())
+    } else {
+        Err(ParseCommandError::InvalidId(args.to_string()))
+    }
+}
+
+/// Returns `text` as the text of a message, failing if it can't fit in one
+/// of `capacity` bytes.
+fn text_arg(text: String, capacity: usize) -> Result<String, ParseCommandError> {
+    let max = msg::max_text(capacity);
+    if text.len() > max {
+        Err(ParseCommandError::MessageTooLong {
+            bytes: text.len(),
+            chars: text.chars().count(),
+            max,
+        })
+    } else {
+        Ok(text)
+    }
+}
+
+/// Returns the arguments `args` given to `cmd`, failing if there are none.
+fn required<'a>(cmd: &str, args: &'a str) -> Result<&'a str, ParseCommandError> {
+    if args.is_empty() {
+        Err(ParseCommandError::MissingArgument(cmd.to_string()))
+    } else {
+        Ok(args)
+    }
+}
+
+/// Parses a time-to-live of whole seconds, minutes or hours, like `30s`.
+fn parse_ttl(ttl: &str) -> Result<Duration, ParseCommandError> {
+    let invalid = || ParseCommandError::InvalidTtl(ttl.to_string());
+    let (count, unit) = [("s", 1), ("m", 60), ("h", 60 * 60)]
+        .into_iter()
+        .find_map(|(suffix, unit)| ttl.strip_suffix(suffix).map(|count| (count, unit)))
+        .ok_or_else(invalid)?;
+    let count: u64 = count.parse().map_err(|_| invalid())?;
+    let secs = count.checked_mul(unit).ok_or_else(invalid)?;
+
+    Ok(Duration::from_secs(secs))
+}
+
+/// Renders `ttl` in the largest unit [`parse_ttl`] takes that fits it whole.
+fn format_ttl(ttl: Duration) -> String {
+    let secs = ttl.as_secs();
+    let (suffix, unit) = [("h", 60 * 60), ("m", 60)]
+        .into_iter()
+        .find(|&(_, unit)| secs > 0 && secs.is_multiple_of(unit))
+        .unwrap_or(("s", 1));
+    format!("{}{suffix}", secs / unit)
+}
+
+/// Replaces the escapes `\n` and `\\` typed on a single input line
+/// with a newline and a backslash respectively.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                unescaped.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                unescaped.push('\\');
+                chars.next();
+            }
+            (c, _) => unescaped.push(c),
+        }
+    }
+
+    unescaped
+}
+
+#[derive(Debug, thiserror::Error)]
+enum InputError<T> {
+    #[error(transparent)]
+    Parse(#[from] ParseCommandError),
+    #[error(transparent)]
+    Send(#[from] mpsc::SendError<T>),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Where a command was read from.
+#[derive(Debug)]
+enum Origin {
+    /// Stdin, whose feedback goes to the sink like any other.
+    Stdin,
+    /// A connection to the control socket, over which the notices the
+    /// command leads to are replied as well.
+    Control(UnixStream),
+}
+
+/// A command read from one of the node's inputs, along with where from.
+type Input = (Result<Command, InputError<Command>>, Origin);
+
+/// Seconds a message is still accepted past its expiry, to tolerate
+/// the clocks of nodes running slightly apart.
+const CLOCK_SKEW: u64 = 5;
+
+/// Number of frames taken from one peer per pass, so a busy peer can't
+/// starve the others.
+const FRAMES_PER_PASS: usize = 64;
+
+/// Runs the p2p peer on `listener`, reporting to `sink`, until it drains or
+/// is interrupted.
+fn run(listener: TcpListener, config: Config, sink: impl MessageSink) -> io::Result<()> {
+    interrupt::install()?;
+    let (detach, control, capacity) = (config.detach, config.control.clone(), config.capacity());
+    let mut node = Node::with_listener(listener, config, sink)?;
+    node.announce();
+    node.bootstrap();
+
+    let (tx, cmds) = mpsc::channel();
+    let stop = Arc::new(AtomicBool::new(false));
+    let stdin = if detach {
+        None
+    } else {
+        let stdin = File::from(io::stdin().as_fd().try_clone_to_owned()?);
+        Some(input::read(stdin, tx.clone(), stop.clone(), capacity))
+    };
+    // Without a control socket, only the input thread holds on to `tx`, so
+    // the channel closes when it ends.
+    match &control {
+        Some(path) => control::listen(path, tx, capacity)?,
+        None => drop(tx),
+    }
+
+    let mut cmds = Some(cmds);
+    while !node.drained() && !interrupt::requested() {
+        node.handle_next(&mut cmds)?;
+        node.poll();
+    }
+
+    node.leave();
+    stop.store(true, Ordering::Relaxed);
+    if let Some(stdin) = stdin {
+        let _ = stdin.join();
+    }
+    Ok(())
+}
+
+/// Sends a single message with `text` to the peer at `to`, then leaves.
+fn send_once(
+    to: SocketAddr,
+    text: String,
+    config: Config,
+    sink: impl MessageSink,
+) -> io::Result<()> {
+    let mut node = Node::bind((Ipv4Addr::LOCALHOST, 0).into(), config, sink)?;
+    node.connect(to)?;
+    node.send_broadcast(text, None);
+    node.leave();
+    Ok(())
+}
+
+/// Runs a node on `listener` until it has received `count` messages, then
+/// leaves.
+fn recv_count(
+    listener: TcpListener,
+    count: usize,
+    config: Config,
+    sink: impl MessageSink,
+) -> io::Result<()> {
+    let mut node = Node::with_listener(listener, config, Counted::new(sink))?;
+    node.announce();
+
+    while node.sink().messages() < count {
+        node.accept_incoming();
+        node.receive();
+    }
+
+    node.leave();
+    Ok(())
+}
+
+/// Sends `count` messages from one local node to another over loopback and
+/// reports how fast they were received.
+fn bench(count: usize, config: Config, mut sink: impl MessageSink) -> io::Result<()> {
+    let loopback = (Ipv4Addr::LOCALHOST, 0).into();
+    let mut receiver = Node::bind(loopback, config.clone(), Counted::new(Discard))?;
+    let mut sender = Node::bind(loopback, config, Discard)?;
+
+    let connected = Rc::new(Cell::new(false));
+    let on_connect = connected.clone();
+    receiver.on_peer_event(move |ev| {
+        if let PeerEvent::Connected(_) = ev {
+            on_connect.set(true);
+        }
+    });
+
+    sender.handle(Command::Connect(vec![receiver.local_addr()], None))?;
+    while !connected.get() {
+        receiver.accept_incoming();
+    }
+
+    let start = clock::System.now_instant();
+    for i in 0..count {
+        sender.send_broadcast(format!("bench {i}"), None);
+        sender.write_coalesced(false);
+        receiver.receive();
+    }
+    while receiver.sink().messages() < count {
+        sender.write_coalesced(false);
+        sender.write_unsent();
+        receiver.receive();
+    }
+    let elapsed = clock::System.now_instant() - start;
+
+    sender.leave();
+    receiver.leave();
+    sink.on_event(&Event::Notice(format!(
+        "received {count} messages in {}ms ({:.0} messages/s)",
+        elapsed.as_millis(),
+        count as f64 / elapsed.as_secs_f64()
+    )));
+    Ok(())
+}
+
+/// Adds an incoming connection to the peers unless its IP is blocked
+/// or already has the maximum number of inbound connections.
+///
+/// Connections per IP are counted over the current peers that dialed us,
+/// so the count drops as soon as a disconnected peer is removed. An accepted peer joins
+/// as heard from `now`.
+fn accept(
+    peers: &mut Vec<Peer>,
+    comm: Peer,
+    blocklist: &Blocklist,
+    config: &Config,
+    now: Instant,
+    audit: &mut Option<AuditLog>,
+    sink: &mut impl MessageSink,
+) {
+    let addr = comm.addr();
+    let from_ip = || {
+        peers
+            .iter()
+            .filter(|peer| !peer.is_dialed() && peer.addr().ip() == addr.ip())
+            .count()
+    };
+
+    let rejection = if blocklist.contains(addr.ip()) {
+        Some(String::from("blocked"))
+    } else if config.max_per_ip.is_some_and(|max| from_ip() >= max) {
+        Some(format!("too many connections from {}", addr.ip()))
+    } else {
+        config
+            .max_peers
+            .filter(|&max| peers.len() >= max)
+            .map(|max| format!("at the limit of {max} peers"))
+    };
+
+    if let Some(reason) = rejection {
+        record_audit(
+            audit,
+            Direction::Inbound,
+            addr,
+            Outcome::Rejected,
+            Some(&reason),
+            sink,
+        );
+        sink.on_event(&Event::Rejected { addr, reason });
+        let _ = comm.shutdown();
+        return;
+    }
+
+    record_audit(
+        audit,
+        Direction::Inbound,
+        addr,
+        Outcome::Accepted,
+        None,
+        sink,
+    );
+    sink.on_event(&Event::Peer(PeerEvent::Connected(addr)));
+    peers.push(comm.with_last_seen(now));
+}
+
+/// Closes all but one link to every peer, by the address it advertised.
+///
+/// Two nodes dialing each other at the same time end up with two links,
+/// of which both keep the one dialed from the lower port, so they don't
+/// each close a different one. Otherwise the older link is kept.
+fn drop_duplicates(peers: Vec<Peer>, sink: &mut impl MessageSink) -> Vec<Peer> {
+    let mut kept: Vec<Peer> = Vec::with_capacity(peers.len());
+    for peer in peers {
+        let Some(i) = kept.iter().position(|k| k.identity() == peer.identity()) else {
+            kept.push(peer);
+            continue;
+        };
+
+        let dropped = match (peer.dialer_port(), kept[i].dialer_port()) {
+            (Some(new), Some(old)) if new < old => mem::replace(&mut kept[i], peer),
+            _ => peer,
+        };
+        sink.on_event(&Event::Notice(format!(
+            "dropping duplicate link to {}",
+            dropped.identity()
+        )));
+        let _ = dropped.shutdown();
+    }
+    kept
+}
+
+/// Records a connection attempt in the audit log, if there is one.
+fn record_audit(
+    audit: &mut Option<AuditLog>,
+    direction: Direction,
+    addr: SocketAddr,
+    outcome: Outcome,
+    reason: Option<&str>,
+    sink: &mut impl MessageSink,
+) {
+    let recorded = audit
+        .as_mut()
+        .map(|log| log.record(direction, addr, outcome, reason));
+    if let Some(Err(err)) = recorded {
+        sink.on_event(&Event::Notice(format!("failed to write audit log: {err}")));
+    }
+}
+
+/// Returns the host name a peer is dialed by, or else the first of the
+/// addresses it is dialed at, to tell the user about.
+fn dial_target(addrs: &[SocketAddr], host: Option<&str>) -> String {
+    match (host, addrs.first()) {
+        (Some(host), _) => host.to_string(),
+        (None, Some(addr)) => addr.to_string(),
+        (None, None) => String::from("no address"),
+    }
+}
+
+/// Closes the links to the node sending `nonce` in its hellos, that is,
+/// the node itself, as told by the hellos that arrived on them.
+fn drop_self(peers: Vec<Peer>, nonce: u64, sink: &mut impl MessageSink) -> Vec<Peer> {
+    peers
+        .into_iter()
+        .filter(|peer| {
+            let looped = peer.nonce() == Some(nonce);
+            if looped {
+                let _ = peer.shutdown();
+                sink.on_event(&Event::Rejected {
+                    addr: peer.addr(),
+                    reason: String::from("connected to ourselves"),
+                });
+            }
+            !looped
+        })
+        .collect()
+}
+
+/// Disconnects any peers whose IP has been blocked.
+fn drop_blocked(peers: Vec<Peer>, blocklist: &Blocklist, sink: &mut impl MessageSink) -> Vec<Peer> {
+    peers
+        .into_iter()
+        .filter(|peer| {
+            let addr = peer.addr();
+            let blocked = blocklist.contains(addr.ip());
+            if blocked {
+                let _ = peer.shutdown();
+                sink.on_event(&Event::Peer(PeerEvent::Disconnected {
+                    addr,
+                    reason: DisconnectReason::Blocked,
+                }));
+            }
+            !blocked
+        })
+        .collect()
+}
+
+/// Reads messages from all peers and propagates the new ones.
+///
+/// A new message is never sent back to the peer it came from. With
+/// [`Fanout::split_horizon`], it is also held back from every other peer
+/// that delivered the same message during this pass.
+pub fn receive_msgs<T: Transport>(
+    peers: Vec<Peer<T>>,
+    seen: &mut DedupQueue<Msg>,
+    seen_log: &mut Option<SeenLog>,
+    drops: &mut Drops,
+    fanout: &mut Fanout,
+    clock: &dyn Clock,
+    sink: &mut impl MessageSink,
+) -> Vec<Peer<T>> {
+    let (retained, frames): (Vec<_>, Vec<_>) = peers
+        .into_iter()
+        .map(|peer| process_msg(peer, seen, seen_log, drops, fanout.hexdump, clock, sink))
+        .unzip();
+
+    relay(
+        retained.into_iter().flatten().collect(),
+        frames.into_iter().flatten().collect(),
+        fanout,
+        clock.now_instant(),
+        sink,
+    )
+}
+
+/// Propagates the new messages among `frames` to `peers`.
+///
+/// A message is never sent back to the peer it was read from, nor to any
+/// other peer that delivered it with [`Fanout::split_horizon`].
+fn relay<T: Transport>(
+    peers: Vec<Peer<T>>,
+    frames: Vec<(Frame, SocketAddr)>,
+    fanout: &mut Fanout,
+    now: Instant,
+    sink: &mut impl MessageSink,
+) -> Vec<Peer<T>> {
+    let mut deliverers: HashMap<Uuid, Vec<SocketAddr>> = HashMap::new();
+    frames.iter().for_each(|(frame, addr)| {
+        deliverers.entry(frame.uuid()).or_default().push(*addr);
+    });
+
+    frames
+        .into_iter()
+        .filter_map(|(frame, origin)| match frame {
+            Frame::New(msg) => Some((msg, origin)),
+            Frame::Seen(_) => None,
+        })
+        .fold(peers, |acc, (msg, origin)| {
+            let excluded = match deliverers.get(&msg.uuid()) {
+                Some(deliverers) if fanout.split_horizon => deliverers.clone(),
+                _ => vec![origin],
+            };
+            propagate(acc, msg, &excluded, fanout, now, sink).0
+        })
+}
+
+/// A valid frame read from a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Frame {
+    /// A message that hasn't been seen before.
+    New(Msg),
+    /// The UUID of a message that was already seen.
+    Seen(Uuid),
+}
+
+impl Frame {
+    fn uuid(&self) -> Uuid {
+        match self {
+            Frame::New(msg) => msg.uuid(),
+            Frame::Seen(uuid) => *uuid,
+        }
+    }
+}
+
+/// A frame as read from a peer, before it is decoded.
+type RawFrame = Vec<u8>;
+
+/// Reads and decodes every frame currently available from `peer`, as of
+/// the time on `clock`.
+///
+/// Returns the peer, unless it disconnected, along with the valid
+/// frames read.
+fn process_msg<T: Transport>(
+    mut peer: Peer<T>,
+    seen: &mut DedupQueue<Msg>,
+    seen_log: &mut Option<SeenLog>,
+    drops: &mut Drops,
+    hexdump: bool,
+    clock: &dyn Clock,
+    sink: &mut impl MessageSink,
+) -> (Option<Peer<T>>, Vec<(Frame, SocketAddr)>) {
+    let now = clock.now_instant();
+    for _ in 0..peer.expire_fragments(now) {
+        drops.record(DropReason::Incomplete);
+    }
+    let (peer, frames) = read_frames(peer, now, hexdump, sink);
+    let unix_now = clock.now_unix_millis() / 1000;
+    let frames = frames
+        .into_iter()
+        .filter_map(|(frame, addr)| {
+            process_frame(frame, addr, seen, seen_log, drops, unix_now, sink)
+                .map(|frame| (frame, addr))
+        })
+        .collect();
+
+    (peer, frames)
+}
+
+/// Reads every frame currently available from `peer`, up to
+/// [`FRAMES_PER_PASS`].
+///
+/// Reading continues until the stream would block, and only whole frames
+/// are returned; the bytes of a frame that was only partly received, and
+/// any frames past the limit, are kept by `peer` for the next pass. Once
+/// the peer has disconnected, every frame left is returned. Returns the
+/// peer, unless it disconnected, along with the frames read, each reported
+/// as a hex dump with `hexdump`. The peer counts as heard from at `now`.
+fn read_frames<T: Transport>(
+    mut peer: Peer<T>,
+    now: Instant,
+    hexdump: bool,
+    sink: &mut impl MessageSink,
+) -> (Option<Peer<T>>, Vec<(RawFrame, SocketAddr)>) {
+    let addr = peer.addr();
+    let closed = loop {
+        match peer.receive(now) {
+            Ok(0) => break Some(DisconnectReason::Closed),
+            Ok(_) => (),
+            Err(err) => match Recovery::of(&err) {
+                Recovery::Retry => (),
+                Recovery::Wait => break None,
+                Recovery::Drop => break Some(DisconnectReason::Failed(err.kind())),
+            },
+        }
+    };
+    let connected = closed.is_none();
+
+    let greeted = peer.advertised().is_some();
+    let mut frames = Vec::new();
+    while !connected || frames.len() < FRAMES_PER_PASS {
+        let Some(frame) = peer.next_frame(now) else {
+            break;
+        };
+        if hexdump {
+            sink.on_event(&Event::Notice(dump_frame("read from", addr, &frame)));
+        }
+        frames.push((frame, addr));
+    }
+    if let Some(listening) = peer.advertised().filter(|_| !greeted) {
+        sink.on_event(&Event::Peer(PeerEvent::Handshaken { addr, listening }));
+    }
+
+    match closed {
+        None => (Some(peer), frames),
+        Some(reason) => {
+            sink.on_event(&Event::Peer(PeerEvent::Disconnected { addr, reason }));
+            (None, frames)
+        }
+    }
+}
+
+/// Decodes a frame from `addr`, delivering the message to `sink` if it hasn't
+/// been seen before, neither in `seen` nor in the persistent `seen_log`.
+///
+/// Messages expired by `now`, in seconds since the Unix epoch, are dropped
+/// outright, so they are neither delivered nor propagated.
+fn process_frame(
+    frame: RawFrame,
+    addr: SocketAddr,
+    seen: &mut DedupQueue<Msg>,
+    seen_log: &mut Option<SeenLog>,
+    drops: &mut Drops,
+    now: u64,
+    sink: &mut impl MessageSink,
+) -> Option<Frame> {
+    match Msg::try_from(frame) {
+        Ok(m) if m.is_expired(now.saturating_sub(CLOCK_SKEW)) => {
+            drops.record(DropReason::Expired);
+            None
+        }
+        Ok(m) => {
+            let logged = seen_log.as_ref().is_some_and(|log| log.contains(m.uuid()));
+            if seen.touch(&m) || logged {
+                drops.record(DropReason::Duplicate);
+                return Some(Frame::Seen(m.uuid()));
+            }
+
+            record_seen(seen_log, &m, sink);
+            seen.push(m.clone());
+
+            sink.on_message(&m, addr);
+
+            Some(Frame::New(m))
+        }
+        Err(err) => {
+            drops.record(DropReason::Malformed);
+            sink.on_event(&Event::Notice(format!(
+                "{err}, skipping message from {addr}"
+            )));
+            None
+        }
+    }
+}
+
+/// Records `msg` in the persistent seen log, if there is one.
+fn record_seen(seen_log: &mut Option<SeenLog>, msg: &Msg, sink: &mut impl MessageSink) {
+    if let Some(Err(err)) = seen_log.as_mut().map(|log| log.record(msg.uuid())) {
+        sink.on_event(&Event::Notice(format!("failed to write seen log: {err}")));
+    }
+}
+
+/// Propagates a message `msg` received from the peers `origins` to the other peers.
+///
+/// The message is forwarded with one hop less, unless it has none left.
+/// The peers are kept in their original order.
+pub fn propagate<T: Transport>(
+    mut peers: Vec<Peer<T>>,
+    msg: Msg,
+    origins: &[SocketAddr],
+    fanout: &mut Fanout,
+    now: Instant,
+    sink: &mut impl MessageSink,
+) -> (Vec<Peer<T>>, Delivery) {
+    let Some(msg) = msg.hop() else {
+        return (peers, Delivery::default());
+    };
+    let targets = (0..peers.len())
+        .filter(|&i| !origins.contains(&peers[i].addr()))
+        .collect();
+    let frames = fragment::split(msg, fanout.capacity());
+    let (failed, delivery) = fan_out(&mut peers, targets, &frames, fanout, now, sink);
+
+    (prune(peers, &failed), delivery)
+}
+
+/// How a node dials its peers, see [`connect`].
+#[derive(Debug, Clone, Copy)]
+pub struct Dialer {
+    /// The address advertised in our hello.
+    pub listening: SocketAddr,
+    /// The nonce sent in our hello, see [`handshake::hello`].
+    pub nonce: u64,
+    /// SOCKS5 proxy through which peers are dialed, if any.
+    pub proxy: Option<SocketAddr>,
+    /// Size of every frame exchanged with the peer.
+    pub capacity: usize,
+    /// Most peers to be connected to at once, if limited.
+    pub max_peers: Option<usize>,
+    /// The framing offered to the peer.
+    pub framing: Framing,
+}
+
+/// Connects to a given peer, through the SOCKS5 proxy of `dialer` if it
+/// has one, and greets it with a hello advertising where we listen.
+///
+/// Fails without dialing if `peers` already holds as many peers as
+/// `dialer` allows. The peer joins as heard from `now`.
+pub fn connect(
+    peers: &mut Vec<Peer>,
+    addr: SocketAddr,
+    host: Option<String>,
+    dialer: Dialer,
+    now: Instant,
+    audit: &mut Option<AuditLog>,
+    sink: &mut impl MessageSink,
+) -> io::Result<()> {
+    let refusal = dialer
+        .max_peers
+        .filter(|&max| peers.len() >= max)
+        .map(|max| format!("at the limit of {max} peers"));
+    if let Some(reason) = refusal {
+        record_audit(
+            audit,
+            Direction::Outbound,
+            addr,
+            Outcome::Rejected,
+            Some(&reason),
+            sink,
+        );
+        return Err(io::Error::other(reason));
+    }
+    let dialed = match dialer.proxy {
+        Some(proxy) => socks::connect(proxy, addr),
+        None => TcpStream::connect(addr),
+    };
+    let conn = match dialed {
+        Ok(conn) => conn,
+        Err(err) => {
+            let reason = err.to_string();
+            record_audit(
+                audit,
+                Direction::Outbound,
+                addr,
+                Outcome::Failed,
+                Some(&reason),
+                sink,
+            );
+            return Err(err);
+        }
+    };
+    conn.set_nonblocking(true)
+        .expect("setting nonblocking failed");
+    record_audit(
+        audit,
+        Direction::Outbound,
+        addr,
+        Outcome::Accepted,
+        None,
+        sink,
+    );
+    let mut peer = Peer::dialed(conn, addr, host)
+        .with_last_seen(now)
+        .with_frame_size(dialer.capacity)
+        .with_framing(dialer.framing);
+    peer.greet(dialer.listening, dialer.nonce)?;
+    sink.on_event(&Event::Peer(PeerEvent::Connected(addr)));
+    peers.push(peer);
+    Ok(())
+}
+
+/// Writes the frames of a serialized message to the peer known by `to`
+/// only, see [`Peer::identity`]. Returns the peers that could be written to.
+fn send_to<T: Transport>(
+    mut peers: Vec<Peer<T>>,
+    to: SocketAddr,
+    frames: &[RawFrame],
+    fanout: &mut Fanout,
+    now: Instant,
+    sink: &mut impl MessageSink,
+) -> (Vec<Peer<T>>, Delivery) {
+    let Some(target) = peers.iter().position(|peer| peer.identity() == to) else {
+        sink.on_event(&Event::Notice(format!("no peer {to}, not sending")));
+        return (peers, Delivery::default());
+    };
+    let (failed, delivery) = fan_out(&mut peers, vec![target], frames, fanout, now, sink);
+
+    (prune(peers, &failed), delivery)
+}
+
+/// Broadcasts a serialized message to peers, as the frames it was split
+/// into, see [`fragment::split`].
+///
+/// The message is serialized once by the caller and the same bytes are
+/// written to every peer. Returns the peers that could be written to,
+/// along with how many were and weren't.
+pub fn broadcast<T: Transport>(
+    mut peers: Vec<Peer<T>>,
+    frames: &[RawFrame],
+    fanout: &mut Fanout,
+    now: Instant,
+    sink: &mut impl MessageSink,
+) -> (Vec<Peer<T>>, Delivery) {
+    let targets = (0..peers.len()).collect();
+    let (failed, delivery) = fan_out(&mut peers, targets, frames, fanout, now, sink);
+
+    (prune(peers, &failed), delivery)
+}
+
+/// Drops the peers at the indices `failed`, keeping the rest in order.
+fn prune<T: Transport>(peers: Vec<Peer<T>>, failed: &[usize]) -> Vec<Peer<T>> {
+    peers
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !failed.contains(i))
+        .map(|(_, peer)| peer)
+        .collect()
+}
+
+/// Writes the frames of a serialized message to the peers at the indices
+/// `targets`, in the order given by `fanout` and subject to its injected
+/// faults.
+///
+/// When coalescing, the frames are only buffered as of `now`, to be written
+/// along with later ones by [`Node::write_coalesced`].
+///
+/// Returns the indices of the peers that couldn't be written to, along
+/// with how many were and weren't, which is also added to
+/// [`Fanout::deliveries`]. Peers skipped by injected loss count as neither.
+fn fan_out<T: Transport>(
+    peers: &mut [Peer<T>],
+    targets: Vec<usize>,
+    frames: &[RawFrame],
+    fanout: &mut Fanout,
+    now: Instant,
+    sink: &mut impl MessageSink,
+) -> (Vec<usize>, Delivery) {
+    let mut failed = Vec::new();
+    let mut delivered = 0;
+    for i in fanout.order(targets) {
+        if !fanout.faults.pass() {
+            continue;
+        }
+        match &mut fanout.coalesce {
+            Some(coalesce) => {
+                let to = peers[i].addr();
+                for frame in frames {
+                    if fanout.hexdump {
+                        sink.on_event(&Event::Notice(dump_frame("buffered for", to, frame)));
+                    }
+                    coalesce.push(to, frame, now);
+                }
+                delivered += 1;
+            }
+            None => {
+                let sent = frames
+                    .iter()
+                    .try_for_each(|frame| send(&mut peers[i], frame, fanout.hexdump, sink));
+                match sent {
+                    Ok(()) => delivered += 1,
+                    Err(err) => {
+                        sink.on_event(&Event::Notice(format!(
+                            "failed to write to {}: {err}, dropping peer",
+                            peers[i].addr()
+                        )));
+                        failed.push(i);
+                    }
+                }
+            }
+        }
+    }
+
+    let delivery = Delivery {
+        delivered,
+        failed: failed.len(),
+    };
+    fanout.deliveries += delivery;
+    (failed, delivery)
+}
+
+/// Writes a serialized message to a single peer, reporting it as a hex
+/// dump with `hexdump`.
+fn send<T: Transport>(
+    peer: &mut Peer<T>,
+    frame: &RawFrame,
+    hexdump: bool,
+    sink: &mut impl MessageSink,
+) -> io::Result<()> {
+    let written = peer.write(frame)?;
+    let to = peer.addr();
+    if hexdump {
+        sink.on_event(&Event::Notice(dump_frame("written to", to, frame)));
+    }
+    sink.on_event(&Event::Sent { to, bytes: written });
+    Ok(())
+}
+
+/// Renders a hex dump of `frame`, leaving out the zero padding at its end.
+fn dump_frame(direction: &str, addr: SocketAddr, frame: &RawFrame) -> String {
+    let end = frame
+        .iter()
+        .rposition(|b| *b != 0)
+        .map_or(0, |last| last + 1);
+    format!(
+        "frame {direction} {addr}, {} padding bytes omitted:\n{}",
+        frame.len() - end,
+        hex::dump(&frame[..end])
+    )
+}
+
+/// Flushes pending writes to every peer, reporting the result per peer.
+fn flush_all<T: Transport>(peers: &mut [Peer<T>], sink: &mut impl MessageSink) {
+    peers.iter_mut().for_each(|peer| {
+        let addr = peer.addr();
+        let notice = match peer.flush() {
+            Ok(()) => format!("flushed {addr}"),
+            Err(err) => format!("failed to flush {addr}: {err}"),
+        };
+        sink.on_event(&Event::Notice(notice));
+    });
+}
+
+/// Decodes a frame given as raw bytes, padding it with zeroes to `capacity`.
+fn decode_frame(bytes: &[u8], capacity: usize) -> Result<Msg, msg::TryFromArrayToMsgError> {
+    let mut frame = bytes.to_vec();
+    frame.resize(capacity.max(bytes.len()), 0);
+    Msg::try_from(frame)
+}
+
+/// Renders the byte layout of the serialized `msg`, one field per line.
+fn explain(msg: &Msg) -> String {
+    msg.layout()
+        .into_iter()
+        .map(|field| {
+            let range = format!("{}..{}", field.range.start, field.range.end);
+            format!("{range:>9}  {} ({} bytes)", field.name, field.range.len())
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Runs the command line given to the process.
+pub fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mode = match Mode::from_args(&args) {
+        Ok(mode) => mode,
+        Err(err) => {
+            eprintln!("{err}\n{}", cli::USAGE);
+            process::exit(1);
+        }
+    };
+    let config = match Config::from_args(&args) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}\n{}", cli::USAGE);
+            process::exit(1);
+        }
+    };
+
+    if config.detach {
+        if config.control.is_none() {
+            eprintln!("--detach requires --control to take commands");
+            process::exit(1);
+        }
+        if let Err(err) = daemon::detach() {
+            eprintln!("failed to detach: {err}");
+            process::exit(1);
+        }
+    }
+
+    let sink = sink::Stdout::new(Printer::new(config.show_seq).with_age(config.show_age))
+        .with_level(config.log_level);
+    let result = match mode {
+        Mode::Run(ip) => listener(ip, &config).and_then(|listener| run(listener, config, sink)),
+        Mode::Send { to, text } => send_once(to, text, config, sink),
+        Mode::Recv { listen, count } => {
+            listener(listen, &config).and_then(|listener| recv_count(listener, count, config, sink))
+        }
+        Mode::Bench { count } => bench(count, config, sink),
+    };
+    if let Err(err) = result {
+        eprintln!("{err}");
+        process::exit(1);
+    }
+}
+
+/// Returns the listener a node runs on: the one passed in with
+/// `--listen-fd`, or one bound on `ip`, see [`on_interface`].
+///
+/// Only ever called once, so the passed in descriptor is adopted once.
+fn listener(ip: SocketAddr, config: &Config) -> io::Result<TcpListener> {
+    match config.listen_fd {
+        // SAFETY: `--listen-fd` names a descriptor inherited from whoever
+        // started the process, e.g. systemd, which nothing else in it owns.
+        Some(fd) => activation::adopt(unsafe { OwnedFd::from_raw_fd(fd) }),
+        None => TcpListener::bind(on_interface(ip, config)),
+    }
+}
+
+/// Replaces the IP of `ip` by the address of the interface named in
+/// `config`, if any, exiting if there is no such interface.
+fn on_interface(mut ip: SocketAddr, config: &Config) -> SocketAddr {
+    if let Some(name) = &config.interface {
+        match iface::lookup(name) {
+            Ok(addr) => ip.set_ip(addr),
+            Err(err) => {
+                eprintln!("failed to bind to interface: {err}");
+                process::exit(1);
+            }
+        }
+    }
+    ip
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        env, fs,
+        io::{ErrorKind, Read, Write},
+        net::{SocketAddr, TcpListener, TcpStream},
+        path::PathBuf,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use uuid::Uuid;
+
+    use crate::{
+        Command, Dialer, FRAMES_PER_PASS, Frame, ParseCommandError, accept,
+        audit::{self, AuditLog, Outcome},
+        blocklist::Blocklist,
+        broadcast,
+        clock::{Clock, System},
+        connect, decode_frame, explain,
+        fanout::Fanout,
+        fragment, hex,
+        msg::{self, Msg},
+        node::Config,
+        peer::{Framing, Peer},
+        process_frame, process_msg, propagate,
+        queue::DedupQueue,
+        read_frames, receive_msgs,
+        rng::Rng,
+        seenlog::SeenLog,
+        sink::{DisconnectReason, Event, PeerEvent, Recorder},
+        stats::{Delivery, DropReason, Drops},
+        transport::{Mock, Transport},
+    };
+
+    #[test]
+    fn blocked_peer_from_file_is_rejected() {
+        let path = temp_path("blocklist");
+        fs::write(&path, "127.0.0.1\n").unwrap();
+        let blocklist = Blocklist::load(&path).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (comm, _) = listener.accept().unwrap();
+
+        let mut peers = Vec::new();
+        let mut sink = Recorder::default();
+        accept(
+            &mut peers,
+            Peer::try_from(comm).unwrap(),
+            &blocklist,
+            &Config::default(),
+            Instant::now(),
+            &mut None,
+            &mut sink,
+        );
+
+        assert!(peers.is_empty());
+        assert!(matches!(sink.events[..], [Event::Rejected { .. }]));
+        assert_eq!(client.read(&mut [0; 1]).unwrap(), 0);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn received_message_is_delivered_to_sink() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (comm, _) = listener.accept().unwrap();
+        comm.set_nonblocking(true).unwrap();
+        let msg = Msg::new("hello").unwrap();
+        client.write_all(&msg.clone().into_bytes()).unwrap();
+
+        let mut seen = DedupQueue::new(16);
+        let mut sink = Recorder::default();
+        let (stream, propagees) = process_msg(
+            Peer::try_from(comm).unwrap(),
+            &mut seen,
+            &mut None,
+            &mut Drops::default(),
+            false,
+            &System,
+            &mut sink,
+        );
+
+        let from = client.local_addr().unwrap();
+        assert!(stream.is_some());
+        assert_eq!(sink.messages, vec![(msg.clone(), from)]);
+        assert_eq!(propagees, vec![(Frame::New(msg), from)]);
+    }
+
+    #[test]
+    fn full_frame_followed_by_another_is_read() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (comm, _) = listener.accept().unwrap();
+        comm.set_nonblocking(true).unwrap();
+
+        let max_text = msg::CAPACITY - msg::TEXT_LEN_SIZE - msg::UUID_SIZE - msg::TTL_SIZE;
+        let full = Msg::new("a".repeat(max_text)).unwrap();
+        let second = Msg::new("second").unwrap();
+        let mut bytes = full.clone().into_bytes().to_vec();
+        bytes.extend(second.clone().into_bytes());
+        client.write_all(&bytes).unwrap();
+
+        let mut seen = DedupQueue::new(16);
+        let mut sink = Recorder::default();
+        let (_, propagees) = process_msg(
+            Peer::try_from(comm).unwrap(),
+            &mut seen,
+            &mut None,
+            &mut Drops::default(),
+            false,
+            &System,
+            &mut sink,
+        );
+
+        let from = client.local_addr().unwrap();
+        assert_eq!(
+            propagees,
+            vec![(Frame::New(full), from), (Frame::New(second), from)]
+        );
+    }
+
+    #[test]
+    fn ready_frames_are_read_in_one_pass_up_to_the_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (comm, _) = listener.accept().unwrap();
+        comm.set_nonblocking(true).unwrap();
+        let mut peer = Peer::try_from(comm).unwrap();
+        let mut seen = DedupQueue::new(FRAMES_PER_PASS * 2);
+        let mut sink = Recorder::default();
+        let mut pass = |peer: Peer| {
+            let (peer, propagees) = process_msg(
+                peer,
+                &mut seen,
+                &mut None,
+                &mut Drops::default(),
+                false,
+                &System,
+                &mut sink,
+            );
+            (peer.unwrap(), propagees.len())
+        };
+
+        let frames: Vec<_> = (0..3)
+            .flat_map(|i| Msg::new(format!("frame {i}")).unwrap().into_bytes())
+            .collect();
+        client.write_all(&frames).unwrap();
+        let read;
+        (peer, read) = pass(peer);
+        assert_eq!(read, 3);
+
+        let frames: Vec<_> = (0..FRAMES_PER_PASS + 1)
+            .flat_map(|i| Msg::new(format!("burst {i}")).unwrap().into_bytes())
+            .collect();
+        client.write_all(&frames).unwrap();
+        let mut passes = Vec::new();
+        while passes.iter().sum::<usize>() < FRAMES_PER_PASS + 1 {
+            let read;
+            (peer, read) = pass(peer);
+            passes.push(read);
+        }
+        assert!(passes.iter().all(|&read| read <= FRAMES_PER_PASS));
+        assert!(passes.contains(&FRAMES_PER_PASS));
+    }
+
+    #[test]
+    fn long_message_is_sent_in_fragments_and_delivered_whole() {
+        let addr: SocketAddr = "10.0.0.2:4000".parse().unwrap();
+        let sender = Mock::new("10.0.0.1:4000".parse().unwrap(), addr);
+        let msg = Msg::new_in("long ".repeat(200), msg::MAX_CAPACITY).unwrap();
+        let mut sink = Recorder::default();
+        let _peers = broadcast(
+            vec![Peer::new(sender.clone(), addr)],
+            &fragment::split(msg.clone(), msg::CAPACITY),
+            &mut Fanout::default(),
+            Instant::now(),
+            &mut sink,
+        );
+        let written = sender.written();
+        assert_eq!(written.len(), 3 * msg::CAPACITY);
+
+        let receiver = Mock::new("10.0.0.1:4000".parse().unwrap(), addr);
+        for i in [1, 2, 0] {
+            receiver.feed(&written[i * msg::CAPACITY..(i + 1) * msg::CAPACITY]);
+        }
+        let mut drops = Drops::default();
+        let (_, frames) = process_msg(
+            Peer::new(receiver, addr),
+            &mut DedupQueue::new(16),
+            &mut None,
+            &mut drops,
+            false,
+            &System,
+            &mut sink,
+        );
+        assert_eq!(frames, vec![(Frame::New(msg.clone()), addr)]);
+        assert_eq!(sink.messages, vec![(msg, addr)]);
+        assert_eq!(drops, Drops::default());
+    }
+
+    #[test]
+    fn read_errors_are_retried_or_drop_the_peer() {
+        let addr: SocketAddr = "10.0.0.2:4000".parse().unwrap();
+        let mock = Mock::new("10.0.0.1:4000".parse().unwrap(), addr);
+        let mut sink = Recorder::default();
+
+        mock.fail(ErrorKind::Interrupted);
+        mock.feed(&Msg::new("after a retry").unwrap().into_bytes());
+        let (peer, frames) = read_frames(
+            Peer::new(mock.clone(), addr),
+            Instant::now(),
+            false,
+            &mut sink,
+        );
+        assert!(peer.is_some());
+        assert_eq!(frames.len(), 1);
+
+        mock.fail(ErrorKind::ConnectionReset);
+        let (peer, _) = read_frames(peer.unwrap(), Instant::now(), false, &mut sink);
+        assert!(peer.is_none());
+        assert_eq!(
+            sink.events,
+            vec![Event::Peer(PeerEvent::Disconnected {
+                addr,
+                reason: DisconnectReason::Failed(ErrorKind::ConnectionReset),
+            })]
+        );
+    }
+
+    #[test]
+    fn message_is_propagated_to_every_peer_but_its_origin() {
+        let local: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let mocks: Vec<_> = (1..=3)
+            .map(|i| Mock::new(local, format!("10.0.0.{}:4000", i + 1).parse().unwrap()))
+            .collect();
+        let peers: Vec<_> = mocks
+            .iter()
+            .map(|mock| Peer::new(mock.clone(), mock.peer_addr().unwrap()))
+            .collect();
+        let msg = Msg::new("relayed").unwrap().with_ttl(2);
+        mocks[0].feed(&msg.clone().into_bytes());
+
+        let mut sink = Recorder::default();
+        let peers = receive_msgs(
+            peers,
+            &mut DedupQueue::new(16),
+            &mut None,
+            &mut Drops::default(),
+            &mut Fanout::default(),
+            &System,
+            &mut sink,
+        );
+
+        assert_eq!(peers.len(), 3);
+        assert_eq!(sink.messages, vec![(msg.clone(), peers[0].addr())]);
+        assert!(mocks[0].written().is_empty());
+        for mock in &mocks[1..] {
+            let frame: [u8; msg::CAPACITY] = mock.written().try_into().unwrap();
+            let relayed = Msg::try_from(frame).unwrap();
+            assert_eq!(relayed, msg);
+            assert_eq!(relayed.ttl(), 1);
+        }
+    }
+
+    /// Processes frames from `peer` until some arrive, returning them.
+    fn process_until_frames(
+        peer: &mut Option<Peer>,
+        seen: &mut DedupQueue<Msg>,
+        sink: &mut Recorder,
+    ) -> Vec<(Frame, SocketAddr)> {
+        loop {
+            let (retained, propagees) = process_msg(
+                peer.take().unwrap(),
+                seen,
+                &mut None,
+                &mut Drops::default(),
+                false,
+                &System,
+                sink,
+            );
+            *peer = retained;
+            if !propagees.is_empty() {
+                return propagees;
+            }
+        }
+    }
+
+    #[test]
+    fn frame_sent_byte_by_byte_is_read_once_whole() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (comm, mut client) = loopback(&listener);
+        let from = client.local_addr().unwrap();
+        let msg = Msg::new("trickle").unwrap();
+        let bytes = msg.clone().into_bytes();
+
+        let mut peer = Some(comm);
+        let mut seen = DedupQueue::new(16);
+        let mut sink = Recorder::default();
+        for byte in &bytes[..msg::CAPACITY - 1] {
+            client.write_all(&[*byte]).unwrap();
+            let (retained, propagees) = process_msg(
+                peer.take().unwrap(),
+                &mut seen,
+                &mut None,
+                &mut Drops::default(),
+                false,
+                &System,
+                &mut sink,
+            );
+            assert!(propagees.is_empty());
+            peer = retained;
+        }
+        client.write_all(&bytes[msg::CAPACITY - 1..]).unwrap();
+
+        let propagees = process_until_frames(&mut peer, &mut seen, &mut sink);
+        assert_eq!(propagees, vec![(Frame::New(msg), from)]);
+    }
+
+    #[test]
+    fn partial_frame_is_kept_for_next_read() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (comm, mut client) = loopback(&listener);
+        let from = client.local_addr().unwrap();
+        let first = Msg::new("first").unwrap();
+        let second = Msg::new("second").unwrap();
+        let second_bytes = second.clone().into_bytes();
+        let (head, tail) = second_bytes.split_at(msg::CAPACITY / 2);
+
+        let mut peer = Some(comm);
+        let mut seen = DedupQueue::new(16);
+        let mut sink = Recorder::default();
+        let mut bytes = first.clone().into_bytes().to_vec();
+        bytes.extend_from_slice(head);
+        client.write_all(&bytes).unwrap();
+        let propagees = process_until_frames(&mut peer, &mut seen, &mut sink);
+        assert_eq!(propagees, vec![(Frame::New(first), from)]);
+
+        client.write_all(tail).unwrap();
+        let propagees = process_until_frames(&mut peer, &mut seen, &mut sink);
+        assert_eq!(propagees, vec![(Frame::New(second), from)]);
+    }
+
+    #[test]
+    fn parse_flush_all() {
+        assert!(matches!("flush-all".parse(), Ok(Command::FlushAll)));
+        assert!(matches!("flush-all\n".parse(), Ok(Command::FlushAll)));
+    }
+
+    #[test]
+    fn encode_decode_commands_round_trip() {
+        let Ok(Command::Encode(msg)) = "encode hello".parse() else {
+            panic!("expected an encode command");
+        };
+        let hex = hex::encode(&msg.clone().into_bytes());
+
+        let Ok(Command::Decode(bytes)) = format!("decode {hex}").parse() else {
+            panic!("expected a decode command");
+        };
+
+        assert_eq!(decode_frame(&bytes, msg::CAPACITY).unwrap(), msg);
+    }
+
+    #[test]
+    fn decode_command_rejects_bad_input() {
+        assert!("decode 0g".parse::<Command>().is_err());
+        assert!(
+            format!("decode {}", "00".repeat(msg::CAPACITY + 1))
+                .parse::<Command>()
+                .is_err()
+        );
+        assert!(
+            format!("encode {}", "a".repeat(msg::CAPACITY))
+                .parse::<Command>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn connections_per_ip_are_capped() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = Config {
+            max_per_ip: Some(2),
+            ..Config::default()
+        };
+
+        let mut peers = Vec::new();
+        let mut sink = Recorder::default();
+        let clients: Vec<_> = (0..3)
+            .map(|_| {
+                let client = TcpStream::connect(addr).unwrap();
+                let (comm, _) = listener.accept().unwrap();
+                accept(
+                    &mut peers,
+                    Peer::try_from(comm).unwrap(),
+                    &Blocklist::default(),
+                    &config,
+                    Instant::now(),
+                    &mut None,
+                    &mut sink,
+                );
+                client
+            })
+            .collect();
+
+        assert_eq!(peers.len(), 2);
+        assert!(matches!(sink.events[..], [_, _, Event::Rejected { .. }]));
+        assert_eq!((&clients[2]).read(&mut [0; 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn dialed_peers_dont_count_towards_the_cap_per_ip() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = Config {
+            max_per_ip: Some(2),
+            ..Config::default()
+        };
+        let dialed = Peer::dialed(TcpStream::connect(addr).unwrap(), addr, None);
+        let _dialed_end = listener.accept().unwrap();
+
+        let mut peers = vec![dialed];
+        let mut sink = Recorder::default();
+        for _ in 0..3 {
+            let _client = TcpStream::connect(addr).unwrap();
+            let (comm, _) = listener.accept().unwrap();
+            accept(
+                &mut peers,
+                Peer::try_from(comm).unwrap(),
+                &Blocklist::default(),
+                &config,
+                Instant::now(),
+                &mut None,
+                &mut sink,
+            );
+        }
+
+        assert_eq!(peers.len(), 3);
+        assert!(matches!(sink.events[..], [_, _, Event::Rejected { .. }]));
+    }
+
+    #[test]
+    fn peers_past_the_limit_are_refused_both_ways() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = Config {
+            max_peers: Some(2),
+            ..Config::default()
+        };
+
+        let mut peers = Vec::new();
+        let mut sink = Recorder::default();
+        let clients: Vec<_> = (0..3)
+            .map(|_| {
+                let client = TcpStream::connect(addr).unwrap();
+                let (comm, _) = listener.accept().unwrap();
+                accept(
+                    &mut peers,
+                    Peer::try_from(comm).unwrap(),
+                    &Blocklist::default(),
+                    &config,
+                    Instant::now(),
+                    &mut None,
+                    &mut sink,
+                );
+                client
+            })
+            .collect();
+        assert_eq!(peers.len(), 2);
+        assert!(matches!(
+            &sink.events[2],
+            Event::Rejected { reason, .. } if reason == "at the limit of 2 peers"
+        ));
+        assert_eq!((&clients[2]).read(&mut [0; 1]).unwrap(), 0);
+
+        let dialer = Dialer {
+            listening: "127.0.0.1:4000".parse().unwrap(),
+            nonce: 1,
+            proxy: None,
+            capacity: msg::CAPACITY,
+            max_peers: config.max_peers,
+            framing: Framing::Fixed,
+        };
+        let err = connect(
+            &mut peers,
+            addr,
+            None,
+            dialer,
+            Instant::now(),
+            &mut None,
+            &mut sink,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "at the limit of 2 peers");
+        assert_eq!(peers.len(), 2);
+    }
+
+    #[test]
+    fn broadcast_unescapes_newlines() {
+        let Ok(Command::Broadcast(text, None)) = r"broadcast one\ntwo \\n".parse() else {
+            panic!("expected a broadcast command");
+        };
+
+        assert_eq!(text, "one\ntwo \\n");
+    }
+
+    #[test]
+    fn explain_annotates_layout() {
+        let msg = Msg::from_parts("hello", Uuid::new_v4(), None).unwrap();
+        let padding = format!("24..{}", msg::CAPACITY);
+        let expected = [
+            format!("{:>9}  text length (2 bytes)", "0..2"),
+            format!("{:>9}  text (5 bytes)", "2..7"),
+            format!("{:>9}  uuid (16 bytes)", "7..23"),
+            format!("{:>9}  ttl (1 bytes)", "23..24"),
+            format!("{padding:>9}  padding ({} bytes)", msg::CAPACITY - 24),
+        ];
+
+        assert_eq!(explain(&msg), expected.join("\n"));
+    }
+
+    #[test]
+    fn message_in_persisted_log_is_not_propagated() {
+        let path = temp_path("seenlog");
+        let msg = Msg::new("before restart").unwrap();
+        let addr = "127.0.0.1:9000".parse().unwrap();
+
+        let mut seen_log = Some(SeenLog::open(&path, 16).unwrap());
+        let mut sink = Recorder::default();
+        let first = process_frame(
+            msg.clone().into_bytes(),
+            addr,
+            &mut DedupQueue::new(16),
+            &mut seen_log,
+            &mut Drops::default(),
+            System.now_unix_millis() / 1000,
+            &mut sink,
+        );
+        assert_eq!(first, Some(Frame::New(msg.clone())));
+        drop(seen_log);
+
+        let mut seen_log = Some(SeenLog::open(&path, 16).unwrap());
+        let mut sink = Recorder::default();
+        let again = process_frame(
+            msg.clone().into_bytes(),
+            addr,
+            &mut DedupQueue::new(16),
+            &mut seen_log,
+            &mut Drops::default(),
+            System.now_unix_millis() / 1000,
+            &mut sink,
+        );
+        assert_eq!(again, Some(Frame::Seen(msg.uuid())));
+        assert!(sink.messages.is_empty());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    /// Returns a path in the temp dir that no other test uses, named after
+    /// `name`.
+    pub(crate) fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("dust-{name}-{}", Uuid::new_v4()))
+    }
+
+    /// Returns the accepted end of a new loopback connection along with
+    /// the client end, both nonblocking.
+    pub(crate) fn loopback(listener: &TcpListener) -> (Peer, TcpStream) {
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (comm, _) = listener.accept().unwrap();
+        comm.set_nonblocking(true).unwrap();
+        client.set_nonblocking(true).unwrap();
+        (Peer::try_from(comm).unwrap(), client)
+    }
+
+    /// Delivers the same message from two peers in one pass and returns
+    /// whether each of them got it echoed back.
+    fn echoes(split_horizon: bool) -> [bool; 2] {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (b, mut b_client) = loopback(&listener);
+        let (c, mut c_client) = loopback(&listener);
+
+        let msg = Msg::new("gossip").unwrap();
+        b_client.write_all(&msg.clone().into_bytes()).unwrap();
+        c_client.write_all(&msg.into_bytes()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        let mut sink = Recorder::default();
+        let seen = &mut DedupQueue::new(16);
+        let fanout = &mut Fanout {
+            split_horizon,
+            ..Fanout::default()
+        };
+        let _peers = receive_msgs(
+            vec![b, c],
+            seen,
+            &mut None,
+            &mut Drops::default(),
+            fanout,
+            &System,
+            &mut sink,
+        );
+        thread::sleep(Duration::from_millis(50));
+
+        [&mut b_client, &mut c_client]
+            .map(|client| client.read(&mut [0; msg::CAPACITY]).is_ok_and(|n| n > 0))
+    }
+
+    #[test]
+    fn split_horizon_prevents_echo() {
+        assert_eq!(echoes(false), [false, true]);
+        assert_eq!(echoes(true), [false, false]);
+    }
+
+    /// Broadcasts a message to a single peer with the given `loss` and
+    /// returns whether the peer received it.
+    fn delivered(loss: f64) -> bool {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (peer, mut client) = loopback(&listener);
+
+        let mut fanout = Fanout::default();
+        fanout.faults.set(loss, Duration::ZERO);
+        let _peers = broadcast(
+            vec![peer],
+            &[Msg::new("lossy").unwrap().into_bytes()],
+            &mut fanout,
+            Instant::now(),
+            &mut Recorder::default(),
+        );
+        thread::sleep(Duration::from_millis(50));
+
+        client.read(&mut [0; msg::CAPACITY]).is_ok_and(|n| n > 0)
+    }
+
+    #[test]
+    fn injected_loss_drops_frames() {
+        assert!(!delivered(1.0));
+        assert!(delivered(0.0));
+    }
+
+    #[test]
+    fn rejected_connection_is_audited() {
+        let path = temp_path("audit");
+        let config = Config {
+            max_per_ip: Some(0),
+            ..Config::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (comm, _) = listener.accept().unwrap();
+
+        let mut audit = Some(AuditLog::open(&path).unwrap());
+        accept(
+            &mut Vec::new(),
+            Peer::try_from(comm).unwrap(),
+            &Blocklist::default(),
+            &config,
+            Instant::now(),
+            &mut audit,
+            &mut Recorder::default(),
+        );
+
+        let log = fs::read_to_string(&path).unwrap();
+        let entry: audit::Entry = serde_json::from_str(log.lines().next().unwrap()).unwrap();
+        assert_eq!(entry.addr, client.local_addr().unwrap());
+        assert_eq!(entry.outcome, Outcome::Rejected);
+        assert_eq!(
+            entry.reason.as_deref(),
+            Some("too many connections from 127.0.0.1")
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn broadcast_writes_same_frame_to_every_peer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (peers, mut clients): (Vec<_>, Vec<_>) = (0..3).map(|_| loopback(&listener)).unzip();
+        let frame = [Msg::new("once").unwrap().into_bytes()];
+
+        let mut sink = Recorder::default();
+        let _peers = broadcast(
+            peers,
+            &frame,
+            &mut Fanout::default(),
+            Instant::now(),
+            &mut sink,
+        );
+        thread::sleep(Duration::from_millis(50));
+
+        for client in &mut clients {
+            let mut received = [0; msg::CAPACITY];
+            client.read_exact(&mut received).unwrap();
+            assert_eq!(received[..], frame[0][..]);
+        }
+        assert_eq!(sink.events.len(), 3);
+    }
+
+    #[test]
+    fn broadcast_drops_peers_that_fail() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (peers, mut clients): (Vec<_>, Vec<_>) = (0..3).map(|_| loopback(&listener)).unzip();
+        let dead = peers[1].addr();
+        peers[1].shutdown().unwrap();
+        let frame = [Msg::new("survivors").unwrap().into_bytes()];
+
+        let mut sink = Recorder::default();
+        let mut fanout = Fanout::default();
+        let (peers, delivery) = broadcast(peers, &frame, &mut fanout, Instant::now(), &mut sink);
+
+        assert_eq!(
+            delivery,
+            Delivery {
+                delivered: 2,
+                failed: 1
+            }
+        );
+        assert_eq!(fanout.deliveries, delivery);
+        assert_eq!(peers.len(), 2);
+        assert!(peers.iter().all(|peer| peer.addr() != dead));
+        for i in [0, 2] {
+            clients[i].set_nonblocking(false).unwrap();
+            let mut received = [0; msg::CAPACITY];
+            clients[i].read_exact(&mut received).unwrap();
+            assert_eq!(received[..], frame[0][..]);
+        }
+        assert!(
+            sink.events.iter().any(
+                |ev| matches!(ev, Event::Notice(notice) if notice.contains(&dead.to_string()))
+            )
+        );
+    }
+
+    #[test]
+    fn propagation_spends_a_hop_and_stops_when_none_are_left() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (peer, mut client) = loopback(&listener);
+        let mut sink = Recorder::default();
+
+        let (peers, delivery) = propagate(
+            vec![peer],
+            Msg::new("last hop").unwrap().with_ttl(0),
+            &[],
+            &mut Fanout::default(),
+            Instant::now(),
+            &mut sink,
+        );
+        assert_eq!(delivery, Delivery::default());
+        let msg = Msg::new("more hops").unwrap().with_ttl(3);
+        let (_, delivery) = propagate(
+            peers,
+            msg.clone(),
+            &[],
+            &mut Fanout::default(),
+            Instant::now(),
+            &mut sink,
+        );
+        assert_eq!(delivery.delivered, 1);
+
+        client.set_nonblocking(false).unwrap();
+        let mut frame = [0; msg::CAPACITY];
+        client.read_exact(&mut frame).unwrap();
+        let forwarded = Msg::try_from(frame).unwrap();
+        assert_eq!(forwarded, msg);
+        assert_eq!(forwarded.ttl(), 2);
+    }
+
+    #[test]
+    fn message_without_hops_is_still_delivered() {
+        let frame = Msg::new("arrived").unwrap().with_ttl(0).into_bytes();
+        let mut sink = Recorder::default();
+
+        let processed = process_frame(
+            frame,
+            "127.0.0.1:9000".parse().unwrap(),
+            &mut DedupQueue::new(16),
+            &mut None,
+            &mut Drops::default(),
+            System.now_unix_millis() / 1000,
+            &mut sink,
+        );
+
+        assert!(matches!(processed, Some(Frame::New(_))));
+        assert_eq!(sink.messages.len(), 1);
+    }
+
+    #[test]
+    fn propagation_keeps_peer_order() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (peers, _clients): (Vec<_>, Vec<_>) = (0..3).map(|_| loopback(&listener)).unzip();
+        let order: Vec<_> = peers.iter().map(Peer::addr).collect();
+
+        let (peers, _) = propagate(
+            peers,
+            Msg::new("reorder?").unwrap(),
+            &order[1..2],
+            &mut Fanout::default(),
+            Instant::now(),
+            &mut Recorder::default(),
+        );
+
+        let after: Vec<_> = peers.iter().map(Peer::addr).collect();
+        assert_eq!(after, order);
+    }
+
+    #[test]
+    fn duplicate_frame_counts_as_duplicate_drop() {
+        let frame = Msg::new("twice").unwrap().into_bytes();
+        let addr = "127.0.0.1:9000".parse().unwrap();
+        let mut seen = DedupQueue::new(16);
+        let mut drops = Drops::default();
+        let mut sink = Recorder::default();
+
+        for _ in 0..2 {
+            process_frame(
+                frame.clone(),
+                addr,
+                &mut seen,
+                &mut None,
+                &mut drops,
+                System.now_unix_millis() / 1000,
+                &mut sink,
+            );
+        }
+
+        assert_eq!(drops.get(DropReason::Duplicate), 1);
+        assert_eq!(drops.get(DropReason::Malformed), 0);
+    }
+
+    #[test]
+    fn shuffled_broadcast_follows_seed_and_reaches_everyone() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (peers, mut clients): (Vec<_>, Vec<_>) = (0..4).map(|_| loopback(&listener)).unzip();
+        let mut expected: Vec<_> = peers.iter().map(Peer::addr).collect();
+        Rng::seeded(11).shuffle(&mut expected);
+
+        let mut fanout = Fanout {
+            shuffle: Some(Rng::seeded(11)),
+            ..Fanout::default()
+        };
+        let mut sink = Recorder::default();
+        let frame = [Msg::new("fair").unwrap().into_bytes()];
+        let _peers = broadcast(peers, &frame, &mut fanout, Instant::now(), &mut sink);
+        thread::sleep(Duration::from_millis(50));
+
+        let order: Vec<_> = sink
+            .events
+            .iter()
+            .map(|ev| match ev {
+                Event::Sent { to, .. } => *to,
+                ev => panic!("unexpected event {ev:?}"),
+            })
+            .collect();
+        assert_eq!(order, expected);
+        for client in &mut clients {
+            client.read_exact(&mut [0; msg::CAPACITY]).unwrap();
+        }
+    }
+
+    #[test]
+    fn commands_display_as_typed() {
+        for typed in [
+            "connect 1.2.3.4:9000",
+            "broadcast two\\nlines",
+            "broadcast --ttl 2m hello",
+            "broadcast --ttl 90s hello",
+            "whisper 10.0.0.1:4000 psst",
+            "disconnect",
+            "filter off",
+            "replay --keep-uuids seen.json",
+            "replay --as-fast-as-possible seen.json",
+            "replay --keep-uuids --as-fast-as-possible seen.json",
+            "resize-seen 64",
+            "history",
+            "decode 00ff",
+            "faults loss=0.5 delay=20ms",
+            "log debug",
+        ] {
+            let cmd: Command = typed.parse().unwrap();
+            assert_eq!(cmd.to_string(), typed);
+        }
+        let whisper = Command::Whisper(
+            "10.0.0.1:4000".parse().unwrap(),
+            Box::new(Msg::new("a\\b").unwrap()),
+        );
+        assert_eq!(whisper.to_string(), "whisper 10.0.0.1:4000 a\\\\b");
+        assert!(matches!(
+            "log loud".parse::<Command>(),
+            Err(ParseCommandError::ParseLevelError(_))
+        ));
+    }
+
+    #[test]
+    fn parse_broadcast_with_ttl() {
+        let Ok(Command::Broadcast(text, ttl)) = "broadcast --ttl 2m hello".parse() else {
+            panic!("expected a broadcast command");
+        };
+
+        assert_eq!(text, "hello");
+        assert_eq!(ttl, Some(Duration::from_secs(120)));
+        assert!("broadcast --ttl soon hello".parse::<Command>().is_err());
+        assert!(matches!(
+            "broadcast --ttl 18446744073709551615h hello".parse::<Command>(),
+            Err(ParseCommandError::InvalidTtl(_))
+        ));
+    }
+
+    #[test]
+    fn oversized_arguments_get_targeted_errors() {
+        let host = "a".repeat(10_000);
+        for cmd in ["connect", "block", "unblock"] {
+            assert!(matches!(
+                format!("{cmd} {host}:9000").parse::<Command>(),
+                Err(ParseCommandError::AddressTooLong(10_005))
+            ));
+        }
+
+        let text = "a".repeat(msg::MAX_TEXT + 1);
+        for cmd in ["encode", "explain"] {
+            assert!(matches!(
+                format!("{cmd} {text}").parse::<Command>(),
+                Err(ParseCommandError::MessageTooLong { bytes, .. }) if bytes == msg::MAX_TEXT + 1
+            ));
+        }
+        let fragmented = msg::max_text(msg::MAX_CAPACITY);
+        for cmd in ["broadcast", "broadcast --ttl 5m"] {
+            let over = "a".repeat(fragmented + 1);
+            assert!(matches!(
+                format!("{cmd} {over}").parse::<Command>(),
+                Err(ParseCommandError::MessageTooLong { max, .. }) if max == fragmented
+            ));
+        }
+        let fits = "a".repeat(fragmented);
+        assert!(format!("broadcast {fits}").parse::<Command>().is_ok());
+    }
+
+    #[test]
+    fn parse_whisper() {
+        let Ok(Command::Whisper(to, msg)) = "whisper 127.0.0.1:9000 psst, hi".parse() else {
+            panic!("expected a whisper command");
+        };
+
+        assert_eq!(to, "127.0.0.1:9000".parse().unwrap());
+        assert_eq!(msg.text(), "psst, hi");
+        assert!(matches!(
+            "whisper 127.0.0.1:9000".parse::<Command>(),
+            Err(ParseCommandError::MissingArgument(_))
+        ));
+        assert!("whisper nobody hi".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn commands_without_arguments_parse() {
+        assert!(matches!("disconnect".parse(), Ok(Command::Disconnect)));
+        assert!(matches!("disconnect ".parse(), Ok(Command::Disconnect)));
+        assert!(matches!(
+            "broadcast".parse::<Command>(),
+            Err(ParseCommandError::MissingArgument(_))
+        ));
+        assert!(matches!(
+            "broadcast --ttl 5s".parse::<Command>(),
+            Err(ParseCommandError::MissingArgument(_))
+        ));
+        assert!(matches!(
+            "connect ".parse::<Command>(),
+            Err(ParseCommandError::MissingArgument(_))
+        ));
+    }
+
+    #[test]
+    fn connect_resolves_hostnames() {
+        let Ok(Command::Connect(addrs, host)) = "connect localhost:9000".parse() else {
+            panic!("expected a connect command");
+        };
+        assert_eq!(host.as_deref(), Some("localhost:9000"));
+
+        assert!(!addrs.is_empty());
+        assert!(
+            addrs
+                .iter()
+                .all(|addr| addr.ip().is_loopback() && addr.port() == 9000)
+        );
+        assert!(matches!(
+            "connect localhost".parse::<Command>(),
+            Err(ParseCommandError::ResolveError(..))
+        ));
+    }
+
+    #[test]
+    fn expired_message_is_dropped() {
+        let expired = Msg::new("stale")
+            .unwrap()
+            .with_expiry(msg::unix_time() - 60)
+            .unwrap();
+        let mut drops = Drops::default();
+        let mut sink = Recorder::default();
+
+        let frame = process_frame(
+            expired.into_bytes(),
+            "127.0.0.1:9000".parse().unwrap(),
+            &mut DedupQueue::new(16),
+            &mut None,
+            &mut drops,
+            System.now_unix_millis() / 1000,
+            &mut sink,
+        );
+
+        assert_eq!(frame, None);
+        assert_eq!(drops.get(DropReason::Expired), 1);
+        assert!(sink.messages.is_empty());
+    }
+}
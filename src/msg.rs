@@ -1,108 +1,719 @@
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Range,
+    string::FromUtf8Error,
+};
+
 use uuid::Uuid;
 
+use crate::{
+    clock::{self, Clock},
+    queue::HeapSize,
+};
+
 /// A message with a unique id to be sent over the p2p network.
 ///
 /// Every [`Msg`] is guaranteed an upperbound in size.
-/// It is guaranteed that the the message along with the UUID and a
-/// seperating byte all together take up at most `CAPACITY` bytes.
+/// It is guaranteed that the the message along with its length,
+/// the UUID and the TTL all together take up at most its capacity, which
+/// is `CAPACITY` bytes unless chosen otherwise, see [`Msg::with_capacity`].
 ///
 /// When converted to bytes using [`Msg::into_bytes`], the resulting
-/// array is padded with zeroes to take up exactly `CAPACITY` bytes.
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// frame is padded with zeroes to take up exactly its capacity. Every node
+/// of a network must use the same capacity, as the frames are told apart
+/// by their size alone.
+///
+/// A message may also carry the nickname of its sender, which counts
+/// towards the capacity along with its length prefix and is further limited
+/// to `MAX_NICK` bytes. Likewise, it may carry a wall-clock time after
+/// which nodes drop it, and the wall-clock time it was created at.
+///
+/// Every message carries a TTL: the number of further hops it may still
+/// be forwarded. Like the capacity, it is left out when comparing messages,
+/// as the same message arrives with a different TTL depending on the path
+/// it took.
+///
+/// The serialization is canonical: [`Msg::into_bytes`] writes the fields
+/// in a fixed order and zeroes all padding, so messages with the same
+/// fields, TTL included, serialize to the same bytes. Values the format
+/// can't tell apart from an absent field, such as an empty nick or an
+/// expiry of `0`, are rejected when set.
+///
+/// A message is identified by its UUID alone: messages with the same UUID
+/// compare and hash equal whatever their other fields, so a message is
+/// recognized as seen even if it was passed on with fewer hops left.
+#[derive(Debug, Clone, Eq)]
 pub struct Msg {
-    pub text: String,
+    text: String,
     uuid: Uuid,
+    ttl: u8,
+    nick: Option<String>,
+    expires_at: Option<u64>,
+    /// Milliseconds since the Unix epoch.
+    created_at: Option<u64>,
+    /// Size of the frame the message is serialized to.
+    capacity: usize,
+}
+
+impl PartialEq for Msg {
+    fn eq(&self, other: &Self) -> bool {
+        self.uuid == other.uuid
+    }
+}
+
+impl Hash for Msg {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.uuid.hash(state);
+    }
+}
+
+/// Renders the message on a single line as its text, after the sender's
+/// nick if it carries one, with newlines and backslashes escaped as typed
+/// on input, see [`escape`], and other control characters made inert, see
+/// [`sanitize`].
+impl fmt::Display for Msg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = sanitize(&escape(&self.text));
+        match &self.nick {
+            Some(nick) => write!(f, "{}: {text}", sanitize(nick)),
+            None => write!(f, "{text}"),
+        }
+    }
+}
+
+/// Escapes newlines and backslashes in `text` as `\n` and `\\`, so it
+/// fits on a single line.
+pub fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// Escapes the control characters in `text` other than newlines, like
+/// `\u{1b}` for an escape, so that printing text from peers can't move the
+/// cursor, clear the screen or otherwise rewrite the terminal.
+pub fn sanitize(text: &str) -> String {
+    let mut sanitized = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_control() && c != '\n' {
+            sanitized.extend(c.escape_default());
+        } else {
+            sanitized.push(c);
+        }
+    }
+    sanitized
 }
 
 pub const UUID_SIZE: usize = 16;
-pub const SEP_SIZE: usize = 1;
+pub const TEXT_LEN_SIZE: usize = 2;
+pub const TTL_SIZE: usize = 1;
+pub const DEFAULT_TTL: u8 = 16;
+pub const NICK_LEN_SIZE: usize = 1;
+pub const MAX_NICK: usize = 32;
+pub const EXPIRY_SIZE: usize = 8;
+pub const CREATED_SIZE: usize = 8;
+/// Size of a frame unless configured otherwise.
 pub const CAPACITY: usize = 512;
+/// Smallest configurable frame size, leaving room for a hello and a short
+/// message.
+pub const MIN_CAPACITY: usize = 64;
+/// Largest configurable frame size, keeping the text length within a `u16`.
+pub const MAX_CAPACITY: usize = u16::MAX as usize;
+/// Number of hex digits of the UUID in a short id, see [`Msg::short_id`].
+pub const SHORT_ID_LEN: usize = 8;
+/// The longest text a message without a nick or expiry can carry.
+pub const MAX_TEXT: usize = max_text(CAPACITY);
+
+/// Returns the longest text a message without a nick or expiry can carry
+/// in a frame of `capacity` bytes.
+pub const fn max_text(capacity: usize) -> usize {
+    capacity - TEXT_LEN_SIZE - UUID_SIZE - TTL_SIZE
+}
 
-#[derive(Debug, Clone, thiserror::Error)]
-#[error("failed to convert `String` to `Msg`")]
-pub struct TryFromStringToMsgError;
+/// Returns `text`, failing if it is empty or only whitespace.
+///
+/// Only messages created here are held to this; a frame with empty text
+/// still decodes, so nodes that allowed it aren't cut off.
+fn non_empty(text: String) -> Result<String, TryFromStringToMsgError> {
+    if text.trim().is_empty() {
+        Err(TryFromStringToMsgError::EmptyText)
+    } else {
+        Ok(text)
+    }
+}
+
+/// A named byte range within a serialized [`Msg`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub name: &'static str,
+    pub range: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TryFromStringToMsgError {
+    #[error("failed to convert `String` to `Msg`")]
+    Invalid,
+    #[error("message has no text")]
+    EmptyText,
+}
 
 impl TryFrom<String> for Msg {
     type Error = TryFromStringToMsgError;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        let within_capacity = value.len() + SEP_SIZE + UUID_SIZE <= CAPACITY;
-
-        let uuid = Uuid::new_v4();
+        Msg::new(value)
+    }
+}
 
-        assert!(!uuid.as_bytes()[0] != 0, "Uuid started with 0!");
+impl TryFrom<&str> for Msg {
+    type Error = TryFromStringToMsgError;
 
-        if within_capacity {
-            Ok(Self {
-                text: value,
-                uuid: Uuid::new_v4(),
-            })
-        } else {
-            Err(TryFromStringToMsgError)
-        }
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Msg::new(value)
     }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum TryFromArrayToMsgError {
-    #[error("missing seperator")]
-    MissingSep,
+    #[error("text cut off by the end of the frame")]
+    TruncatedText,
+    #[error("uuid cut off by the end of the frame")]
+    TruncatedUuid,
+    #[error("ttl cut off by the end of the frame")]
+    TruncatedTtl,
+    #[error("nick cut off by the end of the frame")]
+    TruncatedNick,
+    #[error("nick of {0} bytes exceeds the maximum of {MAX_NICK} bytes")]
+    NickTooLong(usize),
     #[error("uuid error: `{0}`")]
     CorruptUuid(#[from] uuid::Error),
+    #[error("nil uuid")]
+    NilUuid,
+    #[error("invalid utf-8: `{0}`")]
+    InvalidUtf8(#[from] FromUtf8Error),
 }
 
 impl TryFrom<[u8; CAPACITY]> for Msg {
     type Error = TryFromArrayToMsgError;
 
     fn try_from(value: [u8; CAPACITY]) -> Result<Self, Self::Error> {
-        let sep = value
-            .iter()
-            .position(|c| c == &0)
-            .ok_or(TryFromArrayToMsgError::MissingSep)?;
+        Msg::try_from(&value[..])
+    }
+}
+
+impl TryFrom<Vec<u8>> for Msg {
+    type Error = TryFromArrayToMsgError;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        Msg::try_from(&value[..])
+    }
+}
+
+/// Decodes a frame of any size, which becomes the capacity of the message.
+impl TryFrom<&[u8]> for Msg {
+    type Error = TryFromArrayToMsgError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let len_bytes = value
+            .get(..TEXT_LEN_SIZE)
+            .ok_or(TryFromArrayToMsgError::TruncatedText)?;
+        let text_len = usize::from(u16::from_be_bytes([len_bytes[0], len_bytes[1]]));
+        let text_end = TEXT_LEN_SIZE + text_len;
 
-        let text_bytes = &value[..sep];
-        let uuid_bytes = &value[(sep + SEP_SIZE)..(sep + SEP_SIZE + UUID_SIZE)];
+        let text_bytes = value
+            .get(TEXT_LEN_SIZE..text_end)
+            .ok_or(TryFromArrayToMsgError::TruncatedText)?;
+        let uuid_bytes = value
+            .get(text_end..text_end + UUID_SIZE)
+            .ok_or(TryFromArrayToMsgError::TruncatedUuid)?;
 
-        let text = String::from_utf8_lossy(text_bytes).to_string();
-        let uuid = Uuid::from_slice(uuid_bytes).unwrap();
+        let text = String::from_utf8(text_bytes.to_vec())?;
+        let uuid = Uuid::from_slice(uuid_bytes)?;
+        // Every nil-UUID message would be deduplicated as the same one.
+        if uuid.is_nil() {
+            return Err(TryFromArrayToMsgError::NilUuid);
+        }
+
+        let ttl_at = text_end + UUID_SIZE;
+        let ttl = *value
+            .get(ttl_at)
+            .ok_or(TryFromArrayToMsgError::TruncatedTtl)?;
+
+        // Everything after the TTL is optional. A zero length, or no room
+        // left for one, means there is no nick, and likewise a zero expiry
+        // means the message never expires and a zero creation time that
+        // it wasn't stamped.
+        let nick_len_at = ttl_at + TTL_SIZE;
+        let nick_len = value.get(nick_len_at).map_or(0, |&len| usize::from(len));
+        let nick_start = nick_len_at + NICK_LEN_SIZE;
+        let nick = match nick_len {
+            0 => None,
+            len if len > MAX_NICK => return Err(TryFromArrayToMsgError::NickTooLong(len)),
+            len => {
+                let nick_bytes = value
+                    .get(nick_start..nick_start + len)
+                    .ok_or(TryFromArrayToMsgError::TruncatedNick)?;
+                Some(String::from_utf8(nick_bytes.to_vec())?)
+            }
+        };
+        let expiry_start = nick_start + nick_len;
+        let expires_at = value
+            .get(expiry_start..expiry_start + EXPIRY_SIZE)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u64::from_be_bytes)
+            .filter(|&at| at != 0);
+        let created_start = expiry_start + EXPIRY_SIZE;
+        let created_at = value
+            .get(created_start..created_start + CREATED_SIZE)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u64::from_be_bytes)
+            .filter(|&at| at != 0);
 
-        Ok(Self { text, uuid })
+        Ok(Self {
+            text,
+            uuid,
+            ttl,
+            nick,
+            expires_at,
+            created_at,
+            capacity: value.len(),
+        })
     }
 }
 
 impl Msg {
-    /// Returns and array containing the message in bytes.
+    /// Creates a [`Msg`] with a freshly generated UUID and `DEFAULT_TTL`,
+    /// stamped with the current time of the system, see
+    /// [`Msg::with_created_at`].
+    ///
+    /// Fails if `text` is empty or only whitespace, or if together with
+    /// its length, UUID and TTL it doesn't fit within `CAPACITY` bytes.
+    /// The stamp is left out of messages it doesn't fit in, see
+    /// [`Msg::created_at`].
+    pub fn new(text: impl Into<String>) -> Result<Self, TryFromStringToMsgError> {
+        Self::new_in(text, CAPACITY)
+    }
+
+    /// Creates a [`Msg`] like [`Msg::new`], to fit within `capacity` bytes
+    /// instead.
+    pub fn new_in(
+        text: impl Into<String>,
+        capacity: usize,
+    ) -> Result<Self, TryFromStringToMsgError> {
+        let msg = Self {
+            text: non_empty(text.into())?,
+            uuid: Uuid::new_v4(),
+            ttl: DEFAULT_TTL,
+            nick: None,
+            expires_at: None,
+            created_at: Some(clock::System.now_unix_millis()),
+            capacity,
+        };
+
+        msg.fit()
+    }
+
+    /// Reconstructs a [`Msg`] from its stored text, UUID and creation time,
+    /// in milliseconds since the Unix epoch, if it was stamped.
+    ///
+    /// Unlike [`Msg::new`], no UUID is generated and the message isn't
+    /// stamped anew, so a persisted message keeps its identity and age.
+    /// Fails under the same capacity bound, leaving out the stamp first.
+    pub fn from_parts(
+        text: impl Into<String>,
+        uuid: Uuid,
+        created_at: Option<u64>,
+    ) -> Result<Self, TryFromStringToMsgError> {
+        let msg = Self {
+            text: non_empty(text.into())?,
+            uuid,
+            ttl: DEFAULT_TTL,
+            nick: None,
+            expires_at: None,
+            created_at,
+            capacity: CAPACITY,
+        };
+
+        msg.fit()
+    }
+
+    /// Returns the number of bytes the message is serialized to.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Makes the message serialize to `capacity` bytes.
+    ///
+    /// Fails if the message doesn't fit within `capacity` bytes, even
+    /// without its creation time.
+    pub fn with_capacity(self, capacity: usize) -> Result<Self, TryFromStringToMsgError> {
+        Self { capacity, ..self }.fit()
+    }
+
+    /// Returns the message if it fits within its capacity, leaving out
+    /// the creation time first if that is what doesn't fit.
+    fn fit(self) -> Result<Self, TryFromStringToMsgError> {
+        if self.fits() {
+            return Ok(self);
+        }
+
+        let unstamped = Self {
+            created_at: None,
+            ..self
+        };
+        if unstamped.fits() {
+            Ok(unstamped)
+        } else {
+            Err(TryFromStringToMsgError::Invalid)
+        }
+    }
+
+    /// Returns the number of further hops the message may be forwarded.
+    pub fn ttl(&self) -> u8 {
+        self.ttl
+    }
+
+    /// Sets the number of further hops the message may be forwarded.
+    pub fn with_ttl(self, ttl: u8) -> Self {
+        Self { ttl, ..self }
+    }
+
+    /// Returns the message as forwarded one hop further, or [`None`] if
+    /// it has no hops left.
+    pub fn hop(self) -> Option<Self> {
+        let ttl = self.ttl.checked_sub(1)?;
+        Some(Self { ttl, ..self })
+    }
+
+    /// Attaches the sender's nickname to the message.
+    ///
+    /// Fails if `nick` is empty or longer than `MAX_NICK` bytes, or if the
+    /// message would no longer fit within its capacity.
+    pub fn with_nick(self, nick: impl Into<String>) -> Result<Self, TryFromStringToMsgError> {
+        let nick = nick.into();
+        if nick.is_empty() || nick.len() > MAX_NICK {
+            return Err(TryFromStringToMsgError::Invalid);
+        }
+
+        Self {
+            nick: Some(nick),
+            ..self
+        }
+        .fit()
+    }
+
+    /// Returns the sender's nickname, if the message carries one.
+    pub fn nick(&self) -> Option<&str> {
+        self.nick.as_deref()
+    }
+
+    /// Makes the message expire at `expires_at`, in seconds since the Unix
+    /// epoch.
+    ///
+    /// Fails if `expires_at` is `0`, which is serialized as no expiry, or if
+    /// the message would no longer fit within its capacity.
+    pub fn with_expiry(self, expires_at: u64) -> Result<Self, TryFromStringToMsgError> {
+        if expires_at == 0 {
+            return Err(TryFromStringToMsgError::Invalid);
+        }
+
+        Self {
+            expires_at: Some(expires_at),
+            ..self
+        }
+        .fit()
+    }
+
+    /// Stamps the message as created at `created_at`, in milliseconds since
+    /// the Unix epoch, in place of the time it was stamped with.
+    ///
+    /// The stamp is left out if it doesn't fit, see [`Msg::created_at`].
+    pub fn with_created_at(self, created_at: u64) -> Result<Self, TryFromStringToMsgError> {
+        Self {
+            created_at: Some(created_at),
+            ..self
+        }
+        .fit()
+    }
+
+    /// Returns when the message was created, in milliseconds since the Unix
+    /// epoch, or [`None`] if it wasn't stamped.
+    ///
+    /// The stamp is the first thing to go when a message runs out of room,
+    /// so messages close to their capacity may not carry one.
+    pub fn created_at(&self) -> Option<u64> {
+        self.created_at
+    }
+
+    /// Returns `true` if the message has expired by `now`, in seconds since
+    /// the Unix epoch.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|at| now > at)
+    }
+
+    /// Returns the number of bytes the message takes up before padding.
+    pub fn encoded_len(&self) -> usize {
+        TEXT_LEN_SIZE + self.text.len() + UUID_SIZE + TTL_SIZE + self.trailer_len()
+    }
+
+    /// Returns `true` if the message fits a frame of its capacity.
     ///
-    /// The array contains both `text.msg` and `text.uuid`
-    /// seperated with a `0` byte. The array has a fixed
-    /// size of `CAPACITY` and is padded with trailing `0`s.
-    pub fn into_bytes(self) -> [u8; CAPACITY] {
-        let mut bytes = [0; CAPACITY];
-        let length = self.text.len();
+    /// Every way of building a message checks this, so only messages that
+    /// fit are ever serialized, see [`Msg::into_bytes`].
+    pub fn fits(&self) -> bool {
+        self.encoded_len() <= self.capacity
+    }
+
+    /// Returns the number of bytes taken up by the optional fields after the
+    /// TTL, which are left out entirely when none are set.
+    ///
+    /// The expiry takes up room whenever a creation time follows it.
+    fn trailer_len(&self) -> usize {
+        if self.nick.is_none() && self.expires_at.is_none() && self.created_at.is_none() {
+            return 0;
+        }
+
+        let nick = self.nick.as_ref().map_or(0, String::len);
+        let expiry = match (self.expires_at, self.created_at) {
+            (None, None) => 0,
+            _ => EXPIRY_SIZE,
+        };
+        let created = self.created_at.map_or(0, |_| CREATED_SIZE);
+        NICK_LEN_SIZE + nick + expiry + created
+    }
+
+    /// Returns the text of the message.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns the text of the message, consuming it.
+    pub fn into_text(self) -> String {
         self.text
-            .into_bytes()
-            .into_iter()
-            .enumerate()
-            .for_each(|(i, b)| bytes[i] = b);
+    }
+
+    /// Returns the unique id of the message.
+    pub fn uuid(&self) -> Uuid {
         self.uuid
-            .into_bytes()
-            .into_iter()
-            .enumerate()
-            .for_each(|(i, b)| bytes[i + length + SEP_SIZE] = b);
+    }
+
+    /// Returns the first [`SHORT_ID_LEN`] hex digits of the UUID, to refer to
+    /// the message by where the full UUID would be unwieldy.
+    pub fn short_id(&self) -> String {
+        let mut id = self.uuid.simple().to_string();
+        id.truncate(SHORT_ID_LEN);
+        id
+    }
+
+    /// Returns the byte ranges of each field in [`Msg::into_bytes`], in order.
+    pub fn layout(&self) -> Vec<Field> {
+        let text_end = TEXT_LEN_SIZE + self.text.len();
+        let uuid_end = text_end + UUID_SIZE;
+        let ttl_end = uuid_end + TTL_SIZE;
+
+        let mut fields = vec![
+            Field {
+                name: "text length",
+                range: 0..TEXT_LEN_SIZE,
+            },
+            Field {
+                name: "text",
+                range: TEXT_LEN_SIZE..text_end,
+            },
+            Field {
+                name: "uuid",
+                range: text_end..uuid_end,
+            },
+            Field {
+                name: "ttl",
+                range: uuid_end..ttl_end,
+            },
+        ];
+        if self.trailer_len() > 0 {
+            let nick_start = ttl_end + NICK_LEN_SIZE;
+            let nick_end = nick_start + self.nick.as_ref().map_or(0, String::len);
+            fields.push(Field {
+                name: "nick length",
+                range: ttl_end..nick_start,
+            });
+            if self.nick.is_some() {
+                fields.push(Field {
+                    name: "nick",
+                    range: nick_start..nick_end,
+                });
+            }
+            if self.expires_at.is_some() || self.created_at.is_some() {
+                fields.push(Field {
+                    name: "expiry",
+                    range: nick_end..nick_end + EXPIRY_SIZE,
+                });
+            }
+            if self.created_at.is_some() {
+                let created_start = nick_end + EXPIRY_SIZE;
+                fields.push(Field {
+                    name: "created",
+                    range: created_start..created_start + CREATED_SIZE,
+                });
+            }
+        }
+        fields.push(Field {
+            name: "padding",
+            range: self.encoded_len()..self.capacity,
+        });
+
+        fields
+    }
+
+    /// Returns the frame containing the message in bytes.
+    ///
+    /// The array contains the length of `text.msg` as a big-endian `u16`,
+    /// followed by `text.msg`, `text.uuid` and the TTL as a single byte.
+    /// The length is explicit because the text may itself contain `0`
+    /// bytes. If there is a nick, an expiry
+    /// or a creation time, these follow as the length of the nick, the nick
+    /// itself, the expiry and the creation time, each as a big-endian `u64`
+    /// and left `0` when unset. The frame has the fixed
+    /// size of the capacity and is padded with trailing `0`s.
+    ///
+    /// All offsets are in bytes, so multi-byte characters are
+    /// copied whole.
+    pub fn into_bytes(self) -> Vec<u8> {
+        let text_end = TEXT_LEN_SIZE + self.text.len();
+        let uuid_end = text_end + UUID_SIZE;
+        let ttl_end = uuid_end + TTL_SIZE;
+        let len = self.encoded_len();
+        let capacity = self.capacity;
+        // Every way of building a message keeps it within its capacity.
+        debug_assert!(
+            self.fits(),
+            "message of {len} bytes exceeds capacity of {capacity} bytes"
+        );
+
+        let mut bytes = vec![0; capacity];
+        // The capacity bound keeps the text length well within a `u16`.
+        bytes[..TEXT_LEN_SIZE].copy_from_slice(&(self.text.len() as u16).to_be_bytes());
+        bytes[TEXT_LEN_SIZE..text_end].copy_from_slice(self.text.as_bytes());
+        bytes[text_end..uuid_end].copy_from_slice(self.uuid.as_bytes());
+        bytes[uuid_end] = self.ttl;
+        let nick_start = ttl_end + NICK_LEN_SIZE;
+        let mut nick_end = nick_start;
+        if let Some(nick) = &self.nick {
+            // `with_nick` bounds the length by `MAX_NICK`, so it fits a byte.
+            bytes[ttl_end] = nick.len() as u8;
+            nick_end += nick.len();
+            bytes[nick_start..nick_end].copy_from_slice(nick.as_bytes());
+        }
+        if let Some(expires_at) = self.expires_at {
+            bytes[nick_end..nick_end + EXPIRY_SIZE].copy_from_slice(&expires_at.to_be_bytes());
+        }
+        if let Some(created_at) = self.created_at {
+            let created_start = nick_end + EXPIRY_SIZE;
+            bytes[created_start..created_start + CREATED_SIZE]
+                .copy_from_slice(&created_at.to_be_bytes());
+        }
 
         bytes
     }
 }
 
+impl HeapSize for Msg {
+    fn heap_bytes(&self) -> usize {
+        self.text.capacity() + self.nick.as_ref().map_or(0, String::capacity)
+    }
+}
+
+/// Returns the current wall-clock time in seconds since the Unix epoch.
+pub fn unix_time() -> u64 {
+    clock::System.now_unix_millis() / 1000
+}
+
 #[cfg(test)]
 mod test {
-    use crate::msg::Msg;
+    use std::{collections::HashSet, mem};
+
+    use uuid::Uuid;
+
+    use crate::msg::{
+        CAPACITY, DEFAULT_TTL, MAX_NICK, MAX_TEXT, Msg, TEXT_LEN_SIZE, TTL_SIZE,
+        TryFromArrayToMsgError, TryFromStringToMsgError, UUID_SIZE, max_text,
+    };
+
+    #[test]
+    fn display_is_the_nick_and_single_line_text() {
+        let msg = Msg::new("two\nlines, one \\").unwrap();
+        assert_eq!(msg.to_string(), "two\\nlines, one \\\\");
+        assert_eq!(
+            msg.with_nick("alice").unwrap().to_string(),
+            "alice: two\\nlines, one \\\\"
+        );
+    }
+
+    #[test]
+    fn display_escapes_control_characters() {
+        let msg = Msg::new("\x1b[2Jspoofed\rbob: hi\x08\x08")
+            .unwrap()
+            .with_nick("\x1b[31malice")
+            .unwrap();
+
+        let shown = msg.to_string();
+
+        assert_eq!(
+            shown,
+            "\\u{1b}[31malice: \\u{1b}[2Jspoofed\\rbob: hi\\u{8}\\u{8}"
+        );
+        assert!(!shown.chars().any(char::is_control));
+    }
+
+    #[test]
+    fn identical_fields_serialize_identically() {
+        let uuid = Uuid::new_v4();
+        let build = || {
+            Msg::from_parts("same", uuid, None)
+                .unwrap()
+                .with_ttl(3)
+                .with_nick("alice")
+                .unwrap()
+                .with_expiry(1_000)
+                .unwrap()
+        };
+
+        assert_eq!(build().into_bytes(), build().into_bytes());
+        assert!(Msg::new("never").unwrap().with_expiry(0).is_err());
+    }
 
     #[test]
     fn from_test_string_doesnt_panic() {
         let _: Msg = String::from("test").try_into().unwrap();
     }
 
+    #[test]
+    fn from_str_within_capacity() {
+        let text = "a".repeat(CAPACITY - TEXT_LEN_SIZE - UUID_SIZE - TTL_SIZE);
+        let msg = Msg::try_from(text.as_str()).unwrap();
+
+        assert_eq!(msg.text, text)
+    }
+
+    #[test]
+    fn from_str_over_capacity_fails() {
+        let text = "a".repeat(CAPACITY - TEXT_LEN_SIZE - UUID_SIZE - TTL_SIZE + 1);
+
+        assert!(Msg::try_from(text.as_str()).is_err());
+        assert!(Msg::new(text).is_err())
+    }
+
+    #[test]
+    fn other_capacity_round_trips_and_bounds_the_text() {
+        let capacity = 2048;
+        let text = "a".repeat(max_text(capacity));
+        let msg = Msg::new_in(text.as_str(), capacity).unwrap();
+
+        let bytes = msg.clone().into_bytes();
+        assert_eq!(bytes.len(), capacity);
+        assert_eq!(Msg::try_from(bytes).unwrap(), msg);
+        assert!(Msg::new_in(format!("{text}a"), capacity).is_err());
+        assert!(msg.with_capacity(CAPACITY).is_err());
+    }
+
     #[test]
     fn to_from_bytes_involution() {
         let msg: Msg = String::from(
@@ -120,4 +731,349 @@ mod test {
 
         assert_eq!(msg, msg_prime)
     }
+
+    #[test]
+    fn max_text_is_pinned_to_capacity() {
+        assert_eq!(MAX_TEXT, 493);
+        let msg = Msg::new("a".repeat(MAX_TEXT)).unwrap();
+
+        assert!(msg.fits());
+        assert_eq!(msg.encoded_len(), CAPACITY);
+        assert!(Msg::new("a".repeat(MAX_TEXT + 1)).is_err());
+        assert!(msg.with_nick("bob").is_err());
+    }
+
+    #[test]
+    fn multi_byte_char_at_capacity_edge() {
+        let max_text = CAPACITY - TEXT_LEN_SIZE - UUID_SIZE - TTL_SIZE;
+        let text = format!("{}é", "a".repeat(max_text - 'é'.len_utf8()));
+        let msg = Msg::new(text).unwrap();
+
+        let msg_prime: Msg = msg.clone().into_bytes().try_into().unwrap();
+
+        assert_eq!(msg, msg_prime);
+        assert!(Msg::new(format!("{}é", "a".repeat(max_text - 1))).is_err());
+    }
+
+    #[test]
+    fn newlines_survive_round_trip() {
+        let msg = Msg::new("first line\nsecond line\n").unwrap();
+        let msg_prime: Msg = msg.clone().into_bytes().try_into().unwrap();
+
+        assert_eq!(msg_prime.text, "first line\nsecond line\n");
+        assert_eq!(msg, msg_prime);
+    }
+
+    #[test]
+    fn layout_matches_bytes() {
+        let msg = Msg::from_parts("layout", Uuid::new_v4(), None).unwrap();
+        let layout = msg.layout();
+        let bytes = msg.clone().into_bytes();
+
+        let names: Vec<_> = layout.iter().map(|field| field.name).collect();
+        assert_eq!(names, ["text length", "text", "uuid", "ttl", "padding"]);
+        assert_eq!(&bytes[layout[0].range.clone()], [0, 6]);
+        assert_eq!(&bytes[layout[1].range.clone()], b"layout");
+        assert_eq!(&bytes[layout[2].range.clone()], msg.uuid().as_bytes());
+        assert_eq!(&bytes[layout[3].range.clone()], [DEFAULT_TTL]);
+        assert!(bytes[layout[4].range.clone()].iter().all(|b| *b == 0));
+        assert_eq!(layout[4].range.end, CAPACITY);
+    }
+
+    #[test]
+    fn from_parts_preserves_uuid_and_creation_time() {
+        let uuid = Uuid::new_v4();
+        let msg = Msg::from_parts("persisted", uuid, Some(1_000)).unwrap();
+        let msg_prime: Msg = msg.clone().into_bytes().try_into().unwrap();
+
+        assert_eq!(msg_prime, msg);
+        assert_eq!(msg_prime.uuid(), uuid);
+        assert_eq!(msg_prime.created_at(), Some(1_000));
+        assert_eq!(msg.text, "persisted");
+        assert_eq!(
+            Msg::from_parts("unstamped", uuid, None)
+                .unwrap()
+                .created_at(),
+            None
+        );
+        assert!(Msg::from_parts("a".repeat(CAPACITY), uuid, None).is_err());
+    }
+
+    #[test]
+    fn nick_survives_round_trip() {
+        let plain = Msg::new("no nick").unwrap();
+        let nicked = Msg::new("with nick").unwrap().with_nick("alice").unwrap();
+
+        let plain_prime: Msg = plain.clone().into_bytes().try_into().unwrap();
+        let nicked_prime: Msg = nicked.clone().into_bytes().try_into().unwrap();
+
+        assert_eq!(plain_prime, plain);
+        assert_eq!(plain_prime.nick(), None);
+        assert_eq!(nicked_prime, nicked);
+        assert_eq!(nicked_prime.nick(), Some("alice"));
+    }
+
+    #[test]
+    fn nick_has_its_own_budget() {
+        let msg = Msg::new("hi").unwrap();
+        assert!(msg.clone().with_nick("a".repeat(MAX_NICK)).is_ok());
+        assert!(msg.with_nick("a".repeat(MAX_NICK + 1)).is_err());
+
+        let full = Msg::new("a".repeat(CAPACITY - TEXT_LEN_SIZE - UUID_SIZE - TTL_SIZE)).unwrap();
+        assert!(full.with_nick("bob").is_err());
+    }
+
+    #[test]
+    fn creation_time_survives_round_trip() {
+        let msg = Msg::new("stamped").unwrap();
+        let nicked = msg.clone().with_nick("dave").unwrap();
+        assert!(msg.created_at().is_some_and(|at| at > 0));
+
+        let msg_prime: Msg = msg.clone().into_bytes().try_into().unwrap();
+        let nicked_prime: Msg = nicked.clone().into_bytes().try_into().unwrap();
+
+        assert_eq!(msg_prime.created_at(), msg.created_at());
+        assert_eq!(msg_prime.expires_at, None);
+        assert_eq!(nicked_prime, nicked);
+
+        let restamped: Msg = msg
+            .with_created_at(1_000)
+            .unwrap()
+            .into_bytes()
+            .try_into()
+            .unwrap();
+        assert_eq!(restamped.created_at(), Some(1_000));
+    }
+
+    #[test]
+    fn creation_time_is_left_out_when_there_is_no_room() {
+        let full = Msg::new("a".repeat(CAPACITY - TEXT_LEN_SIZE - UUID_SIZE - TTL_SIZE)).unwrap();
+        assert_eq!(full.created_at(), None);
+
+        let stamped = Msg::new("a".repeat(450)).unwrap();
+        assert!(stamped.created_at().is_some());
+        let nicked = stamped.with_nick("a".repeat(MAX_NICK)).unwrap();
+        assert_eq!(nicked.created_at(), None);
+    }
+
+    #[test]
+    fn expiry_survives_round_trip() {
+        let msg = Msg::new("soon gone").unwrap().with_expiry(1_000).unwrap();
+        let nicked = msg.clone().with_nick("carol").unwrap();
+
+        let msg_prime: Msg = msg.clone().into_bytes().try_into().unwrap();
+        let nicked_prime: Msg = nicked.clone().into_bytes().try_into().unwrap();
+
+        assert_eq!(msg_prime, msg);
+        assert_eq!(nicked_prime, nicked);
+        assert!(!msg_prime.is_expired(1_000));
+        assert!(msg_prime.is_expired(1_001));
+        assert!(!Msg::new("forever").unwrap().is_expired(u64::MAX));
+    }
+
+    #[test]
+    fn new_messages_get_distinct_v4_uuids() {
+        let uuids: HashSet<Uuid> = (0..1000)
+            .map(|_| Msg::new("hi").unwrap().uuid())
+            .inspect(|uuid| assert_eq!(uuid.get_version_num(), 4))
+            .collect();
+
+        assert_eq!(uuids.len(), 1000);
+    }
+
+    #[test]
+    fn uuid_survives_round_trip() {
+        let msg = Msg::try_from("round trip").unwrap();
+        let uuid = msg.uuid();
+
+        let msg_prime = Msg::try_from(msg.into_bytes()).unwrap();
+
+        assert_eq!(msg_prime.uuid(), uuid);
+    }
+
+    #[test]
+    fn text_with_nul_survives_round_trip() {
+        let msg = Msg::new("a\0b\0").unwrap();
+        let msg_prime = Msg::try_from(msg.clone().into_bytes()).unwrap();
+
+        assert_eq!(msg_prime.text, "a\0b\0");
+        assert_eq!(msg_prime, msg);
+    }
+
+    #[test]
+    fn empty_or_blank_text_is_rejected() {
+        for text in ["", " ", "\t\n "] {
+            assert_eq!(
+                Msg::new(text).unwrap_err(),
+                TryFromStringToMsgError::EmptyText
+            );
+            assert_eq!(
+                Msg::from_parts(text, Uuid::new_v4(), None).unwrap_err(),
+                TryFromStringToMsgError::EmptyText
+            );
+        }
+        assert_eq!(Msg::new("a").unwrap().text, "a");
+    }
+
+    #[test]
+    fn text_at_capacity_survives_round_trip() {
+        let msg = Msg::new("\0".repeat(CAPACITY - TEXT_LEN_SIZE - UUID_SIZE - TTL_SIZE)).unwrap();
+        let msg_prime = Msg::try_from(msg.clone().into_bytes()).unwrap();
+
+        assert_eq!(msg_prime, msg);
+    }
+
+    #[test]
+    fn length_past_frame_is_truncated_text() {
+        let mut frame = [0; CAPACITY];
+        frame[..TEXT_LEN_SIZE].copy_from_slice(&(CAPACITY as u16).to_be_bytes());
+
+        assert!(matches!(
+            Msg::try_from(frame),
+            Err(TryFromArrayToMsgError::TruncatedText)
+        ));
+    }
+
+    #[test]
+    fn short_id_is_the_start_of_the_uuid() {
+        let uuid = Uuid::parse_str("3f2a9c1e-5b7d-4e8f-a0b1-c2d3e4f5a6b7").unwrap();
+        let msg = Msg::from_parts("short", uuid, None).unwrap();
+
+        assert_eq!(msg.short_id(), "3f2a9c1e");
+    }
+
+    #[test]
+    fn nil_uuid_is_rejected() {
+        let msg = Msg::from_parts("anonymous", Uuid::nil(), None).unwrap();
+
+        assert!(matches!(
+            Msg::try_from(msg.into_bytes()),
+            Err(TryFromArrayToMsgError::NilUuid)
+        ));
+    }
+
+    #[test]
+    fn invalid_utf8_is_rejected() {
+        let mut frame = Msg::new("ok").unwrap().into_bytes();
+        frame[TEXT_LEN_SIZE] = 0xff;
+
+        assert!(matches!(
+            Msg::try_from(frame),
+            Err(TryFromArrayToMsgError::InvalidUtf8(_))
+        ));
+    }
+
+    #[test]
+    fn overlong_nick_is_rejected() {
+        let msg = Msg::new("named").unwrap();
+        let nick_len_at = TEXT_LEN_SIZE + msg.text().len() + UUID_SIZE + TTL_SIZE;
+        let mut frame = msg.with_nick("a").unwrap().into_bytes();
+        frame[nick_len_at] = (MAX_NICK + 1) as u8;
+
+        assert!(matches!(
+            Msg::try_from(frame),
+            Err(TryFromArrayToMsgError::NickTooLong(len)) if len == MAX_NICK + 1
+        ));
+    }
+
+    #[test]
+    fn ttl_survives_round_trip_and_decrements_per_hop() {
+        let msg = Msg::new("hops").unwrap().with_ttl(2);
+        let msg_prime = Msg::try_from(msg.into_bytes()).unwrap();
+        assert_eq!(msg_prime.ttl(), 2);
+
+        let once = msg_prime.hop().unwrap();
+        assert_eq!(once.ttl(), 1);
+        let twice = once.hop().unwrap();
+        assert_eq!(twice.ttl(), 0);
+        assert!(twice.hop().is_none());
+    }
+
+    #[test]
+    fn messages_with_the_same_uuid_are_the_same_message() {
+        let uuid = Uuid::new_v4();
+        let first = Msg::from_parts("first", uuid, None).unwrap();
+        let edited = Msg::from_parts("edited", uuid, None).unwrap();
+
+        assert_eq!(first, edited);
+        assert_eq!(HashSet::from([first, edited]).len(), 1);
+        assert_ne!(Msg::new("same").unwrap(), Msg::new("same").unwrap());
+    }
+
+    #[test]
+    fn malformed_and_edge_case_frames() {
+        let uuid = Uuid::new_v4();
+        let frame = |text_len: u16, text: &[u8], uuid: Option<Uuid>| {
+            let mut frame = [0; CAPACITY];
+            frame[..TEXT_LEN_SIZE].copy_from_slice(&text_len.to_be_bytes());
+            frame[TEXT_LEN_SIZE..TEXT_LEN_SIZE + text.len()].copy_from_slice(text);
+            if let Some(uuid) = uuid {
+                let at = TEXT_LEN_SIZE + text.len();
+                frame[at..at + UUID_SIZE].copy_from_slice(uuid.as_bytes());
+            }
+            frame
+        };
+        let len = |len: usize| u16::try_from(len).unwrap();
+        let max_text = "a".repeat(max_text(CAPACITY));
+        let err = |err: TryFromArrayToMsgError| Err(mem::discriminant(&err));
+        let cases = [
+            (
+                "all zero",
+                [0; CAPACITY],
+                err(TryFromArrayToMsgError::NilUuid),
+            ),
+            (
+                "length of the whole frame",
+                frame(u16::MAX, b"", None),
+                err(TryFromArrayToMsgError::TruncatedText),
+            ),
+            (
+                "text up to the last byte",
+                frame(len(CAPACITY - TEXT_LEN_SIZE), b"", None),
+                err(TryFromArrayToMsgError::TruncatedUuid),
+            ),
+            (
+                "truncated uuid",
+                frame(len(CAPACITY - TEXT_LEN_SIZE - UUID_SIZE / 2), b"", None),
+                err(TryFromArrayToMsgError::TruncatedUuid),
+            ),
+            (
+                "no room for the ttl",
+                frame(
+                    len(CAPACITY - TEXT_LEN_SIZE - UUID_SIZE),
+                    &[b'a'; CAPACITY - TEXT_LEN_SIZE - UUID_SIZE],
+                    Some(uuid),
+                ),
+                err(TryFromArrayToMsgError::TruncatedTtl),
+            ),
+            (
+                "invalid utf-8",
+                frame(2, &[0xc3, 0x28], Some(uuid)),
+                err(TryFromArrayToMsgError::InvalidUtf8(
+                    String::from_utf8(vec![0xc3, 0x28]).unwrap_err(),
+                )),
+            ),
+            ("empty text", frame(0, b"", Some(uuid)), Ok(String::new())),
+            (
+                "max-length text",
+                Msg::from_parts(max_text.as_str(), uuid, None)
+                    .unwrap()
+                    .into_bytes()
+                    .try_into()
+                    .unwrap(),
+                Ok(max_text.clone()),
+            ),
+        ];
+
+        for (name, frame, expected) in cases {
+            let decoded = Msg::try_from(frame)
+                .map(|msg| msg.text)
+                .map_err(|err| mem::discriminant(&err));
+            assert_eq!(decoded, expected, "{name}");
+        }
+        assert!(matches!(
+            Msg::try_from(&[][..]),
+            Err(TryFromArrayToMsgError::TruncatedText)
+        ));
+    }
 }
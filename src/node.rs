@@ -0,0 +1,2723 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    mem,
+    net::{self, SocketAddr, TcpListener},
+    num,
+    os::fd::RawFd,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, TryRecvError},
+    time::{Duration, Instant},
+};
+
+use uuid::Uuid;
+
+use crate::{
+    Command, Dialer, Input, Origin, RawFrame, accept,
+    audit::{AuditLog, Direction, Outcome},
+    blocklist::Blocklist,
+    broadcast,
+    clock::{self, Clock},
+    coalesce::Coalesce,
+    confirm::Confirm,
+    connect, decode_frame, dial_target, drop_blocked, drop_duplicates, drop_self, explain, export,
+    fanout::Fanout,
+    faults::Faults,
+    flush_all, fragment, handshake, hex, listen,
+    msg::{self, Msg},
+    peer::{Framing, Health, Peer},
+    process_frame,
+    queue::{self, DedupQueue, Eviction, Queue},
+    ratelimit::RateLimit,
+    read_frames, receive_msgs, record_audit, record_seen,
+    redial::Redials,
+    relay,
+    resolve::{self, Resolve},
+    rng::Rng,
+    seenlog::SeenLog,
+    send_to,
+    sink::{
+        self, DisconnectReason, Echoing, Event, Filtered, MessageSink, Metered, Notifying,
+        PeerEvent, Subscriber, Throttled,
+    },
+    snapshot::Snapshot,
+    state,
+    stats::{Delivery, DropReason, Drops},
+    throughput::Throughput,
+};
+
+/// Options given on the command line after the listening address.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Tag each displayed message with a local receive-sequence number.
+    pub show_seq: bool,
+    /// Tag each displayed message with how long ago it was created.
+    pub show_age: bool,
+    /// File the peer blocklist is loaded from and saved to.
+    pub blocklist: Option<PathBuf>,
+    /// SOCKS5 proxy through which outgoing connections are dialed.
+    pub proxy: Option<SocketAddr>,
+    /// Maximum number of simultaneous inbound connections from one IP.
+    pub max_per_ip: Option<usize>,
+    /// Maximum number of connected peers, inbound and outbound together.
+    pub max_peers: Option<usize>,
+    /// Network interface whose address the listener binds to.
+    pub interface: Option<String>,
+    /// File in which seen message UUIDs are persisted across restarts.
+    pub seen_log: Option<PathBuf>,
+    /// File the seen messages are saved to on leaving and loaded from at
+    /// startup.
+    pub state_file: Option<PathBuf>,
+    /// Never propagate a message to any peer that delivered it.
+    pub split_horizon: bool,
+    /// Allow the `faults` command to inject outbound faults.
+    pub test_faults: bool,
+    /// Write to peers in a new random order for every message.
+    pub shuffle: bool,
+    /// Offer peers length-prefixed frames rather than padded ones.
+    pub length_prefixed: bool,
+    /// Nickname attached to every message broadcast from this node.
+    pub nick: Option<String>,
+    /// Report a hex dump of every frame read and written.
+    pub hexdump: bool,
+    /// Report the resolved configuration on startup.
+    pub verbose: bool,
+    /// Unix socket on which commands are accepted besides stdin.
+    pub control: Option<PathBuf>,
+    /// Run in the background, taking commands only over `control`, which
+    /// replies to them.
+    pub detach: bool,
+    /// Which seen message is forgotten first once the seen queue is full.
+    pub seen_eviction: Eviction,
+    /// File every connection attempt is logged to.
+    pub audit_log: Option<PathBuf>,
+    /// When a broadcast typed at the terminal waits for `confirm`.
+    pub confirm: Confirm,
+    /// Send every broadcast right away, regardless of `confirm`.
+    pub yes: bool,
+    /// Already listening socket to accept peers on instead of binding one,
+    /// adopted once at startup, see [`Node::with_listener`].
+    pub listen_fd: Option<RawFd>,
+    /// How long outbound frames are held back to be written together.
+    pub coalesce: Option<Duration>,
+    /// Number of bytes held back for a peer at which they are written
+    /// together without waiting for `coalesce` to pass, if coalescing.
+    pub coalesce_bytes: Option<usize>,
+    /// Number of hops messages broadcast from this node may be forwarded,
+    /// or `DEFAULT_TTL` if unset.
+    pub hops: Option<u8>,
+    /// Maximum number of characters in the text of messages broadcast from
+    /// this node, on top of the limit in bytes every message is held to.
+    pub max_chars: Option<usize>,
+    /// Text a received message must contain to be shown.
+    pub filter: Option<String>,
+    /// Peers dialed on startup.
+    pub bootstrap: Vec<SocketAddr>,
+    /// Number of seen messages kept in memory, or `SEEN_CAPACITY` if unset.
+    pub seen_capacity: Option<usize>,
+    /// Reply to every new message by sending its text back to the sender.
+    pub echo_server: bool,
+    /// Least important events printed.
+    pub log_level: sink::Level,
+    /// Size of every frame exchanged with peers, or `msg::CAPACITY` if unset.
+    /// Every node of a network must use the same.
+    pub capacity: Option<usize>,
+    /// Number of connected peers at which the node reports it is ready,
+    /// see [`Event::Ready`].
+    pub ready_at: Option<usize>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("missing value for `{0}`")]
+    MissingValue(String),
+    #[error("unknown flag `{0}`")]
+    UnknownFlag(String),
+    #[error(transparent)]
+    AddrParseError(#[from] net::AddrParseError),
+    #[error(transparent)]
+    ParseIntError(#[from] num::ParseIntError),
+    #[error(transparent)]
+    ParseEvictionError(#[from] queue::ParseEvictionError),
+    #[error(transparent)]
+    ParseLevelError(#[from] sink::ParseLevelError),
+    #[error(
+        "capacity of {0} bytes out of range, expected {min} to {max}",
+        min = msg::MIN_CAPACITY,
+        max = msg::MAX_CAPACITY
+    )]
+    CapacityOutOfRange(usize),
+}
+
+impl Config {
+    /// Returns the size of every frame exchanged with peers.
+    pub fn capacity(&self) -> usize {
+        self.capacity.unwrap_or(msg::CAPACITY)
+    }
+
+    /// Returns the framing offered to every peer.
+    pub fn framing(&self) -> Framing {
+        if self.length_prefixed {
+            Framing::LengthPrefixed
+        } else {
+            Framing::Fixed
+        }
+    }
+
+    /// Parses the options given on the command line after the listening
+    /// address.
+    pub fn from_args(args: &[String]) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| ConfigError::MissingValue(arg.clone()))
+            };
+
+            match arg.as_str() {
+                "--show-seq" => config.show_seq = true,
+                "--show-age" => config.show_age = true,
+                "--split-horizon" => config.split_horizon = true,
+                "--test-faults" => config.test_faults = true,
+                "--shuffle" => config.shuffle = true,
+                "--length-prefixed" => config.length_prefixed = true,
+                "--hexdump" => config.hexdump = true,
+                "--verbose" => config.verbose = true,
+                "--detach" => config.detach = true,
+                "--yes" => config.yes = true,
+                "--echo-server" => config.echo_server = true,
+                "--blocklist" => config.blocklist = Some(PathBuf::from(value()?)),
+                "--proxy" => config.proxy = Some(value()?.parse()?),
+                "--max-per-ip" => config.max_per_ip = Some(value()?.parse()?),
+                "--max-peers" => config.max_peers = Some(value()?.parse()?),
+                "--interface" => config.interface = Some(value()?.clone()),
+                "--seen-log" => config.seen_log = Some(PathBuf::from(value()?)),
+                "--state-file" => config.state_file = Some(PathBuf::from(value()?)),
+                "--control" => config.control = Some(PathBuf::from(value()?)),
+                "--seen-eviction" => config.seen_eviction = value()?.parse()?,
+                "--audit-log" => config.audit_log = Some(PathBuf::from(value()?)),
+                "--nick" => config.nick = Some(value()?.clone()),
+                "--filter" => config.filter = Some(value()?.clone()),
+                "--connect" => config.bootstrap.push(value()?.parse()?),
+                "--seen-capacity" => config.seen_capacity = Some(value()?.parse()?),
+                "--confirm-peers" => config.confirm.peers = Some(value()?.parse()?),
+                "--confirm-bytes" => config.confirm.bytes = Some(value()?.parse()?),
+                "--listen-fd" => config.listen_fd = Some(value()?.parse()?),
+                "--hops" => config.hops = Some(value()?.parse()?),
+                "--max-chars" => config.max_chars = Some(value()?.parse()?),
+                "--ready-at" => config.ready_at = Some(value()?.parse()?),
+                "--log-level" => config.log_level = value()?.parse()?,
+                "--capacity" => {
+                    let capacity: usize = value()?.parse()?;
+                    if !(msg::MIN_CAPACITY..=msg::MAX_CAPACITY).contains(&capacity) {
+                        return Err(ConfigError::CapacityOutOfRange(capacity));
+                    }
+                    config.capacity = Some(capacity);
+                }
+                "--coalesce" => {
+                    config.coalesce = Some(Duration::from_millis(value()?.parse()?));
+                }
+                "--coalesce-bytes" => config.coalesce_bytes = Some(value()?.parse()?),
+                // Taken by the subcommand, see `cli::Mode::from_args`.
+                "--listen" | "--count" => {
+                    value()?;
+                }
+                flag if flag.starts_with("--") => {
+                    return Err(ConfigError::UnknownFlag(flag.to_string()));
+                }
+                // Positional arguments of the subcommand.
+                _ => (),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// Number of frames held back while paused before the oldest are dropped.
+const PAUSE_BUFFER: usize = 256;
+
+/// How long a draining node keeps relaying in-flight messages before leaving.
+const DRAIN_GRACE: Duration = Duration::from_secs(1);
+
+/// How long repeats of an identical error are suppressed for.
+const ERROR_WINDOW: Duration = Duration::from_secs(5);
+
+/// How often a live peer is expected to be heard from, and how often peers
+/// are sent a heartbeat so they hear from us.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Number of seen messages kept in memory unless configured otherwise.
+const SEEN_CAPACITY: usize = 16;
+
+/// Number of most recent UUIDs the persistent seen log remembers.
+const SEEN_LOG_BOUND: usize = 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BroadcastError {
+    #[error("message too long")]
+    TryFromStringToMsgError(#[from] msg::TryFromStringToMsgError),
+    #[error("draining, not sending new messages")]
+    Draining,
+    #[error("message has {chars} characters ({bytes} bytes) where at most {max} are allowed")]
+    TooManyChars {
+        chars: usize,
+        bytes: usize,
+        max: usize,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+enum IdError {
+    #[error("no seen message {0}")]
+    Unknown(String),
+    #[error("id {id} is ambiguous, {count} seen messages start with it")]
+    Ambiguous { id: String, count: usize },
+}
+
+/// Returns the seen message whose UUID starts with the hex digits `id`,
+/// failing if there is none or more than one.
+fn find_seen<'a>(seen: &'a DedupQueue<Msg>, id: &str) -> Result<&'a Msg, IdError> {
+    let mut matches = seen
+        .iter()
+        .filter(|msg| msg.uuid().simple().to_string().starts_with(id));
+    match (matches.next(), matches.count()) {
+        (None, _) => Err(IdError::Unknown(id.to_string())),
+        (Some(msg), 0) => Ok(msg),
+        (Some(_), others) => Err(IdError::Ambiguous {
+            id: id.to_string(),
+            count: others + 1,
+        }),
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+enum ReplayError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Broadcast(#[from] BroadcastError),
+}
+
+/// A running p2p peer: its listener, connected peers and message state.
+///
+/// Besides being run from the command line, a node can be embedded: bind it,
+/// connect it to peers and keep calling [`Node::poll`] for it to do its work.
+pub struct Node<S: MessageSink> {
+    config: Config,
+    local_addr: SocketAddr,
+    /// Sent in our hellos, so links back to ourselves can be told apart.
+    nonce: u64,
+    /// Peers accepted by the listener; `None` once it can't be listened on.
+    incoming: Option<mpsc::Receiver<io::Result<Peer>>>,
+    peers: Vec<Peer>,
+    seen: DedupQueue<Msg>,
+    seen_log: Option<SeenLog>,
+    blocklist: Blocklist,
+    audit: Option<AuditLog>,
+    fanout: Fanout,
+    /// Frames read while paused, processed on resume; `None` unless paused.
+    held: Option<Queue<(RawFrame, SocketAddr)>>,
+    /// Suppresses repeated errors and hex dumps while reading from and
+    /// writing to peers.
+    errors: RateLimit,
+    /// When a draining node leaves the mesh; `None` unless draining.
+    leave_at: Option<Instant>,
+    /// Frames dropped on the way in; outbound losses are counted by `fanout`.
+    drops: Drops,
+    /// Where the command being handled came from, `Stdin` between commands.
+    /// Notices are replied over a control connection too.
+    origin: Origin,
+    /// A broadcast waiting for `confirm`, with its ttl.
+    pending: Option<(String, Option<Duration>)>,
+    /// Rate and count of new messages received from peers.
+    received: Throughput,
+    /// Rate and count of messages broadcast by this node.
+    broadcasts: Throughput,
+    /// Number of messages sent by this node, broadcast or not.
+    originated: u64,
+    /// Number of peers dropped since the node started.
+    pruned: u64,
+    /// When peers are next sent a heartbeat.
+    next_heartbeat: Instant,
+    /// When the node started.
+    started: Instant,
+    clock: Box<dyn Clock>,
+    resolver: Box<dyn Resolve>,
+    /// Callbacks for peer events, see [`Node::on_peer_event`].
+    subscribers: Vec<Subscriber>,
+    /// Whether `ready_at` peers were connected at some point.
+    ready: bool,
+    /// Peers dialed again once they drop, see [`Node::redial`].
+    redials: Redials,
+    /// Replayed messages waiting to be broadcast, with when they are due.
+    replays: VecDeque<(Instant, Msg)>,
+    sink: S,
+}
+
+impl<S: MessageSink> Node<S> {
+    /// Binds a listener on `ip` and loads any persisted state named in `config`.
+    pub fn bind(ip: SocketAddr, config: Config, sink: S) -> io::Result<Self> {
+        Self::with_listener(TcpListener::bind(ip)?, config, sink)
+    }
+
+    /// Accepts peers on `listener`, which is already listening, e.g. as
+    /// adopted from socket activation, and loads any persisted state named
+    /// in `config`.
+    pub fn with_listener(listener: TcpListener, config: Config, mut sink: S) -> io::Result<Self> {
+        let blocklist = match &config.blocklist {
+            Some(path) => Blocklist::load(path)?,
+            None => Blocklist::default(),
+        };
+        let seen_log = match &config.seen_log {
+            Some(path) => Some(SeenLog::open(path, SEEN_LOG_BOUND)?),
+            None => None,
+        };
+        let audit = match &config.audit_log {
+            Some(path) => Some(AuditLog::open(path)?),
+            None => None,
+        };
+
+        let nonce = Rng::from_entropy().next_u64();
+        let (local_addr, incoming) = listen(listener, nonce, config.capacity(), config.framing())?;
+        let mut seen = DedupQueue::with_eviction(
+            config.seen_capacity.unwrap_or(SEEN_CAPACITY),
+            config.seen_eviction,
+        );
+        let clock = clock::System;
+        let fanout = Fanout {
+            split_horizon: config.split_horizon,
+            shuffle: config.shuffle.then(Rng::from_entropy),
+            faults: Faults::default(),
+            hexdump: config.hexdump,
+            coalesce: config.coalesce.map(|window| {
+                let coalesce = Coalesce::new(window);
+                match config.coalesce_bytes {
+                    Some(threshold) => coalesce.with_threshold(threshold),
+                    None => coalesce,
+                }
+            }),
+            capacity: config.capacity,
+            deliveries: Delivery::default(),
+        };
+        // A broken state file is no reason not to start, only to start over.
+        if let Some(path) = &config.state_file {
+            match state::load(path) {
+                Ok(messages) => messages.into_iter().for_each(|msg| {
+                    seen.push(msg);
+                }),
+                Err(err) => sink.on_event(&Event::Notice(format!(
+                    "ignoring state file {}: {err}, starting with no seen messages",
+                    path.display()
+                ))),
+            }
+        }
+
+        Ok(Self {
+            config,
+            local_addr,
+            nonce,
+            incoming: Some(incoming),
+            peers: Vec::new(),
+            seen,
+            seen_log,
+            blocklist,
+            audit,
+            fanout,
+            held: None,
+            errors: RateLimit::new(ERROR_WINDOW),
+            leave_at: None,
+            drops: Drops::default(),
+            origin: Origin::Stdin,
+            pending: None,
+            received: Throughput::new(clock.now_instant()),
+            broadcasts: Throughput::new(clock.now_instant()),
+            originated: 0,
+            pruned: 0,
+            next_heartbeat: clock.now_instant() + HEARTBEAT_INTERVAL,
+            started: clock.now_instant(),
+            clock: Box::new(clock),
+            resolver: Box::new(resolve::System),
+            subscribers: Vec::new(),
+            ready: false,
+            redials: Redials::default(),
+            replays: VecDeque::new(),
+            sink,
+        })
+    }
+
+    /// Returns the address the listener is bound to.
+    ///
+    /// Unlike the address given to [`Node::bind`], this holds the actual
+    /// port when binding to port `0`.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Returns the sink the node reports to.
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// Dials the peers that dropped again, once their backoff has passed.
+    ///
+    /// Every peer whose hello arrived is dialed again on the address it
+    /// advertised, or by its host name if it was dialed by name, until it
+    /// is connected again. Peers disconnected on purpose, blocked ones and
+    /// every peer once draining are left alone.
+    fn redial(&mut self) {
+        for peer in &self.peers {
+            if let Some(listening) = peer
+                .advertised()
+                .filter(|_| peer.nonce() != Some(self.nonce))
+            {
+                self.redials
+                    .connected(listening, peer.host().map(String::from));
+            }
+        }
+        if self.leave_at.is_some() {
+            return;
+        }
+
+        let connected: Vec<_> = self.peers.iter().map(Peer::identity).collect();
+        for (addr, host) in self.redials.due(&connected, self.clock.now_instant()) {
+            if self.blocklist.contains(addr.ip()) {
+                self.redials.forget(addr);
+                continue;
+            }
+            let addrs = match &host {
+                Some(host) => self.resolver.resolve(host),
+                None => Ok(vec![addr]),
+            };
+            let notice = match addrs.and_then(|addrs| self.dial(addrs, host)) {
+                Ok(()) => format!("reconnected to {addr}"),
+                Err(err) => format!("failed to reconnect to {addr}: {err}"),
+            };
+            self.notice(notice);
+        }
+    }
+
+    /// Returns the number of peers currently connected.
+    pub fn connected_count(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Returns whether `ready_at` peers were connected at some point, see
+    /// [`Node::wait_ready`].
+    pub fn is_ready(&self) -> bool {
+        self.ready
+    }
+
+    /// Keeps the node running until `ready_at` peers are connected, or for
+    /// at most `timeout`, and returns whether they are.
+    ///
+    /// Once ready, the node stays so even as peers drop. Without `ready_at`,
+    /// it is never ready.
+    pub fn wait_ready(&mut self, timeout: Duration) -> bool {
+        let deadline = self.clock.now_instant() + timeout;
+        while !self.ready && self.clock.now_instant() < deadline {
+            self.poll();
+        }
+        self.ready
+    }
+
+    /// Reports [`Event::Ready`] the first time as many peers as configured
+    /// with `ready_at` are connected.
+    fn check_ready(&mut self) {
+        let peers = self.connected_count();
+        if !self.ready && self.config.ready_at.is_some_and(|target| peers >= target) {
+            self.ready = true;
+            self.sink.on_event(&Event::Ready { peers });
+        }
+    }
+
+    /// Calls `subscriber` with every peer event from now on, along with any
+    /// other subscribers, e.g. so an app can keep its list of peers current.
+    pub fn on_peer_event(&mut self, subscriber: impl FnMut(&PeerEvent) + 'static) {
+        self.subscribers.push(Box::new(subscriber));
+    }
+
+    /// Reports that the node is up, including its configuration when verbose.
+    pub fn announce(&mut self) {
+        let config = self.config.verbose.then(|| format!("{:?}", self.config));
+        self.sink.on_event(&Event::Listening {
+            addr: self.local_addr(),
+            config,
+        });
+    }
+
+    /// Dials every bootstrap peer, reporting each that can't be reached
+    /// rather than failing.
+    pub fn bootstrap(&mut self) {
+        for addr in self.config.bootstrap.clone() {
+            if let Err(err) = self.dial(vec![addr], None) {
+                self.notice(format!("failed to connect to bootstrap peer {addr}: {err}"));
+            }
+        }
+    }
+
+    /// Does one pass of the node's work besides commands: accepts incoming
+    /// peers, reads and relays their messages, writes what is due and
+    /// dials again the peers that dropped.
+    ///
+    /// An embedder keeps calling this for the node to make progress.
+    pub fn poll(&mut self) {
+        self.accept_incoming();
+        self.receive();
+        self.write_coalesced(false);
+        self.write_unsent();
+        self.heartbeat();
+        self.redial();
+        self.replay_due();
+    }
+
+    /// Adds a pending incoming connection to the peers, if there is one.
+    pub(crate) fn accept_incoming(&mut self) {
+        let Some(incoming) = &self.incoming else {
+            return;
+        };
+        match incoming.try_recv() {
+            Err(TryRecvError::Empty) => (),
+            Err(TryRecvError::Disconnected) => self.relisten(String::from("listener stopped")),
+            Ok(Err(err)) => self.relisten(format!("failed to accept: {err}")),
+            Ok(Ok(comm)) if self.leave_at.is_some() => {
+                let addr = comm.addr();
+                let reason = String::from("draining");
+                record_audit(
+                    &mut self.audit,
+                    Direction::Inbound,
+                    addr,
+                    Outcome::Rejected,
+                    Some(&reason),
+                    &mut self.sink,
+                );
+                self.sink.on_event(&Event::Rejected { addr, reason });
+                let _ = comm.shutdown();
+            }
+            Ok(Ok(comm)) => accept(
+                &mut self.peers,
+                comm,
+                &self.blocklist,
+                &self.config,
+                self.clock.now_instant(),
+                &mut self.audit,
+                &mut Notifying::new(&mut self.sink, &mut self.subscribers),
+            ),
+        }
+        self.check_ready();
+    }
+
+    /// Listens again on the same address after the listener stopped because
+    /// of `cause`, or stops accepting peers if that fails too.
+    fn relisten(&mut self, cause: String) {
+        let capacity = self.config.capacity();
+        let framing = self.config.framing();
+        let nonce = self.nonce;
+        let notice = match TcpListener::bind(self.local_addr)
+            .and_then(|listener| listen(listener, nonce, capacity, framing))
+        {
+            Ok((_, incoming)) => {
+                self.incoming = Some(incoming);
+                format!("{cause}, listening again on {}", self.local_addr)
+            }
+            Err(err) => {
+                self.incoming = None;
+                format!("{cause}, no longer accepting peers: {err}")
+            }
+        };
+        self.notice(notice);
+    }
+
+    /// Carries out the next command from `cmds`, if there is one.
+    ///
+    /// Once every input has closed, `cmds` is cleared and the node carries
+    /// on only receiving.
+    pub(crate) fn handle_next(
+        &mut self,
+        cmds: &mut Option<mpsc::Receiver<Input>>,
+    ) -> io::Result<()> {
+        let Some(rx) = cmds else {
+            return Ok(());
+        };
+        match rx.try_recv() {
+            Err(TryRecvError::Empty) => (),
+            Err(TryRecvError::Disconnected) => {
+                *cmds = None;
+                self.notice(String::from(
+                    "all inputs closed, only receiving from now on",
+                ));
+            }
+            Ok((command, origin)) => {
+                self.origin = origin;
+                let handled = match command {
+                    Ok(cmd) => self.handle(cmd),
+                    Err(err) => {
+                        self.notice(format!("input error: {err}, skipping command"));
+                        Ok(())
+                    }
+                };
+                self.origin = Origin::Stdin;
+                handled?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads messages from all peers and propagates the new ones.
+    ///
+    /// While paused, frames are only read and held back, so peers aren't
+    /// left blocking on a full connection.
+    pub(crate) fn receive(&mut self) {
+        let before = self.peers.len();
+        self.peers = drop_self(mem::take(&mut self.peers), self.nonce, &mut self.sink);
+        self.peers = drop_duplicates(mem::take(&mut self.peers), &mut self.sink);
+        let now = self.clock.now_instant();
+        let mut echoes = Vec::new();
+        let mut notifying = Notifying::new(&mut self.sink, &mut self.subscribers);
+        let mut filtered = Filtered::new(&mut notifying, self.config.filter.as_deref());
+        let mut sink = Echoing::new(
+            Metered::new(
+                Throttled::new(&mut filtered, &mut self.errors, now),
+                &mut self.received,
+                now,
+            ),
+            self.config.echo_server.then_some(&mut echoes),
+        );
+        let Some(held) = &mut self.held else {
+            self.peers = receive_msgs(
+                mem::take(&mut self.peers),
+                &mut self.seen,
+                &mut self.seen_log,
+                &mut self.drops,
+                &mut self.fanout,
+                self.clock.as_ref(),
+                &mut sink,
+            );
+            self.count_pruned(before);
+            self.echo(echoes);
+            return;
+        };
+
+        let (retained, frames): (Vec<_>, Vec<_>) = mem::take(&mut self.peers)
+            .into_iter()
+            .map(|peer| read_frames(peer, now, self.fanout.hexdump, &mut sink))
+            .unzip();
+        self.peers = retained.into_iter().flatten().collect();
+
+        for frame in frames.into_iter().flatten() {
+            if let Some((_, addr)) = held.push(frame) {
+                self.drops.record(DropReason::PauseOverflow);
+                sink.on_event(&Event::Notice(format!(
+                    "pause buffer full, dropped frame from {addr}"
+                )));
+            }
+        }
+        self.count_pruned(before);
+    }
+
+    /// Counts the peers dropped since there were `before` of them as pruned.
+    fn count_pruned(&mut self, before: usize) {
+        self.pruned += before.saturating_sub(self.peers.len()) as u64;
+    }
+
+    /// Processes and propagates the frames held back while paused.
+    fn resume(&mut self, held: Queue<(RawFrame, SocketAddr)>) {
+        let now = self.clock.now_instant();
+        let unix_now = self.clock.now_unix_millis() / 1000;
+        let mut echoes = Vec::new();
+        let mut notifying = Notifying::new(&mut self.sink, &mut self.subscribers);
+        let mut filtered = Filtered::new(&mut notifying, self.config.filter.as_deref());
+        let mut sink = Echoing::new(
+            Metered::new(
+                Throttled::new(&mut filtered, &mut self.errors, now),
+                &mut self.received,
+                now,
+            ),
+            self.config.echo_server.then_some(&mut echoes),
+        );
+        let frames = held
+            .into_iter()
+            .filter_map(|(frame, addr)| {
+                process_frame(
+                    frame,
+                    addr,
+                    &mut self.seen,
+                    &mut self.seen_log,
+                    &mut self.drops,
+                    unix_now,
+                    &mut sink,
+                )
+                .map(|frame| (frame, addr))
+            })
+            .collect();
+
+        let before = self.peers.len();
+        self.peers = relay(
+            mem::take(&mut self.peers),
+            frames,
+            &mut self.fanout,
+            now,
+            &mut sink,
+        );
+        self.count_pruned(before);
+        self.echo(echoes);
+    }
+
+    /// Whispers the text of every message in `echoes` back to the peer it
+    /// was received from, under a new UUID, see [`Node::whisper`].
+    fn echo(&mut self, echoes: Vec<(Msg, SocketAddr)>) {
+        for (msg, from) in echoes {
+            let Some(to) = self.peers.iter().find(|peer| peer.addr() == from) else {
+                continue;
+            };
+            let to = to.identity();
+            let id = msg.short_id();
+            let echoed = Msg::new_in(msg.into_text(), msg::MAX_CAPACITY)
+                .map_err(BroadcastError::from)
+                .and_then(|echo| self.whisper(to, echo));
+            if let Err(err) = echoed {
+                self.notice(format!("failed to echo {id}: {err}"));
+            }
+        }
+    }
+
+    /// Sends every peer a heartbeat once per [`HEARTBEAT_INTERVAL`], first
+    /// dropping the peers that are dead by then, see [`Health`].
+    ///
+    /// Peers that can't be written to are dropped as well.
+    fn heartbeat(&mut self) {
+        let now = self.clock.now_instant();
+        if now < self.next_heartbeat {
+            return;
+        }
+        self.next_heartbeat = now + HEARTBEAT_INTERVAL;
+
+        let mut sink = Notifying::new(&mut self.sink, &mut self.subscribers);
+        let frame = handshake::heartbeat(self.config.capacity());
+        let before = self.peers.len();
+        self.peers = mem::take(&mut self.peers)
+            .into_iter()
+            .filter_map(|mut peer| {
+                let addr = peer.addr();
+                if peer.health(now, HEARTBEAT_INTERVAL) == Health::Dead {
+                    let _ = peer.shutdown();
+                    sink.on_event(&Event::Peer(PeerEvent::Disconnected {
+                        addr,
+                        reason: DisconnectReason::TimedOut,
+                    }));
+                    return None;
+                }
+                if let Err(err) = peer.write(&frame) {
+                    sink.on_event(&Event::Notice(format!(
+                        "failed to write heartbeat to {addr}: {err}, dropping peer"
+                    )));
+                    return None;
+                }
+                Some(peer)
+            })
+            .collect();
+        self.count_pruned(before);
+    }
+
+    /// Returns `true` once a draining node's grace period is over.
+    pub fn drained(&self) -> bool {
+        self.leave_at
+            .is_some_and(|at| self.clock.now_instant() >= at)
+    }
+
+    /// Writes out the frames held back for coalescing once their window has
+    /// passed, or right away with `force`.
+    pub(crate) fn write_coalesced(&mut self, force: bool) {
+        let Some(coalesce) = &mut self.fanout.coalesce else {
+            return;
+        };
+        if !force && !coalesce.is_due(self.clock.now_instant()) {
+            return;
+        }
+
+        for (to, written) in coalesce.flush(&mut self.peers, |peer| Some(peer.addr())) {
+            let ev = match written {
+                Ok(bytes) => Event::Sent { to, bytes },
+                Err(err) => Event::Notice(format!("failed to write to {to}: {err}")),
+            };
+            self.sink.on_event(&ev);
+        }
+    }
+
+    /// Writes what peers didn't take on earlier passes, dropping the peers
+    /// whose connection broke in the meantime, see [`Peer::write_unsent`].
+    pub(crate) fn write_unsent(&mut self) {
+        let before = self.peers.len();
+        let sink = &mut self.sink;
+        self.peers.retain_mut(|peer| match peer.write_unsent() {
+            Ok(()) => true,
+            Err(err) => {
+                sink.on_event(&Event::Notice(format!(
+                    "failed to write to {}: {err}, dropping peer",
+                    peer.addr()
+                )));
+                false
+            }
+        });
+        self.count_pruned(before);
+    }
+
+    /// Leaves the mesh by closing the connection to every peer, once any
+    /// frames held back for coalescing are written, and saves the seen
+    /// messages to the state file, if any.
+    pub fn leave(&mut self) {
+        self.write_coalesced(true);
+        self.write_unsent();
+        for peer in self.peers.drain(..) {
+            let _ = peer.shutdown();
+        }
+        if let Some(path) = &self.config.state_file
+            && let Err(err) = state::save(path, &self.seen)
+        {
+            let notice = format!("failed to save state to {}: {err}", path.display());
+            self.notice(notice);
+        }
+        self.sink
+            .on_event(&Event::Notice(String::from("left the mesh")));
+    }
+
+    /// Lists the address of every connected peer, one per line, after
+    /// their count, along with how long ago it was last heard from and what
+    /// that says about its health.
+    fn list(&self) -> String {
+        let now = self.clock.now_instant();
+        let mut lines = vec![format!("{} peers", self.peers.len())];
+        lines.extend(self.peers.iter().map(|peer| {
+            let name = match peer.nick() {
+                Some(nick) => format!("{} <{nick}>", peer.identity()),
+                None => peer.identity().to_string(),
+            };
+            let idle = peer
+                .last_seen()
+                .map_or(0, |seen| now.saturating_duration_since(seen).as_secs());
+            let health = peer.health(now, HEARTBEAT_INTERVAL);
+            format!("{name}, {health}, last seen {idle}s ago")
+        }));
+        lines.join("\n")
+    }
+
+    /// Summarizes the node's peers and those it dropped, the messages it
+    /// handled and the frames it dropped, one field per line.
+    fn stats(&self) -> String {
+        let uptime = self
+            .clock
+            .now_instant()
+            .saturating_duration_since(self.started);
+        let mut lines = vec![
+            format!("peers: {}", self.peers.len()),
+            format!("pruned: {}", self.pruned),
+            format!("uptime: {}s", uptime.as_secs()),
+            format!("received: {}", self.received.total()),
+            format!("broadcast: {}", self.broadcasts.total()),
+            format!("seen: {}", self.received.total() + self.originated),
+            format!("seen memory: {} bytes", self.seen.memory_bytes()),
+        ];
+        if let Some(oldest) = self.seen.peek_front() {
+            lines.push(format!("oldest seen: {}", oldest.short_id()));
+        }
+        lines.extend(DropReason::ALL.into_iter().map(|reason| {
+            let count = match reason {
+                DropReason::InjectedLoss => self.fanout.faults.lost(),
+                reason => self.drops.get(reason),
+            };
+            format!("dropped {reason}: {count}")
+        }));
+        let deliveries = self.fanout.deliveries;
+        lines.push(format!("delivered: {}", deliveries.delivered));
+        lines.push(format!("failed deliveries: {}", deliveries.failed));
+
+        lines.join("\n")
+    }
+
+    /// Returns the state of the node, without its connections.
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            peers: self.peers.iter().map(Peer::identity).collect(),
+            seen: self
+                .seen
+                .iter()
+                .map(|msg| hex::encode(&msg.clone().into_bytes()))
+                .collect(),
+            drops: self.drops.clone(),
+        }
+    }
+
+    /// Replaces the seen messages and drop counts with those of `snapshot`,
+    /// returning the number of seen messages restored.
+    ///
+    /// The peers aren't dialed, so restoring never opens connections.
+    fn restore(&mut self, snapshot: &Snapshot) -> io::Result<usize> {
+        let messages = snapshot.messages()?;
+        let count = messages.len();
+        self.seen.clear();
+        messages.into_iter().for_each(|msg| {
+            self.seen.push(msg);
+        });
+        self.drops = snapshot.drops.clone();
+        Ok(count)
+    }
+
+    fn notice(&mut self, notice: String) {
+        if let Origin::Control(reply) = &mut self.origin {
+            // A client that went away or doesn't read only misses the reply.
+            let _ = writeln!(reply, "{notice}");
+        }
+        self.sink.on_event(&Event::Notice(notice));
+    }
+
+    /// Broadcasts a new message with the given text to every peer and
+    /// returns its UUID, so it can be recognized when it comes back.
+    ///
+    /// The message carries the configured nick, if any. With a `ttl`, nodes
+    /// drop the message once that much time has passed.
+    pub fn broadcast(
+        &mut self,
+        text: impl Into<String>,
+        ttl: Option<Duration>,
+    ) -> Result<Uuid, BroadcastError> {
+        let msg =
+            Msg::new_in(text, msg::MAX_CAPACITY)?.with_created_at(self.clock.now_unix_millis())?;
+        self.broadcast_msg(msg, ttl)
+    }
+
+    /// Broadcasts `msg` like [`Node::broadcast`], under its own UUID.
+    fn broadcast_msg(&mut self, msg: Msg, ttl: Option<Duration>) -> Result<Uuid, BroadcastError> {
+        let hops = self.config.hops.unwrap_or(msg::DEFAULT_TTL);
+        let msg = self.originate(msg, hops, ttl)?;
+        let uuid = msg.uuid();
+
+        let now = self.clock.now_instant();
+        self.broadcasts.record(now);
+        let before = self.peers.len();
+        (self.peers, _) = broadcast(
+            mem::take(&mut self.peers),
+            &fragment::split(msg, self.fanout.capacity()),
+            &mut self.fanout,
+            now,
+            &mut Throttled::new(&mut self.sink, &mut self.errors, now),
+        );
+        self.count_pruned(before);
+
+        Ok(uuid)
+    }
+
+    /// Connects to the peer listening on `addr`.
+    pub fn connect(&mut self, addr: SocketAddr) -> io::Result<()> {
+        self.dial(vec![addr], None)
+    }
+
+    /// Connects to the first of `addrs` that accepts, resolved from `host`
+    /// if it was dialed by name.
+    fn dial(&mut self, addrs: Vec<SocketAddr>, host: Option<String>) -> io::Result<()> {
+        let mut connected = Ok(());
+        for addr in addrs {
+            connected = connect(
+                &mut self.peers,
+                addr,
+                host.clone(),
+                Dialer {
+                    listening: self.local_addr,
+                    nonce: self.nonce,
+                    proxy: self.config.proxy,
+                    capacity: self.config.capacity(),
+                    max_peers: self.config.max_peers,
+                    framing: self.config.framing(),
+                },
+                self.clock.now_instant(),
+                &mut self.audit,
+                &mut Notifying::new(&mut self.sink, &mut self.subscribers),
+            );
+            if connected.is_ok() {
+                self.check_ready();
+                break;
+            }
+        }
+        connected
+    }
+
+    /// Sends `msg` to the peer known by `to` only.
+    ///
+    /// The message is sent without hops left, so the peer doesn't pass it on,
+    /// and stamped as created now.
+    fn whisper(&mut self, to: SocketAddr, msg: Msg) -> Result<(), BroadcastError> {
+        let msg = msg.with_created_at(self.clock.now_unix_millis())?;
+        let msg = self.originate(msg, 0, None)?;
+        let now = self.clock.now_instant();
+        let before = self.peers.len();
+        (self.peers, _) = send_to(
+            mem::take(&mut self.peers),
+            to,
+            &fragment::split(msg, self.fanout.capacity()),
+            &mut self.fanout,
+            now,
+            &mut Throttled::new(&mut self.sink, &mut self.errors, now),
+        );
+        self.count_pruned(before);
+        Ok(())
+    }
+
+    /// Finishes a message sent by this node with `hops` and our nick, and
+    /// expiring after `ttl`, and remembers it as seen so it isn't shown
+    /// when it comes back.
+    fn originate(
+        &mut self,
+        msg: Msg,
+        hops: u8,
+        ttl: Option<Duration>,
+    ) -> Result<Msg, BroadcastError> {
+        if self.leave_at.is_some() {
+            return Err(BroadcastError::Draining);
+        }
+        let chars = msg.text().chars().count();
+        if let Some(max) = self.config.max_chars.filter(|&max| chars > max) {
+            return Err(BroadcastError::TooManyChars {
+                chars,
+                bytes: msg.text().len(),
+                max,
+            });
+        }
+
+        let msg = msg.with_ttl(hops).with_capacity(msg::MAX_CAPACITY)?;
+        let msg = match &self.config.nick {
+            Some(nick) => msg.with_nick(nick)?,
+            None => msg,
+        };
+        let msg = match ttl {
+            Some(ttl) => {
+                // A ttl too long to count the expiry in means it never expires.
+                let now = self.clock.now_unix_millis() / 1000;
+                msg.with_expiry(now.saturating_add(ttl.as_secs()))?
+            }
+            None => msg,
+        };
+
+        record_seen(&mut self.seen_log, &msg, &mut self.sink);
+        self.seen.push(msg.clone());
+        self.originated += 1;
+        Ok(msg)
+    }
+
+    /// Schedules every message exported to `path` to be broadcast again,
+    /// oldest first, and returns how many were scheduled.
+    ///
+    /// Unless `fast`, the messages are spaced out as they were created,
+    /// going by the creation times in the export; messages without one are
+    /// sent straight after the one before. With `keep_uuids`, peers that
+    /// still remember a message drop it.
+    fn replay(&mut self, path: &Path, keep_uuids: bool, fast: bool) -> Result<usize, ReplayError> {
+        let entries = export::read_seen(path)?;
+        let msgs = entries
+            .iter()
+            .map(|entry| {
+                if keep_uuids {
+                    Msg::from_parts(entry.text.clone(), entry.uuid, entry.created_at)
+                } else {
+                    Msg::from_parts(entry.text.clone(), Uuid::new_v4(), None)
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(BroadcastError::from)?;
+
+        let now = self.clock.now_instant();
+        let first = entries.iter().find_map(|entry| entry.created_at);
+        let mut due = self.replays.back().map_or(now, |&(due, _)| due.max(now));
+        for (entry, msg) in entries.iter().zip(msgs) {
+            if let (false, Some(first), Some(at)) = (fast, first, entry.created_at) {
+                due = due.max(now + Duration::from_millis(at.saturating_sub(first)));
+            }
+            self.replays.push_back((due, msg));
+        }
+
+        self.replay_due();
+        Ok(entries.len())
+    }
+
+    /// Broadcasts the replayed messages that are due, stamping those that
+    /// weren't stamped in the export as created now.
+    fn replay_due(&mut self) {
+        let now = self.clock.now_instant();
+        while let Some((_, msg)) = self.replays.pop_front_if(|(due, _)| *due <= now) {
+            let msg = match msg.created_at() {
+                Some(_) => Ok(msg),
+                None => msg.with_created_at(self.clock.now_unix_millis()),
+            };
+            let sent = msg
+                .map_err(BroadcastError::from)
+                .and_then(|msg| self.broadcast_msg(msg, None));
+            if let Err(err) = sent {
+                self.notice(format!("failed to replay a message: {err}"));
+            }
+        }
+    }
+
+    /// Returns `true` if broadcasting `text` should wait for `confirm`.
+    ///
+    /// Only broadcasts typed at the terminal ask, not those sent over the
+    /// control socket, and `--yes` turns asking off altogether.
+    fn needs_confirmation(&self, text: &str) -> bool {
+        !self.config.yes
+            && matches!(self.origin, Origin::Stdin)
+            && self.config.confirm.required(self.peers.len(), text.len())
+    }
+
+    pub(crate) fn send_broadcast(&mut self, text: String, ttl: Option<Duration>) {
+        if let Err(err) = self.broadcast(text, ttl) {
+            self.notice(format!("failed to broadcast: {err}"));
+        }
+    }
+
+    /// Carries out a command given by the user.
+    pub(crate) fn handle(&mut self, cmd: Command) -> io::Result<()> {
+        match cmd {
+            Command::Connect(addrs, host) => {
+                if let Some(peer) = self
+                    .peers
+                    .iter()
+                    .find(|peer| addrs.contains(&peer.identity()))
+                {
+                    let notice = format!("already connected to {}", peer.identity());
+                    self.notice(notice);
+                    return Ok(());
+                }
+
+                let target = dial_target(&addrs, host.as_deref());
+                if let Err(err) = self.dial(addrs, host) {
+                    self.notice(format!("failed to connect to {target}: {err}"));
+                }
+            }
+            Command::Reconnect(addr) => {
+                let Some(i) = self.peers.iter().position(|peer| peer.identity() == addr) else {
+                    self.notice(format!("no peer {addr}"));
+                    return Ok(());
+                };
+                let host = self.peers[i].host().map(String::from);
+                let addrs = match &host {
+                    Some(host) => match self.resolver.resolve(host) {
+                        Ok(addrs) => addrs,
+                        Err(err) => {
+                            self.notice(format!("failed to resolve `{host}`: {err}"));
+                            return Ok(());
+                        }
+                    },
+                    None => vec![self.peers[i].addr()],
+                };
+
+                let old = self.peers.remove(i);
+                self.pruned += 1;
+                let _ = old.shutdown();
+                let ev = PeerEvent::Disconnected {
+                    addr: old.addr(),
+                    reason: DisconnectReason::Reconnecting,
+                };
+                Notifying::new(&mut self.sink, &mut self.subscribers).on_event(&Event::Peer(ev));
+                let target = dial_target(&addrs, host.as_deref());
+                if let Err(err) = self.dial(addrs, host) {
+                    self.notice(format!("failed to connect to {target}: {err}"));
+                }
+            }
+            Command::Nick(nick) if nick.is_empty() => {
+                self.config.nick = None;
+                self.notice(String::from("nick cleared"));
+            }
+            Command::Nick(nick) if nick.len() > msg::MAX_NICK => self.notice(format!(
+                "nick too long, {} bytes where at most {} fit",
+                nick.len(),
+                msg::MAX_NICK
+            )),
+            Command::Nick(nick) => {
+                self.notice(format!("nick set to {nick}"));
+                self.config.nick = Some(nick);
+            }
+            Command::Filter(None) => {
+                self.config.filter = None;
+                self.notice(String::from("filter cleared, showing every message"));
+            }
+            Command::Filter(Some(pattern)) => {
+                self.notice(format!("showing only messages containing `{pattern}`"));
+                self.config.filter = Some(pattern);
+            }
+            Command::Resolve(host) => {
+                let notice = match self.resolver.resolve(&host) {
+                    Ok(addrs) => {
+                        let addrs: Vec<_> = addrs.iter().map(SocketAddr::to_string).collect();
+                        format!("{host} resolves to {}", addrs.join(", "))
+                    }
+                    Err(err) => format!("failed to resolve `{host}`: {err}"),
+                };
+                self.notice(notice);
+            }
+            Command::Broadcast(text, ttl) if self.needs_confirmation(&text) => {
+                self.notice(format!(
+                    "broadcast of {} bytes to {} peers: `confirm` to send, `cancel` to drop",
+                    text.len(),
+                    self.peers.len()
+                ));
+                self.pending = Some((text, ttl));
+            }
+            Command::Broadcast(text, ttl) => self.send_broadcast(text, ttl),
+            Command::Whisper(to, msg) => {
+                if let Err(err) = self.whisper(to, *msg) {
+                    self.notice(format!("failed to whisper: {err}"));
+                }
+            }
+            Command::Confirm => match self.pending.take() {
+                Some((text, ttl)) => self.send_broadcast(text, ttl),
+                None => self.notice(String::from("no broadcast to confirm")),
+            },
+            Command::Cancel => match self.pending.take() {
+                Some(_) => self.notice(String::from("broadcast cancelled")),
+                None => self.notice(String::from("no broadcast to cancel")),
+            },
+            Command::Disconnect => {
+                self.redials.forget_all();
+                // A peer that already reset its end is as good as shut down.
+                let failed: Vec<_> = self
+                    .peers
+                    .iter()
+                    .filter_map(|peer| match peer.shutdown() {
+                        Err(err) if err.kind() != io::ErrorKind::NotConnected => {
+                            Some(format!("failed to shut down {}: {err}", peer.addr()))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                failed.into_iter().for_each(|notice| self.notice(notice));
+            }
+            Command::Block(ip) => {
+                let notice = match self.blocklist.block(ip) {
+                    Ok(_) => format!("blocked {ip}"),
+                    Err(err) => format!("failed to save blocklist: {err}"),
+                };
+                self.notice(notice);
+                let before = self.peers.len();
+                self.peers = drop_blocked(
+                    mem::take(&mut self.peers),
+                    &self.blocklist,
+                    &mut Notifying::new(&mut self.sink, &mut self.subscribers),
+                );
+                self.count_pruned(before);
+            }
+            Command::Unblock(ip) => {
+                let notice = match self.blocklist.unblock(ip) {
+                    Ok(_) => format!("unblocked {ip}"),
+                    Err(err) => format!("failed to save blocklist: {err}"),
+                };
+                self.notice(notice);
+            }
+            Command::Replay(path, keep_uuids, fast) => {
+                let notice = match self.replay(&path, keep_uuids, fast) {
+                    Ok(count) => format!("replaying {count} messages from {}", path.display()),
+                    Err(err) => format!("failed to replay {}: {err}", path.display()),
+                };
+                self.notice(notice);
+            }
+            Command::ExportSeen(path) => {
+                let notice = match export::write_seen(&path, &self.seen) {
+                    Ok(count) => format!("exported {count} messages to {}", path.display()),
+                    Err(err) => format!(
+                        "failed to export seen messages to {}: {err}",
+                        path.display()
+                    ),
+                };
+                self.notice(notice);
+            }
+            Command::Snapshot(path) => {
+                let snapshot = self.snapshot();
+                let notice = match snapshot.save(&path) {
+                    Ok(()) => format!(
+                        "snapshotted {} seen messages and {} peers to {}",
+                        snapshot.seen.len(),
+                        snapshot.peers.len(),
+                        path.display()
+                    ),
+                    Err(err) => format!("failed to snapshot to {}: {err}", path.display()),
+                };
+                self.notice(notice);
+            }
+            Command::Restore(path) => {
+                let notice = match Snapshot::load(&path).and_then(|s| self.restore(&s)) {
+                    Ok(count) => format!("restored {count} seen messages from {}", path.display()),
+                    Err(err) => format!("failed to restore {}: {err}", path.display()),
+                };
+                self.notice(notice);
+            }
+            Command::Forget(id) => {
+                let notice = match find_seen(&self.seen, &id).cloned() {
+                    Ok(msg) => {
+                        self.seen.remove(&msg);
+                        format!("forgot {}", msg.uuid())
+                    }
+                    Err(err) => err.to_string(),
+                };
+                self.notice(notice);
+            }
+            Command::Last => {
+                let notice = match self.seen.peek_back() {
+                    Some(msg) => format!("last seen {}: {:?}", msg.short_id(), msg.text()),
+                    None => String::from("no seen messages"),
+                };
+                self.notice(notice);
+            }
+            Command::History => {
+                let mut lines = vec![format!("{} seen messages", self.seen.len())];
+                lines.extend(self.seen.iter().map(Msg::to_string));
+                self.notice(lines.join("\n"));
+            }
+            Command::ClearSeen => {
+                let count = self.seen.len();
+                self.seen.clear();
+                self.notice(format!("forgot {count} seen messages"));
+            }
+            Command::ResizeSeen(capacity) => {
+                let evicted = self.seen.set_capacity(capacity);
+                self.notice(format!(
+                    "resized seen queue to {capacity}, forgot {} messages",
+                    evicted.len()
+                ));
+            }
+            Command::FlushAll => {
+                self.write_coalesced(true);
+                flush_all(&mut self.peers, &mut self.sink);
+            }
+            Command::Encode(msg) => self.notice(hex::encode(&msg.into_bytes())),
+            Command::Decode(bytes) => {
+                let notice = match decode_frame(&bytes, self.config.capacity()) {
+                    Ok(msg) => format!(
+                        "text: {:?}, uuid: {}, ttl: {}",
+                        msg.text(),
+                        msg.uuid(),
+                        msg.ttl()
+                    ),
+                    Err(err) => format!("failed to decode frame: {err}"),
+                };
+                self.notice(notice);
+            }
+            Command::Explain(msg) => self.notice(explain(&msg)),
+            Command::Faults(loss, delay) => {
+                let notice = if self.config.test_faults {
+                    self.fanout.faults.set(loss, delay);
+                    format!(
+                        "injecting faults: loss={loss} delay={}ms",
+                        delay.as_millis()
+                    )
+                } else {
+                    String::from("fault injection requires --test-faults")
+                };
+                self.notice(notice);
+            }
+            Command::Pause => {
+                let notice = match self.held {
+                    Some(_) => String::from("already paused"),
+                    None => {
+                        self.held = Some(Queue::new(PAUSE_BUFFER));
+                        format!("paused, holding up to {PAUSE_BUFFER} frames")
+                    }
+                };
+                self.notice(notice);
+            }
+            Command::Resume => match self.held.take() {
+                Some(held) => {
+                    let notice = match held.len() {
+                        0 => String::from("resumed"),
+                        n => format!("resumed, processing {n} held frames"),
+                    };
+                    self.notice(notice);
+                    self.resume(held);
+                }
+                None => self.notice(String::from("not paused")),
+            },
+            Command::Drain => {
+                let notice = match self.leave_at {
+                    Some(_) => String::from("already draining"),
+                    None => {
+                        self.leave_at = Some(self.clock.now_instant() + DRAIN_GRACE);
+                        format!("draining, leaving in {}ms", DRAIN_GRACE.as_millis())
+                    }
+                };
+                self.notice(notice);
+            }
+            Command::Stats => self.notice(self.stats()),
+            Command::Throughput => {
+                let now = self.clock.now_instant();
+                self.notice(format!(
+                    "received: {}\nbroadcast: {}",
+                    self.received.rates(now),
+                    self.broadcasts.rates(now)
+                ));
+            }
+            Command::List => self.notice(self.list()),
+            Command::Log(level) => {
+                self.config.log_level = level;
+                self.sink.set_level(level);
+                self.notice(format!("showing {level} events and up"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        cell::RefCell,
+        fs,
+        io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+        net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+        os::unix::net::UnixStream,
+        rc::Rc,
+        sync::mpsc,
+        thread,
+        time::Duration,
+    };
+
+    use uuid::Uuid;
+
+    use crate::{
+        Command, ParseCommandError,
+        clock::{Clock, Manual},
+        confirm::Confirm,
+        control, export, handshake, id_arg,
+        msg::{self, Msg},
+        node::{
+            Config, ConfigError, DRAIN_GRACE, HEARTBEAT_INTERVAL, IdError, Node, PAUSE_BUFFER,
+            find_seen,
+        },
+        peer::Framing,
+        queue::DedupQueue,
+        redial,
+        resolve::Fixed,
+        send_once,
+        sink::{DisconnectReason, Event, PeerEvent, Recorder},
+        stats::DropReason,
+        test::{loopback, temp_path},
+    };
+
+    /// Returns a node on an ephemeral loopback port, reporting to a
+    /// [`Recorder`].
+    fn node(config: Config) -> Node<Recorder> {
+        Node::bind("127.0.0.1:0".parse().unwrap(), config, Recorder::default()).unwrap()
+    }
+
+    /// Dials `b` from `a`, returning once `b` accepted the connection.
+    fn connected(a: &mut Node<Recorder>, b: &mut Node<Recorder>) {
+        a.handle(Command::Connect(vec![b.local_addr()], None))
+            .unwrap();
+        let accepted = b.peers.len() + 1;
+        while b.peers.len() < accepted {
+            b.accept_incoming();
+        }
+    }
+
+    /// Connects a client to `node`, returning it once `node` accepted it.
+    fn client(node: &mut Node<Recorder>) -> TcpStream {
+        let client = TcpStream::connect(node.local_addr()).unwrap();
+        let accepted = node.peers.len() + 1;
+        while node.peers.len() < accepted {
+            node.accept_incoming();
+        }
+        client
+    }
+
+    /// Reads the hello a node sends first, returning the address it advertises.
+    fn read_hello(client: &mut TcpStream) -> SocketAddr {
+        let mut frame = [0; msg::CAPACITY];
+        client.read_exact(&mut frame).unwrap();
+        handshake::read_hello(&frame).unwrap()
+    }
+
+    #[test]
+    fn connecting_twice_keeps_one_peer() {
+        let a = node(Config::default());
+        let mut b = node(Config::default());
+
+        b.handle(Command::Connect(vec![a.local_addr()], None))
+            .unwrap();
+        b.handle(Command::Connect(vec![a.local_addr()], None))
+            .unwrap();
+
+        assert_eq!(b.peers.len(), 1);
+        assert_eq!(
+            b.sink.events.last(),
+            Some(&Event::Notice(format!(
+                "already connected to {}",
+                a.local_addr()
+            )))
+        );
+    }
+
+    #[test]
+    fn overlong_ttl_never_expires() {
+        let mut node = node(Config::default());
+        node.broadcast("forever", Some(Duration::from_secs(u64::MAX)))
+            .unwrap();
+
+        let msg = node.seen.iter().next().unwrap();
+        assert!(!msg.is_expired(u64::MAX));
+    }
+
+    #[test]
+    fn restoring_a_snapshot_reproduces_seen_and_counters() {
+        let path = temp_path("snapshot");
+        let mut saved = node(Config::default());
+        saved.broadcast("first", None).unwrap();
+        saved
+            .broadcast("second", Some(Duration::from_secs(60)))
+            .unwrap();
+        saved.drops.record(DropReason::Duplicate);
+        saved.drops.record(DropReason::Expired);
+        saved.handle(Command::Snapshot(path.clone())).unwrap();
+
+        let mut fresh = node(Config::default());
+        fresh.broadcast("forgotten", None).unwrap();
+        fresh.handle(Command::Restore(path.clone())).unwrap();
+
+        assert!(fresh.seen.iter().eq(saved.seen.iter()));
+        assert_eq!(fresh.drops, saved.drops);
+        assert_eq!(
+            fresh.sink.events.last(),
+            Some(&Event::Notice(format!(
+                "restored 2 seen messages from {}",
+                path.display()
+            )))
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn filtered_out_messages_are_propagated_but_not_shown() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (from, mut sender) = loopback(&listener);
+        let (onward, mut next) = loopback(&listener);
+        let mut node = node(Config::default());
+        let origin = from.addr();
+        node.peers = vec![from, onward];
+        node.handle("filter dust".parse().unwrap()).unwrap();
+
+        let noise = Msg::new("unrelated chatter").unwrap();
+        let wanted = Msg::new("about dust").unwrap();
+        let mut frames = noise.clone().into_bytes().to_vec();
+        frames.extend(wanted.clone().into_bytes());
+        sender.write_all(&frames).unwrap();
+
+        let mut relayed = Vec::new();
+        let mut buf = [0; msg::CAPACITY];
+        while relayed.len() < 2 * msg::CAPACITY {
+            node.receive();
+            match next.read(&mut buf) {
+                Ok(n) => relayed.extend_from_slice(&buf[..n]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => (),
+                Err(err) => panic!("{err}"),
+            }
+        }
+
+        let relayed: Vec<_> = relayed
+            .chunks(msg::CAPACITY)
+            .map(|frame| Msg::try_from(<[u8; msg::CAPACITY]>::try_from(frame).unwrap()).unwrap())
+            .collect();
+        assert_eq!(relayed, vec![noise, wanted.clone()]);
+        assert_eq!(node.sink.messages, vec![(wanted, origin)]);
+    }
+
+    #[test]
+    fn unreachable_bootstrap_peer_is_skipped() {
+        let reachable = TcpListener::bind("127.0.0.1:0").unwrap();
+        let unreachable = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config = Config {
+            bootstrap: vec![unreachable, reachable.local_addr().unwrap()],
+            ..Config::default()
+        };
+        let mut node = node(config);
+
+        node.bootstrap();
+
+        assert_eq!(node.peers.len(), 1);
+        assert_eq!(node.peers[0].addr(), reachable.local_addr().unwrap());
+        assert!(matches!(
+            node.sink.events.as_slice(),
+            [Event::Notice(failed), Event::Peer(PeerEvent::Connected(_))]
+                if failed.starts_with(&format!("failed to connect to bootstrap peer {unreachable}"))
+        ));
+    }
+
+    #[test]
+    fn closed_inputs_leave_the_node_receiving() {
+        let mut node = node(Config::default());
+        let (tx, rx) = mpsc::channel();
+        drop(tx);
+
+        let mut cmds = Some(rx);
+        node.handle_next(&mut cmds).unwrap();
+        node.handle_next(&mut cmds).unwrap();
+
+        assert!(cmds.is_none());
+        assert_eq!(
+            node.sink.events,
+            vec![Event::Notice(String::from(
+                "all inputs closed, only receiving from now on"
+            ))]
+        );
+    }
+
+    #[test]
+    fn failed_listener_stops_accepting_without_panicking() {
+        let mut node = node(Config::default());
+        // The original listener still holds the address, so listening again
+        // on it fails.
+        let (tx, rx) = mpsc::channel();
+        tx.send(Err(io::Error::other("accept failed"))).unwrap();
+        node.incoming = Some(rx);
+
+        node.accept_incoming();
+        node.accept_incoming();
+
+        assert!(node.incoming.is_none());
+        assert!(matches!(
+            node.sink.events.as_slice(),
+            [Event::Notice(notice)]
+                if notice.starts_with("failed to accept: accept failed, no longer accepting peers")
+        ));
+    }
+
+    #[test]
+    fn subscribers_hear_of_new_peers() {
+        let mut a = node(Config::default());
+        let mut b = node(Config::default());
+        let events = Rc::new(RefCell::new(Vec::new()));
+        for _ in 0..2 {
+            let events = events.clone();
+            a.on_peer_event(move |ev| events.borrow_mut().push(ev.clone()));
+        }
+
+        connected(&mut b, &mut a);
+
+        let connected = PeerEvent::Connected(a.peers[0].addr());
+        assert_eq!(*events.borrow(), vec![connected.clone(), connected]);
+    }
+
+    #[test]
+    fn nodes_dialing_each_other_keep_the_same_link() {
+        let mut a = node(Config::default());
+        let mut b = node(Config::default());
+
+        a.handle(Command::Connect(vec![b.local_addr()], None))
+            .unwrap();
+        b.handle(Command::Connect(vec![a.local_addr()], None))
+            .unwrap();
+        while a.peers.len() < 2 || b.peers.len() < 2 {
+            a.accept_incoming();
+            b.accept_incoming();
+        }
+        while a.peers.len() > 1 || b.peers.len() > 1 {
+            a.receive();
+            b.receive();
+        }
+        assert_eq!((a.peers.len(), b.peers.len()), (1, 1));
+
+        a.send_broadcast(String::from("over the kept link"), None);
+        while b.sink.messages.is_empty() {
+            b.receive();
+        }
+        assert_eq!(b.sink.messages[0].0.text(), "over the kept link");
+    }
+
+    #[test]
+    fn nodes_offering_length_prefixed_frames_exchange_them() {
+        let config = Config::from_args(&[String::from("--length-prefixed")]).unwrap();
+        let bind = || node(config.clone());
+        let (mut a, mut b) = (bind(), bind());
+
+        connected(&mut b, &mut a);
+        let prefixed = |node: &Node<Recorder>| node.peers[0].outbound() == Framing::LengthPrefixed;
+        while !prefixed(&a) || !prefixed(&b) {
+            for node in [&mut a, &mut b] {
+                node.receive();
+                node.write_unsent();
+            }
+        }
+
+        a.send_broadcast(String::from("without padding"), None);
+        while b.sink.messages.is_empty() {
+            b.receive();
+        }
+        assert_eq!(b.sink.messages[0].0.text(), "without padding");
+    }
+
+    #[test]
+    fn both_sides_learn_listening_addresses() {
+        let mut a = node(Config::default());
+        let mut b = node(Config::default());
+
+        connected(&mut b, &mut a);
+        assert_ne!(a.peers[0].addr(), b.local_addr());
+
+        while a.peers[0].identity() != b.local_addr() || b.peers[0].identity() != a.local_addr() {
+            a.receive();
+            b.receive();
+        }
+        assert!(a.list().contains(&b.local_addr().to_string()));
+    }
+
+    #[test]
+    fn node_keeps_running_after_connect_past_the_limit() {
+        let args = [String::from("--max-peers"), String::from("1")];
+        let config = Config::from_args(&args).unwrap();
+        let mut node = node(config);
+        let first = TcpListener::bind("127.0.0.1:0").unwrap();
+        let second = TcpListener::bind("127.0.0.1:0").unwrap();
+        let second_addr = second.local_addr().unwrap();
+
+        node.handle(Command::Connect(vec![first.local_addr().unwrap()], None))
+            .unwrap();
+        node.handle(Command::Connect(vec![second_addr], None))
+            .unwrap();
+
+        assert_eq!(node.peers.len(), 1);
+        let refused = format!("failed to connect to {second_addr}: at the limit of 1 peers");
+        assert_eq!(node.sink.events.last(), Some(&Event::Notice(refused)));
+        node.handle(Command::Stats).unwrap();
+        assert!(
+            matches!(node.sink.events.last(), Some(Event::Notice(stats)) if stats.starts_with("peers: 1"))
+        );
+    }
+
+    #[test]
+    fn node_keeps_running_after_connect_to_unreachable_peer() {
+        let mut node = node(Config::default());
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        node.handle(Command::Connect(vec![closed], Some(String::from("gone:1"))))
+            .unwrap();
+
+        assert!(node.peers.is_empty());
+        assert!(matches!(
+            node.sink.events.last(),
+            Some(Event::Notice(notice)) if notice.starts_with("failed to connect to gone:1: ")
+        ));
+    }
+
+    #[test]
+    fn disconnect_shuts_down_every_peer_past_a_gone_one() {
+        let mut node = node(Config::default());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (gone, gone_client) = loopback(&listener);
+        let (live, mut live_client) = loopback(&listener);
+        drop(gone_client);
+        gone.shutdown().unwrap();
+        assert_eq!(gone.shutdown().unwrap_err().kind(), ErrorKind::NotConnected);
+        node.peers = vec![gone, live];
+
+        node.handle(Command::Disconnect).unwrap();
+
+        assert!(node.sink.events.is_empty());
+        live_client.set_nonblocking(false).unwrap();
+        assert_eq!(live_client.read(&mut [0; 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn sent_message_is_received() {
+        let mut receiver = node(Config::default());
+        let to = receiver.local_addr();
+
+        send_once(
+            to,
+            String::from("one-shot"),
+            Config::default(),
+            Recorder::default(),
+        )
+        .unwrap();
+        while receiver.sink.messages.is_empty() {
+            receiver.accept_incoming();
+            receiver.receive();
+        }
+
+        assert_eq!(receiver.sink.messages[0].0.text(), "one-shot");
+    }
+
+    #[test]
+    fn flush_all_reports_each_peer() {
+        let config = Config {
+            coalesce: Some(Duration::from_secs(60)),
+            ..Config::default()
+        };
+        let mut node = node(config);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (peer, mut client) = loopback(&listener);
+        let (other, _other_client) = loopback(&listener);
+        node.peers = vec![peer, other];
+
+        node.broadcast("buffered", None).unwrap();
+        node.poll();
+        let mut bytes = [0; msg::CAPACITY];
+        assert!(client.read(&mut bytes).is_err());
+
+        node.sink.events.clear();
+        node.handle(Command::FlushAll).unwrap();
+
+        let flushed: Vec<_> = node
+            .sink
+            .events
+            .iter()
+            .filter(|ev| matches!(ev, Event::Notice(_)))
+            .cloned()
+            .collect();
+        let expected: Vec<_> = node
+            .peers
+            .iter()
+            .map(|peer| Event::Notice(format!("flushed {}", peer.addr())))
+            .collect();
+        assert_eq!(flushed, expected);
+
+        client.set_nonblocking(false).unwrap();
+        client.read_exact(&mut bytes).unwrap();
+        assert_eq!(Msg::try_from(bytes).unwrap().text(), "buffered");
+    }
+
+    #[test]
+    fn replayed_log_is_broadcast_in_order() {
+        let path = temp_path("seen");
+        let mut seen = DedupQueue::new(16);
+        seen.push(Msg::new("first").unwrap());
+        seen.push(Msg::new("second").unwrap());
+        export::write_seen(&path, &seen).unwrap();
+
+        let mut node = node(Config::default());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (peer, mut client) = loopback(&listener);
+        node.peers = vec![peer];
+
+        let cmd = format!(
+            "replay --keep-uuids --as-fast-as-possible {}",
+            path.display()
+        );
+        let Ok(cmd) = cmd.parse() else {
+            panic!("expected a replay command");
+        };
+        node.handle(cmd).unwrap();
+
+        client.set_nonblocking(false).unwrap();
+        let replayed: Vec<_> = (0..2)
+            .map(|_| {
+                let mut frame = [0; msg::CAPACITY];
+                client.read_exact(&mut frame).unwrap();
+                let msg = Msg::try_from(frame).unwrap();
+                (msg.text().to_string(), msg.uuid())
+            })
+            .collect();
+        let exported: Vec<_> = seen
+            .iter()
+            .map(|msg| (msg.text().to_string(), msg.uuid()))
+            .collect();
+        assert_eq!(replayed, exported);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn replay_keeps_the_original_spacing() {
+        let path = temp_path("seen");
+        let mut seen = DedupQueue::new(16);
+        for (text, created_at) in [("first", 5_000), ("second", 7_000)] {
+            seen.push(Msg::new(text).unwrap().with_created_at(created_at).unwrap());
+        }
+        export::write_seen(&path, &seen).unwrap();
+
+        let mut node = node(Config::default());
+        let clock = Manual::default();
+        node.clock = Box::new(clock.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (peer, mut client) = loopback(&listener);
+        node.peers = vec![peer];
+
+        let Ok(cmd) = format!("replay {}", path.display()).parse() else {
+            panic!("expected a replay command");
+        };
+        node.handle(cmd).unwrap();
+        let mut frame = [0; msg::CAPACITY];
+        client.set_nonblocking(false).unwrap();
+        client.read_exact(&mut frame).unwrap();
+        assert_eq!(Msg::try_from(frame).unwrap().text(), "first");
+
+        client.set_nonblocking(true).unwrap();
+        clock.advance(Duration::from_millis(1_999));
+        node.poll();
+        assert!(client.read(&mut frame).is_err());
+
+        clock.advance(Duration::from_millis(1));
+        node.poll();
+        client.set_nonblocking(false).unwrap();
+        client.read_exact(&mut frame).unwrap();
+        let second = Msg::try_from(frame).unwrap();
+        assert_eq!(second.text(), "second");
+        assert_ne!(second.uuid(), seen.iter().nth(1).unwrap().uuid());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn history_shows_seen_messages_oldest_first() {
+        let args = [String::from("--seen-capacity"), String::from("2")];
+        let config = Config::from_args(&args).unwrap();
+        let mut node = node(config);
+        for text in ["first", "second", "third"] {
+            node.seen.push(Msg::new(text).unwrap());
+        }
+        node.seen
+            .push(Msg::new("two\nlines").unwrap().with_nick("bob").unwrap());
+
+        node.handle(Command::History).unwrap();
+
+        let expected = "2 seen messages\nthird\nbob: two\\nlines";
+        assert_eq!(node.sink.events, vec![Event::Notice(expected.to_string())]);
+    }
+
+    #[test]
+    fn list_shows_every_peer() {
+        let mut node = node(Config::default());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (a, _a_client) = loopback(&listener);
+        let (b, _b_client) = loopback(&listener);
+        let expected = format!(
+            "2 peers\n{}, alive, last seen 0s ago\n{}, alive, last seen 0s ago",
+            a.addr(),
+            b.addr()
+        );
+        node.peers = vec![a, b];
+
+        node.handle(Command::List).unwrap();
+
+        assert_eq!(node.sink.events, vec![Event::Notice(expected)]);
+    }
+
+    #[test]
+    fn list_labels_peers_by_how_long_they_were_quiet() {
+        let mut node = node(Config::default());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (peer, _client) = loopback(&listener);
+        let addr = peer.addr();
+        let clock = Manual::default();
+        node.peers = vec![peer.with_last_seen(clock.now_instant())];
+        node.clock = Box::new(clock.clone());
+
+        let mut labels = Vec::new();
+        for _ in 0..3 {
+            labels.push(node.list().lines().nth(1).unwrap().to_string());
+            clock.advance(HEARTBEAT_INTERVAL + Duration::from_millis(500));
+        }
+
+        let secs = HEARTBEAT_INTERVAL.as_secs();
+        assert_eq!(
+            labels,
+            vec![
+                format!("{addr}, alive, last seen 0s ago"),
+                format!("{addr}, stale, last seen {secs}s ago"),
+                format!("{addr}, dead, last seen {}s ago", secs * 2 + 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn broadcasts_are_stamped_by_the_node_clock() {
+        let mut node = node(Config::default());
+        let clock = Manual::default();
+        clock.advance(Duration::from_secs(3600));
+        node.clock = Box::new(clock.clone());
+
+        let uuid = node.broadcast("an hour on", None).unwrap();
+
+        let msg = node.seen.iter().find(|msg| msg.uuid() == uuid).unwrap();
+        assert_eq!(msg.created_at(), Some(clock.now_unix_millis()));
+    }
+
+    #[test]
+    fn silent_peer_is_dropped_after_the_timeout() {
+        let mut node = node(Config::default());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (peer, mut client) = loopback(&listener);
+        let addr = peer.addr();
+        let clock = Manual::default();
+        node.peers = vec![peer.with_last_seen(clock.now_instant())];
+        node.next_heartbeat = clock.now_instant() + HEARTBEAT_INTERVAL;
+        node.clock = Box::new(clock.clone());
+
+        clock.advance(HEARTBEAT_INTERVAL);
+        node.heartbeat();
+        assert_eq!(node.peers.len(), 1);
+        let mut frame = [0; msg::CAPACITY];
+        client.set_nonblocking(false).unwrap();
+        client.read_exact(&mut frame).unwrap();
+        assert!(handshake::is_heartbeat(&frame));
+
+        clock.advance(HEARTBEAT_INTERVAL * 2);
+        node.heartbeat();
+        assert!(node.peers.is_empty());
+        assert_eq!(
+            node.sink.events,
+            vec![Event::Peer(PeerEvent::Disconnected {
+                addr,
+                reason: DisconnectReason::TimedOut,
+            })]
+        );
+    }
+
+    #[test]
+    fn node_reports_bound_port() {
+        let node = node(Config::default());
+
+        assert_ne!(node.local_addr().port(), 0);
+    }
+
+    #[test]
+    fn messages_held_while_paused_are_processed_on_resume() {
+        let mut node = node(Config::default());
+        let mut client = client(&mut node);
+
+        node.handle(Command::Pause).unwrap();
+        let msg = Msg::new("while paused").unwrap();
+        client.write_all(&msg.clone().into_bytes()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        node.receive();
+        assert!(node.sink.messages.is_empty());
+
+        node.handle(Command::Resume).unwrap();
+        assert_eq!(
+            node.sink.messages,
+            vec![(msg, client.local_addr().unwrap())]
+        );
+    }
+
+    #[test]
+    fn echo_server_echoes_a_message_once() {
+        let config = Config {
+            echo_server: true,
+            ..Config::default()
+        };
+        let mut node = node(config);
+        let mut client = client(&mut node);
+
+        let msg = Msg::new("ping").unwrap();
+        client.write_all(&msg.clone().into_bytes()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        node.receive();
+
+        let mut frame = [0; msg::CAPACITY];
+        client.read_exact(&mut frame).unwrap();
+        assert!(handshake::read_hello(&frame).is_some());
+        client.read_exact(&mut frame).unwrap();
+        let echo = Msg::try_from(frame).unwrap();
+        assert_eq!(echo.text(), "ping");
+        assert_ne!(echo.uuid(), msg.uuid());
+        assert_eq!(echo.ttl(), 0);
+
+        client.write_all(&echo.into_bytes()).unwrap();
+        client.write_all(&msg.into_bytes()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        node.receive();
+        client.set_nonblocking(true).unwrap();
+        let err = client.read(&mut frame).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn command_over_control_socket_reaches_node() {
+        let path = temp_path("control");
+        let (tx, cmds) = mpsc::channel();
+        control::listen(&path, tx, msg::CAPACITY).unwrap();
+        let mut node = node(Config::default());
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"pause\n").unwrap();
+        let mut cmds = Some(cmds);
+        while node.held.is_none() {
+            node.handle_next(&mut cmds).unwrap();
+        }
+
+        let mut reply = String::new();
+        BufReader::new(client).read_line(&mut reply).unwrap();
+        assert_eq!(
+            reply,
+            format!("paused, holding up to {PAUSE_BUFFER} frames\n")
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn only_broadcasts_from_stdin_wait_for_confirmation() {
+        let path = temp_path("control");
+        let (tx, cmds) = mpsc::channel();
+        control::listen(&path, tx, msg::CAPACITY).unwrap();
+        let config = Config {
+            confirm: Confirm {
+                peers: None,
+                bytes: Some(0),
+            },
+            ..Config::default()
+        };
+        let mut node = node(config);
+
+        node.handle(Command::Broadcast(String::from("typed"), None))
+            .unwrap();
+        assert!(node.pending.is_some());
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"broadcast scripted\n").unwrap();
+        let mut cmds = Some(cmds);
+        while node.seen.is_empty() {
+            node.handle_next(&mut cmds).unwrap();
+        }
+        assert_eq!(node.seen.iter().next().unwrap().text(), "scripted");
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn stats_summarize_activity() {
+        let mut node = node(Config::default());
+        let clock = Manual::default();
+        node.clock = Box::new(clock.clone());
+        let mut client = client(&mut node);
+
+        client
+            .write_all(&Msg::new("incoming").unwrap().into_bytes())
+            .unwrap();
+        while node.sink.messages.is_empty() {
+            node.receive();
+        }
+        node.broadcast("outgoing", None).unwrap();
+        clock.advance(Duration::from_secs(90));
+
+        let stats = node.stats();
+        assert!(
+            stats.starts_with(
+                "peers: 1\npruned: 0\nuptime: 90s\nreceived: 1\nbroadcast: 1\nseen: 2\n"
+            ),
+            "{stats}"
+        );
+        assert!(
+            stats.ends_with("\ndelivered: 1\nfailed deliveries: 0"),
+            "{stats}"
+        );
+    }
+
+    #[test]
+    fn stats_tally_deliveries_across_messages() {
+        let mut node = node(Config::default());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (healthy, _healthy_client) = loopback(&listener);
+        let (broken, _broken_client) = loopback(&listener);
+        broken.shutdown().unwrap();
+        node.peers = vec![healthy, broken];
+
+        node.broadcast("first", None).unwrap();
+        node.broadcast("second", None).unwrap();
+
+        let stats = node.stats();
+        assert!(
+            stats.contains("\ndelivered: 2\nfailed deliveries: 1"),
+            "{stats}"
+        );
+    }
+
+    #[test]
+    fn stats_count_pruned_peers_and_every_message_seen() {
+        let config = Config {
+            seen_capacity: Some(1),
+            ..Config::default()
+        };
+        let mut node = node(config);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (closing, closing_client) = loopback(&listener);
+        let (broken, _broken_client) = loopback(&listener);
+        broken.shutdown().unwrap();
+        node.peers = vec![closing, broken];
+
+        node.broadcast("first", None).unwrap();
+        node.broadcast("second", None).unwrap();
+        drop(closing_client);
+        while !node.peers.is_empty() {
+            node.receive();
+        }
+
+        let stats = node.stats();
+        assert!(stats.starts_with("peers: 0\npruned: 2\n"), "{stats}");
+        assert!(stats.contains("\nseen: 2\n"), "{stats}");
+    }
+
+    #[test]
+    fn drain_grace_runs_out_on_the_node_clock() {
+        let mut node = node(Config::default());
+        let clock = Manual::default();
+        node.clock = Box::new(clock.clone());
+
+        node.handle(Command::Drain).unwrap();
+        clock.advance(DRAIN_GRACE - Duration::from_millis(1));
+        assert!(!node.drained());
+        clock.advance(Duration::from_millis(1));
+        assert!(node.drained());
+    }
+
+    #[test]
+    fn draining_node_propagates_in_flight_message_before_leaving() {
+        let mut node = node(Config::default());
+        let mut sender = client(&mut node);
+        let mut receiver = client(&mut node);
+        assert_eq!(read_hello(&mut receiver), node.local_addr());
+
+        node.handle(Command::Drain).unwrap();
+        let msg = Msg::new("in flight").unwrap();
+        sender.write_all(&msg.clone().into_bytes()).unwrap();
+        while !node.drained() {
+            node.receive();
+        }
+        node.leave();
+
+        let mut frame = [0; msg::CAPACITY];
+        receiver.read_exact(&mut frame).unwrap();
+        assert_eq!(Msg::try_from(frame).unwrap(), msg);
+        assert_eq!(receiver.read(&mut frame).unwrap(), 0);
+    }
+
+    #[test]
+    fn seen_messages_survive_a_restart_with_a_state_file() {
+        let path = temp_path("state");
+        let args = [String::from("--state-file"), path.display().to_string()];
+        let config = Config::from_args(&args).unwrap();
+        let bind = |config: &Config| node(config.clone());
+
+        let mut node = bind(&config);
+        let msg = Msg::new("before restart").unwrap();
+        node.seen.push(msg.clone());
+        node.leave();
+        let restarted = bind(&config);
+        assert!(restarted.seen.contains(&msg));
+        assert!(restarted.sink.events.is_empty());
+
+        fs::write(&path, "corrupt").unwrap();
+        let restarted = bind(&config);
+        assert!(restarted.seen.is_empty());
+        assert!(matches!(
+            &restarted.sink.events[..],
+            [Event::Notice(notice)] if notice.starts_with("ignoring state file")
+        ));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn leaving_shuts_down_every_peer() {
+        let mut node = node(Config::default());
+        let mut remotes: Vec<_> = (0..3).map(|_| client(&mut node)).collect();
+
+        node.leave();
+
+        assert!(node.peers.is_empty());
+        let mut frame = [0; msg::CAPACITY];
+        for remote in &mut remotes {
+            assert_eq!(read_hello(remote), node.local_addr());
+            assert_eq!(remote.read(&mut frame).unwrap(), 0);
+        }
+    }
+
+    #[test]
+    fn broadcast_returns_uuid_of_sent_message() {
+        let mut node = node(Config::default());
+        let mut client = client(&mut node);
+
+        assert_eq!(read_hello(&mut client), node.local_addr());
+        let uuid = node.broadcast("track me", None).unwrap();
+
+        let mut frame = [0; msg::CAPACITY];
+        client.read_exact(&mut frame).unwrap();
+        assert_eq!(Msg::try_from(frame).unwrap().uuid(), uuid);
+    }
+
+    #[test]
+    fn larger_capacity_carries_longer_messages() {
+        let config = Config {
+            capacity: Some(1024),
+            ..Config::default()
+        };
+        let mut node = node(config);
+        let mut client = client(&mut node);
+
+        let mut frame = [0; 1024];
+        client.read_exact(&mut frame).unwrap();
+        assert_eq!(handshake::read_hello(&frame), Some(node.local_addr()));
+        let text = "a".repeat(msg::MAX_TEXT + 1);
+        let uuid = node.broadcast(text.clone(), None).unwrap();
+        client.read_exact(&mut frame).unwrap();
+        let msg = Msg::try_from(&frame[..]).unwrap();
+        assert_eq!((msg.uuid(), msg.text()), (uuid, &*text));
+
+        let over = "a".repeat(msg::max_text(1024) + 1);
+        assert!(matches!(
+            Command::parse(&format!("encode {over}"), 1024),
+            Err(ParseCommandError::MessageTooLong { max, .. }) if max == msg::max_text(1024)
+        ));
+        assert!(matches!(
+            Config::from_args(&[String::from("--capacity"), String::from("16")]),
+            Err(ConfigError::CapacityOutOfRange(16))
+        ));
+    }
+
+    #[test]
+    fn ready_is_reported_once_at_the_target() {
+        let args = [String::from("--ready-at"), String::from("2")];
+        let config = Config::from_args(&args).unwrap();
+        let mut node = node(config);
+
+        let _clients: Vec<_> = (0..3).map(|_| client(&mut node)).collect();
+
+        let ready: Vec<_> = node
+            .sink
+            .events
+            .iter()
+            .filter(|ev| matches!(ev, Event::Ready { .. }))
+            .collect();
+        assert_eq!(ready, vec![&Event::Ready { peers: 2 }]);
+    }
+
+    #[test]
+    fn dropped_peer_is_redialed_unless_disconnected() {
+        let mut remote = node(Config::default());
+        let mut node = node(Config::default());
+        let clock = Manual::default();
+        node.clock = Box::new(clock.clone());
+        let connect = |node: &mut Node<Recorder>, remote: &mut Node<Recorder>| {
+            while remote.peers.is_empty() {
+                remote.accept_incoming();
+            }
+            while node.peers[0].advertised().is_none() {
+                node.receive();
+            }
+            node.redial();
+        };
+
+        node.dial(vec![remote.local_addr()], None).unwrap();
+        connect(&mut node, &mut remote);
+        remote.peers.clear();
+        while !node.peers.is_empty() {
+            node.receive();
+        }
+        node.redial();
+        assert!(node.peers.is_empty());
+
+        clock.advance(redial::BASE_DELAY);
+        node.redial();
+        assert_eq!(node.connected_count(), 1);
+        connect(&mut node, &mut remote);
+
+        node.handle(Command::Disconnect).unwrap();
+        while !node.peers.is_empty() {
+            node.receive();
+        }
+        clock.advance(redial::MAX_DELAY);
+        node.redial();
+        assert!(node.peers.is_empty());
+    }
+
+    /// Returns an address of ours other than loopback, if there is one.
+    fn local_ip() -> Option<IpAddr> {
+        let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+        // Picks the address routing there without sending anything.
+        socket.connect("198.51.100.1:9").ok()?;
+        let ip = socket.local_addr().ok()?.ip();
+        (!ip.is_loopback()).then_some(ip)
+    }
+
+    #[test]
+    fn connections_to_ourselves_are_dropped_whatever_the_address() {
+        let mut node = Node::bind(
+            "0.0.0.0:0".parse().unwrap(),
+            Config::default(),
+            Recorder::default(),
+        )
+        .unwrap();
+        let clock = Manual::default();
+        node.clock = Box::new(clock.clone());
+        let port = node.local_addr().port();
+        let mut looped = vec![SocketAddr::from(([127, 0, 0, 1], port))];
+        looped.extend(local_ip().map(|ip| SocketAddr::new(ip, port)));
+
+        for (i, addr) in looped.into_iter().enumerate() {
+            node.handle(Command::Connect(vec![addr], None)).unwrap();
+            while node.peers.len() < 2 {
+                node.accept_incoming();
+            }
+            // Both ends of the link get our own hello.
+            while !node.peers.is_empty() {
+                node.receive();
+            }
+            let rejected = node
+                .sink
+                .events
+                .iter()
+                .filter(|ev| {
+                    matches!(
+                        ev,
+                        Event::Rejected { reason, .. } if reason == "connected to ourselves"
+                    )
+                })
+                .count();
+            assert_eq!(rejected, 2 * (i + 1), "{addr}");
+
+            clock.advance(redial::MAX_DELAY);
+            node.redial();
+            assert!(node.peers.is_empty(), "{addr}");
+        }
+    }
+
+    #[test]
+    fn emoji_are_limited_by_bytes_and_characters() {
+        let emoji = "🦀".repeat(msg::MAX_TEXT / 4 + 1);
+        let err = format!("encode {emoji}").parse::<Command>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "message exceeds max, {} bytes ({} characters) where at most {} bytes fit",
+                emoji.len(),
+                msg::MAX_TEXT / 4 + 1,
+                msg::MAX_TEXT
+            )
+        );
+
+        let config = Config {
+            max_chars: Some(3),
+            ..Config::default()
+        };
+        let mut node = node(config);
+        assert!(node.broadcast("🦀🦀🦀", None).is_ok());
+        let err = node.broadcast("🦀🦀🦀🦀", None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "message has 4 characters (16 bytes) where at most 3 are allowed"
+        );
+    }
+
+    #[test]
+    fn nick_command_sets_nick_of_sent_messages() {
+        let mut node = node(Config::default());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (peer, mut client) = loopback(&listener);
+        node.peers = vec![peer];
+
+        let Ok(cmd) = "nick alice".parse() else {
+            panic!("expected a nick command");
+        };
+        node.handle(cmd).unwrap();
+        node.broadcast("named", None).unwrap();
+        node.handle(Command::Nick(String::new())).unwrap();
+        node.broadcast("anonymous", None).unwrap();
+        node.handle(Command::Nick("a".repeat(msg::MAX_NICK + 1)))
+            .unwrap();
+
+        client.set_nonblocking(false).unwrap();
+        let mut frame = [0; msg::CAPACITY];
+        client.read_exact(&mut frame).unwrap();
+        assert_eq!(Msg::try_from(frame).unwrap().nick(), Some("alice"));
+        client.read_exact(&mut frame).unwrap();
+        assert_eq!(Msg::try_from(frame).unwrap().nick(), None);
+        assert_eq!(node.config.nick, None);
+    }
+
+    #[test]
+    fn repeated_write_dumps_are_rate_limited() {
+        let config = Config {
+            hexdump: true,
+            ..Config::default()
+        };
+        let mut node = node(config);
+        node.clock = Box::new(Manual::default());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (peer, _client) = loopback(&listener);
+        let to = peer.addr();
+        node.peers = vec![peer];
+
+        let msg = Msg::new("again").unwrap();
+        for _ in 0..2 {
+            node.whisper(to, msg.clone()).unwrap();
+        }
+
+        let dumps = node
+            .sink
+            .events
+            .iter()
+            .filter(
+                |ev| matches!(ev, Event::Notice(notice) if notice.starts_with("frame written to")),
+            )
+            .count();
+        assert_eq!(dumps, 1);
+    }
+
+    #[test]
+    fn whisper_reaches_only_the_addressed_peer() {
+        let mut node = node(Config::default());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut clients = Vec::new();
+        for _ in 0..3 {
+            let (peer, client) = loopback(&listener);
+            node.peers.push(peer);
+            clients.push(client);
+        }
+        let to = node.peers[1].addr();
+
+        node.handle(Command::Whisper(to, Box::new(Msg::new("psst").unwrap())))
+            .unwrap();
+        node.handle(Command::Whisper(
+            "127.0.0.1:1".parse().unwrap(),
+            Box::new(Msg::new("lost").unwrap()),
+        ))
+        .unwrap();
+
+        let mut frame = [0; msg::CAPACITY];
+        clients[1].set_nonblocking(false).unwrap();
+        clients[1].read_exact(&mut frame).unwrap();
+        let whispered = Msg::try_from(frame).unwrap();
+        assert_eq!(whispered.text(), "psst");
+        assert_eq!(whispered.ttl(), 0);
+        for i in [0, 2] {
+            let err = clients[i].read(&mut frame).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::WouldBlock);
+        }
+        assert_eq!(
+            node.sink.events.last(),
+            Some(&Event::Notice(String::from(
+                "no peer 127.0.0.1:1, not sending"
+            )))
+        );
+    }
+
+    #[test]
+    fn reconnect_resolves_host_afresh() {
+        let old = TcpListener::bind("127.0.0.1:0").unwrap();
+        let new = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut node = node(Config::default());
+        let resolver = Fixed::default();
+        node.resolver = Box::new(resolver.clone());
+
+        let host = Some(String::from("peer.example:9000"));
+        let old_addr = old.local_addr().unwrap();
+        node.handle(Command::Connect(vec![old_addr], host)).unwrap();
+        resolver.set(vec![new.local_addr().unwrap()]);
+        node.handle(Command::Reconnect(old_addr)).unwrap();
+
+        assert_eq!(node.peers.len(), 1);
+        assert_eq!(node.peers[0].addr(), new.local_addr().unwrap());
+        assert_eq!(node.peers[0].host(), Some("peer.example:9000"));
+        new.accept().unwrap();
+    }
+
+    #[test]
+    fn startup_is_announced_once() {
+        let mut quiet = node(Config::default());
+        quiet.announce();
+
+        assert_eq!(
+            quiet.sink.events,
+            vec![Event::Listening {
+                addr: quiet.local_addr(),
+                config: None
+            }]
+        );
+
+        let config = Config {
+            verbose: true,
+            ..Config::default()
+        };
+        let mut verbose = node(config);
+        verbose.announce();
+
+        let [
+            Event::Listening {
+                config: Some(config),
+                ..
+            },
+        ] = &verbose.sink.events[..]
+        else {
+            panic!("expected a single verbose startup event");
+        };
+        assert!(config.contains("verbose: true"));
+    }
+
+    #[test]
+    fn config_takes_bootstrap_peers_and_seen_capacity() {
+        let args =
+            |args: &str| -> Vec<String> { args.split_whitespace().map(String::from).collect() };
+
+        let config = Config::from_args(&args(
+            "run --listen 127.0.0.1:9000 --connect 127.0.0.1:9001 \
+             --seen-capacity 64 --connect [::1]:9002",
+        ))
+        .unwrap();
+        assert_eq!(
+            config.bootstrap,
+            vec![
+                "127.0.0.1:9001".parse::<SocketAddr>().unwrap(),
+                "[::1]:9002".parse().unwrap()
+            ]
+        );
+        assert_eq!(config.seen_capacity, Some(64));
+
+        assert!(matches!(
+            Config::from_args(&args("run --connect")),
+            Err(ConfigError::MissingValue(flag)) if flag == "--connect"
+        ));
+        assert!(matches!(
+            Config::from_args(&args("run --connect nowhere")),
+            Err(ConfigError::AddrParseError(_))
+        ));
+        assert!(matches!(
+            Config::from_args(&args("run --seen-capacity many")),
+            Err(ConfigError::ParseIntError(_))
+        ));
+    }
+
+    #[test]
+    fn config_rejects_unknown_flags_but_not_positional_arguments() {
+        let args =
+            |args: &str| -> Vec<String> { args.split_whitespace().map(String::from).collect() };
+
+        assert!(Config::from_args(&args("send 127.0.0.1:9000 hello --nick bob")).is_ok());
+        assert!(Config::from_args(&args("recv 127.0.0.1:9000 --count 3")).is_ok());
+        assert!(matches!(
+            Config::from_args(&args("run --listen 127.0.0.1:9000 --nik bob")),
+            Err(ConfigError::UnknownFlag(flag)) if flag == "--nik"
+        ));
+    }
+
+    #[test]
+    fn short_id_must_match_exactly_one_seen_message() {
+        let uuid = |s: &str| Uuid::parse_str(s).unwrap();
+        let mut seen = DedupQueue::new(16);
+        seen.push(
+            Msg::from_parts("one", uuid("3f2a9c1e-0000-4000-8000-000000000001"), None).unwrap(),
+        );
+        seen.push(
+            Msg::from_parts("two", uuid("3f2a9c1e-0000-4000-8000-000000000002"), None).unwrap(),
+        );
+
+        assert_eq!(
+            find_seen(&seen, "3f2a9c1e"),
+            Err(IdError::Ambiguous {
+                id: String::from("3f2a9c1e"),
+                count: 2
+            })
+        );
+        let full = id_arg("3f2a9c1e-0000-4000-8000-000000000002").unwrap();
+        assert_eq!(find_seen(&seen, &full).unwrap().text(), "two");
+        assert!(matches!(find_seen(&seen, "00"), Err(IdError::Unknown(_))));
+        assert!("forget 3F2A9C1E".parse::<Command>().is_ok());
+        assert!("forget nope".parse::<Command>().is_err());
+    }
+}
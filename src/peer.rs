@@ -0,0 +1,604 @@
+use std::{
+    fmt,
+    io::{self, Write},
+    net::{SocketAddr, TcpStream},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    fragment::{self, Reassembly},
+    handshake,
+    msg::CAPACITY,
+    transport::Transport,
+};
+
+/// Most bytes held for a peer that doesn't take them, before it is given up
+/// on as unresponsive.
+pub const MAX_UNSENT: usize = 1 << 20;
+
+/// How long the fragments of a message are held for the rest to arrive.
+pub const FRAGMENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Number of bytes of the length before a length-prefixed frame.
+const LEN_SIZE: usize = 2;
+
+/// How frames are laid out on the connection to a peer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// Every frame is padded with `0`s to the frame size.
+    #[default]
+    Fixed,
+    /// Every frame is its length as a big-endian `u16` followed by that
+    /// many bytes, leaving out the padding.
+    LengthPrefixed,
+}
+
+/// A connected peer: its stream along with what is known about it.
+///
+/// The stream is a [`TcpStream`] but for tests, see [`Transport`].
+///
+/// The address is resolved once on connecting, so it is still known after
+/// the connection has closed.
+///
+/// Since TCP doesn't preserve message boundaries, bytes read from the
+/// peer are buffered until they make up a whole frame, of `CAPACITY` bytes
+/// unless set otherwise with [`Peer::with_frame_size`].
+///
+/// Once the peer's hello arrives, see [`handshake::hello`], it is known by
+/// the address it listens on rather than the one it connected from.
+///
+/// Frames are fixed-size at first. When both ends offer length-prefixed
+/// frames, see [`Peer::with_framing`], each switches to sending those once
+/// it has the other's hello, announcing it with [`handshake::switch`].
+#[derive(Debug)]
+pub struct Peer<T = TcpStream> {
+    stream: T,
+    addr: SocketAddr,
+    /// The address the peer listens on, once its hello has arrived.
+    advertised: Option<SocketAddr>,
+    /// The nonce in the peer's hello, if it sent one.
+    nonce: Option<u64>,
+    /// Whether we dialed the peer, rather than it us.
+    dialed: bool,
+    /// The host name the peer was dialed by, if it was dialed by name.
+    host: Option<String>,
+    nick: Option<String>,
+    /// When something was last read from the peer, or when it joined a
+    /// node if nothing was read yet.
+    last_seen: Option<Instant>,
+    /// Bytes read that don't make up a whole frame yet.
+    pending: Vec<u8>,
+    /// Bytes written that the stream didn't take yet.
+    unsent: Vec<u8>,
+    frame_size: usize,
+    /// The framing offered to the peer in our hello.
+    framing: Framing,
+    /// The framing of the frames written to the peer.
+    outbound: Framing,
+    /// The framing of the frames read from the peer.
+    inbound: Framing,
+    /// Fragments of messages that didn't all arrive yet.
+    fragments: Reassembly,
+}
+
+/// How recently a peer was heard from, relative to the heartbeat interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    /// Heard from within one interval.
+    Alive,
+    /// Heard from within two intervals.
+    Stale,
+    /// Not heard from for longer than that.
+    Dead,
+}
+
+impl Health {
+    /// Returns the health of a peer last heard from `idle` ago.
+    pub fn of(idle: Duration, interval: Duration) -> Self {
+        if idle <= interval {
+            Health::Alive
+        } else if idle <= interval * 2 {
+            Health::Stale
+        } else {
+            Health::Dead
+        }
+    }
+}
+
+/// What to do about an IO error on the connection to a peer.
+///
+/// Reads and writes of every peer are classified alike, so a peer is only
+/// ever dropped for an error that leaves its connection unusable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// Try again right away, as the call was only interrupted.
+    Retry,
+    /// Try again on a later pass, once the stream is ready.
+    Wait,
+    /// Drop the peer, as the connection is broken.
+    Drop,
+}
+
+impl Recovery {
+    /// Returns what to do about `err`.
+    pub fn of(err: &io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::Interrupted => Recovery::Retry,
+            io::ErrorKind::WouldBlock => Recovery::Wait,
+            _ => Recovery::Drop,
+        }
+    }
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Health::Alive => "alive",
+            Health::Stale => "stale",
+            Health::Dead => "dead",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl<T: Transport> Peer<T> {
+    /// Wraps `stream`, connected to the peer at `addr`.
+    ///
+    /// The address is given rather than asked from `stream`, since
+    /// a stream dialed through a proxy is connected to the proxy instead.
+    pub fn new(stream: T, addr: SocketAddr) -> Self {
+        Self {
+            stream,
+            addr,
+            advertised: None,
+            nonce: None,
+            dialed: false,
+            host: None,
+            nick: None,
+            last_seen: None,
+            pending: Vec::new(),
+            unsent: Vec::new(),
+            frame_size: CAPACITY,
+            framing: Framing::Fixed,
+            outbound: Framing::Fixed,
+            inbound: Framing::Fixed,
+            fragments: Reassembly::new(FRAGMENT_TIMEOUT, CAPACITY),
+        }
+    }
+
+    /// Splits what the peer sends into frames of `frame_size` bytes, and
+    /// sizes our hello to match.
+    pub fn with_frame_size(self, frame_size: usize) -> Self {
+        Self {
+            frame_size,
+            fragments: Reassembly::new(FRAGMENT_TIMEOUT, frame_size),
+            ..self
+        }
+    }
+
+    /// Counts the peer as heard from at `now`, e.g. as it joins a node, so
+    /// its health is judged from then on.
+    pub fn with_last_seen(self, now: Instant) -> Self {
+        Self {
+            last_seen: Some(now),
+            ..self
+        }
+    }
+
+    /// Offers the peer `framing` in our hello, to be used in both
+    /// directions if the peer offers it too.
+    pub fn with_framing(self, framing: Framing) -> Self {
+        Self { framing, ..self }
+    }
+
+    /// Returns the framing of the frames written to the peer.
+    pub fn outbound(&self) -> Framing {
+        self.outbound
+    }
+
+    /// Wraps `stream`, dialed by us to the peer at `addr`, which was
+    /// resolved from `host` if it was dialed by name.
+    pub fn dialed(stream: T, addr: SocketAddr, host: Option<String>) -> Self {
+        Self {
+            dialed: true,
+            host,
+            ..Self::new(stream, addr)
+        }
+    }
+
+    /// Returns `true` if we dialed the peer, rather than it us.
+    pub fn is_dialed(&self) -> bool {
+        self.dialed
+    }
+
+    /// Returns the host name the peer was dialed by, if it was dialed by name.
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    /// Returns the port the connection was dialed from, which both ends
+    /// of it agree on, or [`None`] if it isn't known.
+    pub fn dialer_port(&self) -> Option<u16> {
+        if self.dialed {
+            self.stream.local_addr().ok().map(|addr| addr.port())
+        } else {
+            Some(self.addr.port())
+        }
+    }
+
+    /// Returns the address of the peer.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the address the peer listens on, once its hello has arrived.
+    pub fn advertised(&self) -> Option<SocketAddr> {
+        self.advertised
+    }
+
+    /// Returns the nonce in the peer's hello, once it has arrived and if
+    /// the peer sent one, see [`handshake::hello`].
+    pub fn nonce(&self) -> Option<u64> {
+        self.nonce
+    }
+
+    /// Returns the address the peer listens on if it advertised one, and
+    /// the address it is connected from otherwise.
+    pub fn identity(&self) -> SocketAddr {
+        self.advertised.unwrap_or(self.addr)
+    }
+
+    /// Sends the peer our hello, advertising that we listen on `listening`
+    /// and carrying our `nonce`.
+    pub fn greet(&mut self, listening: SocketAddr, nonce: u64) -> io::Result<()> {
+        let hello = handshake::hello(listening, nonce, self.frame_size, self.framing);
+        self.write_all(&hello)
+    }
+
+    /// Returns the nickname of the peer, if it is known.
+    pub fn nick(&self) -> Option<&str> {
+        self.nick.as_deref()
+    }
+
+    /// Returns when something was last read from the peer, or when it
+    /// joined a node if nothing was read yet, see [`Peer::with_last_seen`].
+    pub fn last_seen(&self) -> Option<Instant> {
+        self.last_seen
+    }
+
+    /// Returns the health of the peer as of `now`, see [`Health::of`].
+    ///
+    /// A peer never heard from is alive, having had no chance to go quiet.
+    pub fn health(&self, now: Instant, interval: Duration) -> Health {
+        let quiet = self
+            .last_seen
+            .map_or(Duration::ZERO, |seen| now.saturating_duration_since(seen));
+        Health::of(quiet, interval)
+    }
+
+    /// Reads whatever the peer has sent so far into the frame buffer as of
+    /// `now`, returning the number of bytes read; `0` means the peer
+    /// disconnected.
+    pub fn receive(&mut self, now: Instant) -> io::Result<usize> {
+        let mut chunk = [0; CAPACITY];
+        let n = self.stream.read(&mut chunk)?;
+        self.pending.extend_from_slice(&chunk[..n]);
+        self.last_seen = Some(now);
+        Ok(n)
+    }
+
+    /// Takes the next whole frame out of the bytes received, if there is one.
+    ///
+    /// Hellos are taken in along the way rather than returned, and
+    /// heartbeats skipped, having served their purpose by being received.
+    /// Fragments are held until the last of their message arrives, which
+    /// is then returned whole, see [`fragment::split`].
+    ///
+    /// Length-prefixed frames are padded back to the frame size, so they
+    /// are returned just like fixed-size ones. Fragments arriving are timed
+    /// from `now`, see [`Peer::expire_fragments`].
+    pub fn next_frame(&mut self, now: Instant) -> Option<Vec<u8>> {
+        loop {
+            let frame = self.take_frame()?;
+            if handshake::is_heartbeat(&frame) {
+                continue;
+            }
+            if handshake::is_switch(&frame) {
+                // Only a peer we offered the framing to switches to it.
+                if self.framing == Framing::LengthPrefixed {
+                    self.inbound = Framing::LengthPrefixed;
+                }
+                continue;
+            }
+            if let Some(fragment) = fragment::read(&frame) {
+                match self.fragments.add(fragment, now) {
+                    Some(msg) => return Some(msg),
+                    None => continue,
+                }
+            }
+
+            let Some(advertised) = handshake::read_hello(&frame) else {
+                return Some(frame);
+            };
+            // A node listening on all interfaces advertises the unspecified
+            // address, for which the one it connected from is the best guess.
+            self.advertised = Some(if advertised.ip().is_unspecified() {
+                SocketAddr::new(self.addr.ip(), advertised.port())
+            } else {
+                advertised
+            });
+            self.nonce = handshake::read_nonce(&frame);
+            let offered = handshake::read_framing(&frame);
+            if self.framing == Framing::LengthPrefixed
+                && offered == Some(Framing::LengthPrefixed)
+                && self.outbound == Framing::Fixed
+            {
+                // Goes out behind everything written so far, and before
+                // anything written from now on.
+                self.unsent
+                    .extend_from_slice(&handshake::switch(self.frame_size));
+                self.outbound = Framing::LengthPrefixed;
+            }
+        }
+    }
+
+    /// Takes the bytes of the next frame out of those received, in the
+    /// framing the peer sends, if they are all there.
+    ///
+    /// A length-prefixed frame longer than the frame size is cut to size,
+    /// so it is as malformed as any other corrupt frame.
+    fn take_frame(&mut self) -> Option<Vec<u8>> {
+        match self.inbound {
+            Framing::Fixed => {
+                if self.pending.len() < self.frame_size {
+                    return None;
+                }
+                Some(self.pending.drain(..self.frame_size).collect())
+            }
+            Framing::LengthPrefixed => {
+                let len = self.pending.get(..LEN_SIZE)?;
+                let len = usize::from(u16::from_be_bytes([len[0], len[1]]));
+                if self.pending.len() < LEN_SIZE + len {
+                    return None;
+                }
+                let mut frame: Vec<_> = self
+                    .pending
+                    .drain(..LEN_SIZE + len)
+                    .skip(LEN_SIZE)
+                    .collect();
+                frame.resize(self.frame_size, 0);
+                Some(frame)
+            }
+        }
+    }
+
+    /// Drops the messages whose fragments didn't all arrive in time, as of
+    /// `now`, returning how many were dropped.
+    pub fn expire_fragments(&mut self, now: Instant) -> usize {
+        self.fragments.expire(now)
+    }
+
+    /// Returns the number of bytes written that the stream didn't take yet.
+    pub fn unsent(&self) -> usize {
+        self.unsent.len()
+    }
+
+    /// Writes as much as the stream takes of the bytes it didn't take
+    /// before, keeping the rest for later, see [`Recovery`].
+    ///
+    /// Fails if the connection is broken, or if more than [`MAX_UNSENT`]
+    /// bytes are left.
+    pub fn write_unsent(&mut self) -> io::Result<()> {
+        while !self.unsent.is_empty() {
+            match self.stream.write(&self.unsent) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.unsent.drain(..n);
+                }
+                Err(err) => match Recovery::of(&err) {
+                    Recovery::Retry => (),
+                    Recovery::Wait => break,
+                    Recovery::Drop => return Err(err),
+                },
+            }
+        }
+
+        if self.unsent.len() > MAX_UNSENT {
+            return Err(io::Error::other(format!(
+                "more than {MAX_UNSENT} bytes not taken"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Closes the connection in both directions.
+    pub fn shutdown(&self) -> io::Result<()> {
+        self.stream.shutdown()
+    }
+}
+
+impl TryFrom<TcpStream> for Peer {
+    type Error = io::Error;
+
+    /// Wraps an accepted `stream`, failing if it has already closed.
+    fn try_from(stream: TcpStream) -> Result<Self, Self::Error> {
+        let addr = stream.peer_addr()?;
+        Ok(Self::new(stream, addr))
+    }
+}
+
+/// Writes go behind the bytes the stream didn't take yet, so frames are
+/// never interleaved, and are taken whole unless the connection is broken.
+///
+/// Once length-prefixed, every `frame_size` bytes written are taken as a
+/// frame, sent without its padding.
+impl<T: Transport> Write for Peer<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.outbound {
+            Framing::Fixed => self.unsent.extend_from_slice(buf),
+            Framing::LengthPrefixed => {
+                for frame in buf.chunks(self.frame_size) {
+                    let len = frame
+                        .iter()
+                        .rposition(|b| *b != 0)
+                        .map_or(0, |last| last + 1);
+                    // Frames are at most `MAX_CAPACITY` long, which fits a `u16`.
+                    self.unsent.extend_from_slice(&(len as u16).to_be_bytes());
+                    self.unsent.extend_from_slice(&frame[..len]);
+                }
+            }
+        }
+        self.write_unsent()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_unsent()?;
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{self, ErrorKind, Write},
+        iter,
+        net::{SocketAddr, TcpListener, TcpStream},
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        msg::{CAPACITY, Msg},
+        peer::{Framing, Peer, Recovery},
+        transport::Mock,
+    };
+
+    /// Passes the bytes written to `from` on to `to`, as if sent over the
+    /// connection, returning them.
+    fn pass(from: &Mock, to: &Mock) -> Vec<u8> {
+        let bytes = from.written();
+        to.feed(&bytes);
+        bytes
+    }
+
+    /// Returns the frames of everything passed to `peer`, then writes what
+    /// it holds back.
+    fn read_all(peer: &mut Peer<Mock>) -> Vec<Vec<u8>> {
+        let now = Instant::now();
+        while peer.receive(now).is_ok() {}
+        let frames = iter::from_fn(|| peer.next_frame(now)).collect();
+        peer.write_unsent().unwrap();
+        frames
+    }
+
+    #[test]
+    fn peer_keeps_address_and_tracks_reads() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, addr) = listener.accept().unwrap();
+        let connected = Instant::now();
+        let mut peer = Peer::try_from(stream).unwrap().with_last_seen(connected);
+
+        client.write_all(&[7; CAPACITY + 1]).unwrap();
+        let read = connected + Duration::from_secs(1);
+        let mut received = 0;
+        while received < CAPACITY + 1 {
+            received += peer.receive(read).unwrap();
+        }
+        peer.shutdown().unwrap();
+
+        assert_eq!(peer.addr(), addr);
+        assert_eq!(peer.nick(), None);
+        assert_eq!(peer.last_seen(), Some(read));
+        assert_eq!(peer.next_frame(read), Some(vec![7; CAPACITY]));
+        assert_eq!(peer.next_frame(read), None);
+    }
+
+    #[test]
+    fn frames_are_split_at_the_frame_size() {
+        let addr: SocketAddr = "10.0.0.2:4000".parse().unwrap();
+        let mock = Mock::new("10.0.0.1:4000".parse().unwrap(), addr);
+        let mut peer = Peer::new(mock.clone(), addr).with_frame_size(1024);
+
+        peer.greet("10.0.0.1:4000".parse().unwrap(), 1).unwrap();
+        mock.feed(&[7; 1024 + CAPACITY]);
+        let now = Instant::now();
+        while peer.receive(now).is_ok() {}
+
+        assert_eq!(mock.written().len(), 1024);
+        assert_eq!(peer.next_frame(now), Some(vec![7; 1024]));
+        assert_eq!(peer.next_frame(now), None);
+    }
+
+    #[test]
+    fn framing_is_negotiated_and_frames_round_trip_in_both() {
+        let a_addr: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let b_addr: SocketAddr = "10.0.0.2:4000".parse().unwrap();
+        for (a_offers, b_offers, agreed) in [
+            (Framing::Fixed, Framing::Fixed, Framing::Fixed),
+            (Framing::LengthPrefixed, Framing::Fixed, Framing::Fixed),
+            (Framing::Fixed, Framing::LengthPrefixed, Framing::Fixed),
+            (
+                Framing::LengthPrefixed,
+                Framing::LengthPrefixed,
+                Framing::LengthPrefixed,
+            ),
+        ] {
+            let (a_mock, b_mock) = (Mock::new(a_addr, b_addr), Mock::new(b_addr, a_addr));
+            let mut a = Peer::new(a_mock.clone(), b_addr).with_framing(a_offers);
+            let mut b = Peer::new(b_mock.clone(), a_addr).with_framing(b_offers);
+            a.greet(a_addr, 1).unwrap();
+            b.greet(b_addr, 2).unwrap();
+            // Hellos first, then any switches they lead to.
+            for _ in 0..2 {
+                pass(&a_mock, &b_mock);
+                pass(&b_mock, &a_mock);
+                assert!(read_all(&mut a).is_empty());
+                assert!(read_all(&mut b).is_empty());
+            }
+            assert_eq!((a.outbound(), b.outbound()), (agreed, agreed));
+            assert_eq!(a.advertised(), Some(b_addr));
+            assert_eq!(a.nonce(), Some(2));
+
+            let frame = Msg::new("framed").unwrap().into_bytes();
+            a.write_all(&frame).unwrap();
+            let written = pass(&a_mock, &b_mock);
+            assert_eq!(written.len() < CAPACITY, agreed == Framing::LengthPrefixed);
+            assert_eq!(read_all(&mut b), vec![frame]);
+        }
+    }
+
+    #[test]
+    fn io_errors_are_retried_waited_out_or_fatal() {
+        for (kind, recovery) in [
+            (ErrorKind::Interrupted, Recovery::Retry),
+            (ErrorKind::WouldBlock, Recovery::Wait),
+            (ErrorKind::BrokenPipe, Recovery::Drop),
+            (ErrorKind::ConnectionReset, Recovery::Drop),
+        ] {
+            assert_eq!(Recovery::of(&io::Error::from(kind)), recovery);
+        }
+    }
+
+    #[test]
+    fn blocked_writes_are_kept_until_the_stream_takes_them() {
+        let addr: SocketAddr = "10.0.0.2:4000".parse().unwrap();
+        let mock = Mock::new("10.0.0.1:4000".parse().unwrap(), addr);
+        let mut peer = Peer::new(mock.clone(), addr);
+
+        mock.fail(ErrorKind::Interrupted);
+        peer.write_all(b"first").unwrap();
+        assert_eq!(mock.written(), b"first");
+
+        mock.fail(ErrorKind::WouldBlock);
+        peer.write_all(b"second").unwrap();
+        assert_eq!(peer.unsent(), 6);
+        assert!(mock.written().is_empty());
+        peer.write_unsent().unwrap();
+        assert_eq!(mock.written(), b"second");
+
+        mock.fail(ErrorKind::ConnectionReset);
+        let err = peer.write_all(b"third").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+    }
+}
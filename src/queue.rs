@@ -1,4 +1,9 @@
-use std::collections::VecDeque;
+use std::{
+    collections::{HashSet, VecDeque, vec_deque},
+    hash::Hash,
+    mem,
+    str::FromStr,
+};
 
 /// A simple queue with fixed capacity.
 ///
@@ -6,17 +11,60 @@ use std::collections::VecDeque;
 /// elements can be pushed as a normal [`VecDeque`], but when at `capacity`,
 /// pushing another item to the back of the queue results in in
 /// item at the front being automatically popped.
+///
+/// The capacity is at least `1`; a queue created with capacity `0` holds
+/// a single element, as one that holds none would forget every element
+/// as soon as it is pushed.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Queue<T> {
     capacity: usize,
+    eviction: Eviction,
     items: VecDeque<T>,
 }
 
+/// Which element a full [`Queue`] evicts once another is pushed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Eviction {
+    /// Evict the element pushed longest ago.
+    #[default]
+    Fifo,
+    /// Evict the element pushed or touched longest ago, see [`Queue::touch`].
+    Lru,
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("unknown eviction policy `{0}`, expected `fifo` or `lru`")]
+pub struct ParseEvictionError(String);
+
+impl FromStr for Eviction {
+    type Err = ParseEvictionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fifo" => Ok(Eviction::Fifo),
+            "lru" => Ok(Eviction::Lru),
+            s => Err(ParseEvictionError(s.to_string())),
+        }
+    }
+}
+
+/// Something owning memory on the heap beyond its own size.
+pub trait HeapSize {
+    /// Returns the number of bytes the value owns on the heap.
+    fn heap_bytes(&self) -> usize;
+}
+
 impl<T> Queue<T> {
     /// Creates an empty [`Queue`] with a given capacity.
     pub fn new(capacity: usize) -> Self {
+        Self::with_eviction(capacity, Eviction::default())
+    }
+
+    /// Creates an empty [`Queue`] with a given capacity and eviction policy.
+    pub fn with_eviction(capacity: usize, eviction: Eviction) -> Self {
         Self {
-            capacity,
+            capacity: capacity.max(1),
+            eviction,
             items: VecDeque::new(),
         }
     }
@@ -34,6 +82,82 @@ impl<T> Queue<T> {
             None
         }
     }
+
+    /// Returns an iterator over the elements from front to back.
+    pub fn iter(&self) -> vec_deque::Iter<'_, T> {
+        self.items.iter()
+    }
+
+    /// Returns the number of elements in the queue.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns `true` if the queue holds no elements.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Returns the element at the front, the next to be evicted.
+    pub fn peek_front(&self) -> Option<&T> {
+        self.items.front()
+    }
+
+    /// Returns the element at the back, the one pushed last.
+    pub fn peek_back(&self) -> Option<&T> {
+        self.items.back()
+    }
+
+    /// Removes every element, keeping the capacity.
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    /// Keeps only the elements for which `f` returns `true`, in order.
+    pub fn retain(&mut self, f: impl FnMut(&T) -> bool) {
+        self.items.retain(f);
+    }
+
+    /// Changes the capacity, returning the elements evicted from the front,
+    /// oldest first, to fit within it.
+    ///
+    /// Shrinking to `0` empties the queue, but as at construction, it holds
+    /// a single element again once one is pushed.
+    pub fn set_capacity(&mut self, capacity: usize) -> Vec<T> {
+        let evict = self.items.len().saturating_sub(capacity);
+        self.capacity = capacity.max(1);
+        self.items.drain(..evict).collect()
+    }
+}
+
+impl<T> IntoIterator for Queue<T> {
+    type Item = T;
+    type IntoIter = vec_deque::IntoIter<T>;
+
+    /// Consumes the queue into an iterator over the elements from front to back.
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Queue<T> {
+    type Item = &'a T;
+    type IntoIter = vec_deque::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T: HeapSize> Queue<T> {
+    /// Estimates the memory the queue takes up once full, in bytes.
+    ///
+    /// Room is counted for `capacity` elements, plus whatever the current
+    /// elements own on the heap.
+    pub fn memory_bytes(&self) -> usize {
+        let heap: usize = self.items.iter().map(HeapSize::heap_bytes).sum();
+        mem::size_of::<Self>() + self.capacity * mem::size_of::<T>() + heap
+    }
 }
 
 impl<T: PartialEq> Queue<T> {
@@ -41,4 +165,352 @@ impl<T: PartialEq> Queue<T> {
     pub fn contains(&self, item: &T) -> bool {
         self.items.contains(item)
     }
+
+    /// Returns `true` if the queue contains the given item and `false` otherwise.
+    ///
+    /// Under [`Eviction::Lru`], a found element is also moved to the back,
+    /// so it is evicted last.
+    pub fn touch(&mut self, item: &T) -> bool {
+        let Some(i) = self.items.iter().position(|x| x == item) else {
+            return false;
+        };
+
+        if self.eviction == Eviction::Lru
+            && let Some(found) = self.items.remove(i)
+        {
+            self.items.push_back(found);
+        }
+
+        true
+    }
+
+    /// Removes the first element equal to `item`, keeping the order of the rest.
+    ///
+    /// Returns `true` if an element was removed and `false` otherwise.
+    pub fn remove(&mut self, item: &T) -> bool {
+        match self.items.iter().position(|x| x == item) {
+            Some(i) => {
+                self.items.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A [`Queue`] holding each element at most once, with constant-time lookups.
+///
+/// A hash set of the elements is kept alongside the queue, so
+/// [`DedupQueue::contains`] doesn't have to scan it. Pushing an element
+/// that is already held leaves the queue unchanged.
+#[derive(Debug, Clone)]
+pub struct DedupQueue<T> {
+    queue: Queue<T>,
+    index: HashSet<T>,
+}
+
+impl<T: Hash + Eq + Clone> DedupQueue<T> {
+    /// Creates an empty [`DedupQueue`] with a given capacity.
+    pub fn new(capacity: usize) -> Self {
+        Self::with_eviction(capacity, Eviction::default())
+    }
+
+    /// Creates an empty [`DedupQueue`] with a given capacity and eviction policy.
+    pub fn with_eviction(capacity: usize, eviction: Eviction) -> Self {
+        Self {
+            queue: Queue::with_eviction(capacity, eviction),
+            index: HashSet::new(),
+        }
+    }
+
+    /// Pushes an element to the back of the queue, unless it is already held.
+    ///
+    /// If the queue is full, the element at the front is popped
+    /// and returned. Otherwise, [`None`] is returned.
+    pub fn push(&mut self, item: T) -> Option<T> {
+        if !self.index.insert(item.clone()) {
+            return None;
+        }
+
+        let evicted = self.queue.push(item);
+        if let Some(evicted) = &evicted {
+            self.index.remove(evicted);
+        }
+        evicted
+    }
+
+    /// Returns an iterator over the elements from front to back.
+    pub fn iter(&self) -> vec_deque::Iter<'_, T> {
+        self.queue.iter()
+    }
+
+    /// Returns the number of elements in the queue.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Returns `true` if the queue holds no elements.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Returns the element at the front, the next to be evicted.
+    pub fn peek_front(&self) -> Option<&T> {
+        self.queue.peek_front()
+    }
+
+    /// Returns the element at the back, the one pushed or touched last.
+    pub fn peek_back(&self) -> Option<&T> {
+        self.queue.peek_back()
+    }
+
+    /// Removes every element, keeping the capacity.
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.index.clear();
+    }
+
+    /// Keeps only the elements for which `f` returns `true`, in order.
+    pub fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        let index = &mut self.index;
+        self.queue.retain(|item| {
+            let keep = f(item);
+            if !keep {
+                index.remove(item);
+            }
+            keep
+        });
+    }
+
+    /// Changes the capacity like [`Queue::set_capacity`].
+    pub fn set_capacity(&mut self, capacity: usize) -> Vec<T> {
+        let evicted = self.queue.set_capacity(capacity);
+        for item in &evicted {
+            self.index.remove(item);
+        }
+        evicted
+    }
+
+    /// Returns `true` if the queue contains the given item and `false` otherwise.
+    pub fn contains(&self, item: &T) -> bool {
+        self.index.contains(item)
+    }
+
+    /// Like [`Queue::touch`], but only scans the queue to move a found
+    /// element under [`Eviction::Lru`].
+    pub fn touch(&mut self, item: &T) -> bool {
+        if !self.contains(item) {
+            return false;
+        }
+
+        if self.queue.eviction == Eviction::Lru {
+            self.queue.touch(item);
+        }
+        true
+    }
+
+    /// Removes `item`, keeping the order of the rest.
+    ///
+    /// Returns `true` if an element was removed and `false` otherwise.
+    pub fn remove(&mut self, item: &T) -> bool {
+        self.index.remove(item) && self.queue.remove(item)
+    }
+}
+
+impl<T: HeapSize> DedupQueue<T> {
+    /// Estimates the memory the queue takes up once full, in bytes,
+    /// counting the elements twice for the copies in the hash set.
+    pub fn memory_bytes(&self) -> usize {
+        let heap: usize = self.index.iter().map(HeapSize::heap_bytes).sum();
+        self.queue.memory_bytes() + self.index.capacity() * mem::size_of::<T>() + heap
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        msg::Msg,
+        queue::{DedupQueue, Eviction, Queue},
+    };
+
+    #[test]
+    fn remove_middle_preserves_order() {
+        let mut queue = Queue::new(4);
+        queue.push(1);
+        queue.push(2);
+        queue.push(3);
+
+        assert!(queue.remove(&2));
+        assert!(!queue.contains(&2));
+        assert!(queue.contains(&1));
+        assert!(queue.contains(&3));
+        assert_eq!(queue.iter().collect::<Vec<_>>(), vec![&1, &3]);
+    }
+
+    #[test]
+    fn removing_a_missing_element_changes_nothing() {
+        let mut queue = DedupQueue::new(4);
+        queue.push(1);
+        queue.push(2);
+
+        assert!(!queue.remove(&3));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.iter().collect::<Vec<_>>(), vec![&1, &2]);
+    }
+
+    #[test]
+    fn retain_drops_from_the_queue_and_its_index() {
+        let mut queue = DedupQueue::new(8);
+        (1..=8).for_each(|i| {
+            queue.push(i);
+        });
+
+        queue.retain(|i| i % 2 == 0);
+        assert_eq!(queue.iter().collect::<Vec<_>>(), vec![&2, &4, &6, &8]);
+        assert!(!queue.contains(&3));
+        assert_eq!(queue.push(3), None);
+        assert!(queue.contains(&3));
+    }
+
+    #[test]
+    fn lru_keeps_touched_entry_that_fifo_evicts() {
+        let mut fifo = Queue::with_eviction(2, Eviction::Fifo);
+        let mut lru = Queue::with_eviction(2, Eviction::Lru);
+
+        for queue in [&mut fifo, &mut lru] {
+            queue.push(1);
+            queue.push(2);
+            assert!(queue.touch(&1));
+            queue.push(3);
+        }
+
+        assert!(!fifo.contains(&1));
+        assert_eq!(fifo.iter().collect::<Vec<_>>(), vec![&2, &3]);
+        assert!(lru.contains(&1));
+        assert_eq!(lru.iter().collect::<Vec<_>>(), vec![&1, &3]);
+    }
+
+    #[test]
+    fn iterates_front_to_back_after_overflow() {
+        let mut queue = Queue::new(3);
+        assert!(queue.is_empty());
+        for i in 1..=5 {
+            queue.push(i);
+        }
+
+        assert_eq!(queue.len(), 3);
+        assert!(!queue.is_empty());
+        assert_eq!(queue.iter().collect::<Vec<_>>(), vec![&3, &4, &5]);
+        assert_eq!((&queue).into_iter().collect::<Vec<_>>(), vec![&3, &4, &5]);
+        assert_eq!(queue.into_iter().collect::<Vec<_>>(), vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn peek_and_clear_keep_capacity() {
+        let mut queue = Queue::new(2);
+        assert_eq!(queue.peek_front(), None);
+        assert_eq!(queue.peek_back(), None);
+
+        queue.push(1);
+        assert_eq!(queue.peek_front(), Some(&1));
+        assert_eq!(queue.peek_back(), Some(&1));
+
+        queue.push(2);
+        queue.push(3);
+        assert_eq!(queue.peek_front(), Some(&2));
+        assert_eq!(queue.peek_back(), Some(&3));
+
+        queue.clear();
+        assert!(queue.is_empty());
+        assert_eq!(queue.peek_front(), None);
+        assert_eq!(queue.push(4), None);
+        assert_eq!(queue.push(5), None);
+        assert_eq!(queue.push(6), Some(4));
+    }
+
+    #[test]
+    fn growing_capacity_evicts_nothing() {
+        let mut queue = Queue::new(2);
+        queue.push(1);
+        queue.push(2);
+
+        assert!(queue.set_capacity(3).is_empty());
+        assert_eq!(queue.push(3), None);
+        assert_eq!(queue.iter().collect::<Vec<_>>(), vec![&1, &2, &3]);
+    }
+
+    #[test]
+    fn shrinking_capacity_evicts_oldest_first() {
+        let mut queue = DedupQueue::new(4);
+        for i in 1..=4 {
+            queue.push(i);
+        }
+
+        assert_eq!(queue.set_capacity(1), vec![1, 2, 3]);
+        assert!(!queue.contains(&1));
+        assert_eq!(queue.iter().collect::<Vec<_>>(), vec![&4]);
+        assert_eq!(queue.push(5), Some(4));
+    }
+
+    #[test]
+    fn shrinking_capacity_to_zero_empties() {
+        let mut queue = Queue::new(2);
+        queue.push(1);
+        queue.push(2);
+
+        assert_eq!(queue.set_capacity(0), vec![1, 2]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn zero_capacity_holds_one_element() {
+        let mut queue = DedupQueue::new(0);
+        assert_eq!(queue.push(1), None);
+        assert!(queue.contains(&1));
+
+        assert_eq!(queue.push(2), Some(1));
+        assert!(!queue.contains(&1));
+        assert!(queue.contains(&2));
+    }
+
+    #[test]
+    fn dedup_forgets_evicted_and_keeps_retained() {
+        let mut queue = DedupQueue::new(2);
+        assert_eq!(queue.push(1), None);
+        assert_eq!(queue.push(2), None);
+        assert_eq!(queue.push(3), Some(1));
+        assert_eq!(queue.push(4), Some(2));
+
+        assert!(!queue.contains(&1));
+        assert!(!queue.contains(&2));
+        assert!(queue.contains(&3));
+        assert!(queue.contains(&4));
+        assert_eq!(queue.iter().collect::<Vec<_>>(), vec![&3, &4]);
+    }
+
+    #[test]
+    fn dedup_ignores_pushing_held_element() {
+        let mut queue = DedupQueue::new(2);
+        queue.push(1);
+        queue.push(2);
+
+        assert_eq!(queue.push(1), None);
+        assert_eq!(queue.push(3), Some(1));
+        assert!(!queue.contains(&1));
+        assert_eq!(queue.iter().collect::<Vec<_>>(), vec![&2, &3]);
+    }
+
+    #[test]
+    fn memory_estimate_grows_with_messages() {
+        let mut queue = DedupQueue::new(4);
+        let empty = queue.memory_bytes();
+
+        queue.push(Msg::new("short").unwrap());
+        let one = queue.memory_bytes();
+        queue.push(Msg::new("a somewhat longer message").unwrap());
+        let two = queue.memory_bytes();
+
+        assert!(empty < one);
+        assert!(one < two);
+    }
 }
@@ -0,0 +1,92 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Suppresses repeats of identical log lines within a time window.
+///
+/// The first occurrence of a line is let through and opens a window of
+/// `window` length. Repeats within the window are counted but dropped;
+/// the first occurrence after the window closes is let through again,
+/// annotated with how many repeats were suppressed.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    window: Duration,
+    recent: HashMap<String, Window>,
+}
+
+#[derive(Debug, Clone)]
+struct Window {
+    opened: Instant,
+    suppressed: usize,
+}
+
+impl RateLimit {
+    /// Creates a [`RateLimit`] that hasn't seen any lines yet.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            recent: HashMap::new(),
+        }
+    }
+
+    /// Returns the line to log for `line` occurring at `now`, or [`None`]
+    /// if it is a repeat to be suppressed.
+    pub fn filter(&mut self, line: String, now: Instant) -> Option<String> {
+        let window = self.window;
+        if let Some(open) = self.recent.get_mut(&line)
+            && now.duration_since(open.opened) < window
+        {
+            open.suppressed += 1;
+            return None;
+        }
+
+        // Forget closed windows with nothing left to report, so distinct
+        // one-off lines don't accumulate.
+        self.recent
+            .retain(|_, open| now.duration_since(open.opened) < window || open.suppressed > 0);
+
+        let suppressed = self
+            .recent
+            .insert(
+                line.clone(),
+                Window {
+                    opened: now,
+                    suppressed: 0,
+                },
+            )
+            .map_or(0, |closed| closed.suppressed);
+
+        match suppressed {
+            0 => Some(line),
+            n => Some(format!("{line} (suppressed {n} repeats)")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::ratelimit::RateLimit;
+
+    #[test]
+    fn repeats_within_window_are_suppressed_and_counted() {
+        let mut limit = RateLimit::new(Duration::from_secs(5));
+        let start = Instant::now();
+
+        assert_eq!(
+            limit.filter(String::from("oops"), start),
+            Some(String::from("oops"))
+        );
+        assert_eq!(limit.filter(String::from("oops"), start), None);
+        assert_eq!(
+            limit.filter(String::from("other"), start),
+            Some(String::from("other"))
+        );
+        assert_eq!(
+            limit.filter(String::from("oops"), start + Duration::from_secs(5)),
+            Some(String::from("oops (suppressed 1 repeats)"))
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// How long after a peer drops it is first dialed again.
+pub const BASE_DELAY: Duration = Duration::from_secs(1);
+/// Longest wait between attempts to dial a peer, which doubles after
+/// every failed one.
+pub const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// The peers a node wants to stay connected to, by the address they listen
+/// on, along with when to next dial the ones that dropped.
+#[derive(Debug, Clone, Default)]
+pub struct Redials {
+    desired: HashMap<SocketAddr, Desired>,
+}
+
+#[derive(Debug, Clone)]
+struct Desired {
+    /// The host name the peer was dialed by, if it was dialed by name.
+    host: Option<String>,
+    /// The next attempt to dial the peer; `None` while it is connected.
+    retry: Option<Retry>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Retry {
+    attempts: u32,
+    at: Instant,
+}
+
+impl Redials {
+    /// Remembers that the peer listening on `addr`, dialed by `host` if
+    /// it was dialed by name, is connected, so it is dialed again once it
+    /// drops.
+    pub fn connected(&mut self, addr: SocketAddr, host: Option<String>) {
+        self.desired.insert(addr, Desired { host, retry: None });
+    }
+
+    /// Returns the desired peers missing from `connected` that are due to
+    /// be dialed again at `now`, along with the host names they were
+    /// dialed by.
+    ///
+    /// A peer is first due `BASE_DELAY` after it is found missing. Every
+    /// time it is returned, the wait until it is due again doubles, until
+    /// it is reported connected again.
+    pub fn due(
+        &mut self,
+        connected: &[SocketAddr],
+        now: Instant,
+    ) -> Vec<(SocketAddr, Option<String>)> {
+        let mut due = Vec::new();
+        for (addr, desired) in &mut self.desired {
+            if connected.contains(addr) {
+                desired.retry = None;
+                continue;
+            }
+            let retry = desired.retry.get_or_insert(Retry {
+                attempts: 0,
+                at: now + delay(0),
+            });
+            if retry.at <= now {
+                retry.attempts += 1;
+                retry.at = now + delay(retry.attempts);
+                due.push((*addr, desired.host.clone()));
+            }
+        }
+        due
+    }
+
+    /// Stops dialing the peer listening on `addr` again.
+    pub fn forget(&mut self, addr: SocketAddr) {
+        self.desired.remove(&addr);
+    }
+
+    /// Stops dialing any peer again, until they connect anew.
+    pub fn forget_all(&mut self) {
+        self.desired.clear();
+    }
+}
+
+/// Returns how long to wait after `attempts` failed attempts to dial a peer.
+fn delay(attempts: u32) -> Duration {
+    BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempts))
+        .min(MAX_DELAY)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::SocketAddr, time::Instant};
+
+    use crate::redial::{BASE_DELAY, MAX_DELAY, Redials};
+
+    #[test]
+    fn dropped_peers_are_redialed_with_backoff() {
+        let addr: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let mut redials = Redials::default();
+        let start = Instant::now();
+        redials.connected(addr, None);
+        assert!(redials.due(&[addr], start).is_empty());
+
+        let mut attempts = Vec::new();
+        let mut now = start;
+        while now < start + MAX_DELAY * 4 {
+            if !redials.due(&[], now).is_empty() {
+                attempts.push(now - start);
+            }
+            now += BASE_DELAY;
+        }
+        let secs: Vec<_> = attempts.iter().map(|at| at.as_secs()).collect();
+        assert_eq!(secs, [1, 3, 7, 15, 31, 63, 123, 183]);
+
+        redials.connected(addr, None);
+        assert!(redials.due(&[addr], now).is_empty());
+        redials.forget_all();
+        assert!(redials.due(&[], now + MAX_DELAY).is_empty());
+    }
+}
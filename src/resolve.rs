@@ -0,0 +1,56 @@
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+};
+
+#[cfg(test)]
+use std::{cell::RefCell, rc::Rc};
+
+/// Turns a `host:port` into the addresses to try connecting to, in order.
+pub trait Resolve {
+    /// Resolves `host`, failing if it has no addresses.
+    fn resolve(&self, host: &str) -> io::Result<Vec<SocketAddr>>;
+}
+
+/// Resolves through the system resolver, so addresses are looked up fresh
+/// every time as far as the system doesn't cache them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct System;
+
+impl Resolve for System {
+    fn resolve(&self, host: &str) -> io::Result<Vec<SocketAddr>> {
+        let addrs: Vec<_> = host.to_socket_addrs()?.collect();
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no addresses found",
+            ));
+        }
+        Ok(addrs)
+    }
+}
+
+/// Resolves every host to the same addresses, which can be changed, for tests.
+///
+/// Clones share the addresses, so a test can keep one to change what a
+/// resolver handed to a node returns.
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+pub struct Fixed {
+    addrs: Rc<RefCell<Vec<SocketAddr>>>,
+}
+
+#[cfg(test)]
+impl Fixed {
+    /// Makes every host resolve to `addrs` from now on.
+    pub fn set(&self, addrs: Vec<SocketAddr>) {
+        *self.addrs.borrow_mut() = addrs;
+    }
+}
+
+#[cfg(test)]
+impl Resolve for Fixed {
+    fn resolve(&self, _host: &str) -> io::Result<Vec<SocketAddr>> {
+        Ok(self.addrs.borrow().clone())
+    }
+}
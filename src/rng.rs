@@ -0,0 +1,81 @@
+use uuid::Uuid;
+
+/// A small, seedable xorshift64* pseudo-random number generator.
+///
+/// Not suitable for anything security related; it only serves to make
+/// randomized behavior reproducible in tests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Creates a generator from a fixed seed.
+    pub fn seeded(seed: u64) -> Self {
+        // A zero state would only ever yield zeroes.
+        Self { state: seed.max(1) }
+    }
+
+    /// Creates a generator seeded from the operating system's randomness.
+    pub fn from_entropy() -> Self {
+        Self::seeded(Uuid::new_v4().as_u64_pair().0)
+    }
+
+    /// Returns the next pseudo-random `u64`.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Returns a pseudo-random `f64` in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Shuffles `items` in place with a Fisher-Yates shuffle.
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = (self.next_u64() % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rng::Rng;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let mut a = Rng::seeded(42);
+        let mut b = Rng::seeded(42);
+
+        assert!((0..16).all(|_| a.next_u64() == b.next_u64()));
+    }
+
+    #[test]
+    fn floats_are_in_unit_interval() {
+        let mut rng = Rng::seeded(7);
+
+        assert!(
+            (0..1000)
+                .map(|_| rng.next_f64())
+                .all(|x| (0.0..1.0).contains(&x))
+        );
+    }
+
+    #[test]
+    fn shuffle_is_a_seeded_permutation() {
+        let mut items: Vec<_> = (0..10).collect();
+        Rng::seeded(3).shuffle(&mut items);
+        let mut again: Vec<_> = (0..10).collect();
+        Rng::seeded(3).shuffle(&mut again);
+
+        assert_eq!(items, again);
+        assert_ne!(items, (0..10).collect::<Vec<_>>());
+        items.sort();
+        assert_eq!(items, (0..10).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,130 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use uuid::Uuid;
+
+use crate::queue::Queue;
+
+/// An append-only log of seen message UUIDs, persisted across restarts.
+///
+/// Only the `bound` most recent UUIDs are kept in memory and loaded at
+/// startup. Since the file only ever grows by appending, it is compacted
+/// down to those UUIDs whenever it holds twice as many lines.
+#[derive(Debug)]
+pub struct SeenLog {
+    path: PathBuf,
+    file: File,
+    uuids: Queue<Uuid>,
+    bound: usize,
+    lines: usize,
+}
+
+impl SeenLog {
+    /// Opens the log at `path`, loading its `bound` most recent UUIDs.
+    ///
+    /// A missing file is treated as an empty log and created.
+    pub fn open(path: impl Into<PathBuf>, bound: usize) -> io::Result<Self> {
+        let path = path.into();
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+
+        let mut uuids = Queue::new(bound);
+        let mut lines = 0;
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            let uuid = line
+                .trim()
+                .parse()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            uuids.push(uuid);
+            lines += 1;
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        Ok(Self {
+            path,
+            file,
+            uuids,
+            bound,
+            lines,
+        })
+    }
+
+    /// Returns `true` if `uuid` is among the most recently recorded UUIDs.
+    pub fn contains(&self, uuid: Uuid) -> bool {
+        self.uuids.contains(&uuid)
+    }
+
+    /// Records `uuid` as seen, appending it to the file.
+    pub fn record(&mut self, uuid: Uuid) -> io::Result<()> {
+        self.uuids.push(uuid);
+        writeln!(self.file, "{uuid}")?;
+        self.lines += 1;
+
+        if self.lines >= 2 * self.bound {
+            self.compact()?;
+        }
+
+        Ok(())
+    }
+
+    /// Rewrites the file to hold only the UUIDs kept in memory.
+    fn compact(&mut self) -> io::Result<()> {
+        let tmp = self.path.with_extension("compacting");
+        let mut contents = String::new();
+        for uuid in &self.uuids {
+            contents.push_str(&format!("{uuid}\n"));
+        }
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, &self.path)?;
+
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.lines = self.uuids.len();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use uuid::Uuid;
+
+    use crate::{seenlog::SeenLog, test::temp_path};
+
+    #[test]
+    fn reopened_log_remembers_recent_uuids() {
+        let path = temp_path("seenlog");
+        let uuids: Vec<_> = (0..3).map(|_| Uuid::new_v4()).collect();
+
+        let mut log = SeenLog::open(&path, 2).unwrap();
+        uuids.iter().for_each(|uuid| log.record(*uuid).unwrap());
+        drop(log);
+
+        let log = SeenLog::open(&path, 2).unwrap();
+        assert!(!log.contains(uuids[0]));
+        assert!(log.contains(uuids[1]));
+        assert!(log.contains(uuids[2]));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn log_is_compacted() {
+        let path = temp_path("seenlog");
+
+        let mut log = SeenLog::open(&path, 2).unwrap();
+        (0..4).for_each(|_| log.record(Uuid::new_v4()).unwrap());
+
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        fs::remove_file(path).unwrap();
+    }
+}
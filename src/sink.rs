@@ -0,0 +1,488 @@
+use std::{fmt, io, net::SocketAddr, str::FromStr, time::Instant};
+
+use crate::{display::Printer, msg::Msg, ratelimit::RateLimit, throughput::Throughput};
+
+/// Something that happened on the node other than a message arriving.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// The node started listening on the address, along with its
+    /// configuration when running verbosely.
+    Listening {
+        addr: SocketAddr,
+        config: Option<String>,
+    },
+    /// Something happened to the connection to a peer.
+    Peer(PeerEvent),
+    /// A connection from a peer was refused.
+    Rejected { addr: SocketAddr, reason: String },
+    /// A message was written to a peer.
+    Sent { to: SocketAddr, bytes: usize },
+    /// Feedback on a command, or an error the node recovered from.
+    Notice(String),
+    /// As many peers as the node waits for are connected, which is only
+    /// reported the first time.
+    Ready { peers: usize },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Listening { addr, config: None } => write!(f, "starting on {addr}"),
+            Event::Listening {
+                addr,
+                config: Some(config),
+            } => write!(f, "starting on {addr} with {config}"),
+            Event::Peer(ev) => write!(f, "{ev}"),
+            Event::Rejected { addr, reason } => write!(f, "rejected peer {addr}: {reason}"),
+            Event::Sent { to, bytes } => write!(f, "written {bytes} bytes to {to}"),
+            Event::Notice(notice) => write!(f, "{notice}"),
+            Event::Ready { peers } => write!(f, "ready with {peers} peers"),
+        }
+    }
+}
+
+impl Event {
+    /// Returns how important the event is to the user of the node.
+    pub fn level(&self) -> Level {
+        match self {
+            Event::Sent { .. } => Level::Debug,
+            Event::Listening { .. } | Event::Peer(_) | Event::Notice(_) | Event::Ready { .. } => {
+                Level::Info
+            }
+            Event::Rejected { .. } => Level::Warn,
+        }
+    }
+}
+
+/// How important an event is, from least to most.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Protocol details, like every write to a peer.
+    Debug,
+    /// What the node is doing, like peers coming and going.
+    #[default]
+    Info,
+    /// Something went wrong, like a peer being refused.
+    Warn,
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("unknown log level `{0}`, expected `debug`, `info` or `warn`")]
+pub struct ParseLevelError(String);
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for Level {
+    type Err = ParseLevelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "debug" => Ok(Level::Debug),
+            "info" => Ok(Level::Info),
+            "warn" => Ok(Level::Warn),
+            s => Err(ParseLevelError(s.to_string())),
+        }
+    }
+}
+
+/// Something that happened to the connection to a peer, as handed to
+/// subscribers of a node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerEvent {
+    /// A connection to a peer was established.
+    Connected(SocketAddr),
+    /// The hello of a peer arrived, advertising the address it listens on.
+    Handshaken {
+        addr: SocketAddr,
+        listening: SocketAddr,
+    },
+    /// The connection to a peer was closed.
+    Disconnected {
+        addr: SocketAddr,
+        reason: DisconnectReason,
+    },
+}
+
+impl fmt::Display for PeerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerEvent::Connected(addr) => write!(f, "new peer {addr}"),
+            PeerEvent::Handshaken { addr, listening } => {
+                write!(f, "peer {addr} listens on {listening}")
+            }
+            PeerEvent::Disconnected {
+                addr,
+                reason: DisconnectReason::Closed,
+            } => write!(f, "peer {addr} disconnected"),
+            PeerEvent::Disconnected { addr, reason } => {
+                write!(f, "disconnected peer {addr}: {reason}")
+            }
+        }
+    }
+}
+
+/// Why the connection to a peer was closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The peer closed it.
+    Closed,
+    /// The IP of the peer was blocked.
+    Blocked,
+    /// It was closed to dial the peer again.
+    Reconnecting,
+    /// The peer wasn't heard from for too long.
+    TimedOut,
+    /// Reading from the peer failed with an error that broke the connection.
+    Failed(io::ErrorKind),
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisconnectReason::Closed => write!(f, "closed by peer"),
+            DisconnectReason::Blocked => write!(f, "blocked"),
+            DisconnectReason::Reconnecting => write!(f, "reconnecting"),
+            DisconnectReason::TimedOut => write!(f, "timed out"),
+            DisconnectReason::Failed(kind) => write!(f, "failed: {kind}"),
+        }
+    }
+}
+
+/// A callback subscribed to the peer events of a node.
+pub type Subscriber = Box<dyn FnMut(&PeerEvent)>;
+
+/// Receives everything the node wants to show to its user.
+///
+/// The node never prints on its own; messages and events are handed to
+/// a sink, so embedders can deliver them to e.g. a GUI instead of stdout.
+pub trait MessageSink {
+    /// Called for every new message received from the peer `from`.
+    fn on_message(&mut self, msg: &Msg, from: SocketAddr);
+    /// Called for every event on the node.
+    fn on_event(&mut self, ev: &Event);
+    /// Called when the user changes the least [`Level`] of the events to
+    /// show. Sinks that show every event ignore it.
+    fn set_level(&mut self, _level: Level) {}
+}
+
+/// The default sink, printing messages to stdout and the events of at
+/// least its [`Level`] to stderr, so the output of the chat stays clean.
+#[derive(Debug)]
+pub struct Stdout {
+    printer: Printer,
+    level: Level,
+}
+
+impl Stdout {
+    pub fn new(printer: Printer) -> Self {
+        Self {
+            printer,
+            level: Level::default(),
+        }
+    }
+
+    /// Only prints events of at least `level`.
+    pub fn with_level(self, level: Level) -> Self {
+        Self { level, ..self }
+    }
+
+    fn shows(&self, ev: &Event) -> bool {
+        ev.level() >= self.level
+    }
+}
+
+impl MessageSink for Stdout {
+    fn on_message(&mut self, msg: &Msg, from: SocketAddr) {
+        println!("{}", self.printer.render(from, msg));
+    }
+
+    fn on_event(&mut self, ev: &Event) {
+        if self.shows(ev) {
+            eprintln!("{ev}");
+        }
+    }
+
+    fn set_level(&mut self, level: Level) {
+        self.level = level;
+    }
+}
+
+/// A sink ignoring everything, for nodes nobody is watching.
+#[derive(Debug, Default)]
+pub struct Discard;
+
+impl MessageSink for Discard {
+    fn on_message(&mut self, _msg: &Msg, _from: SocketAddr) {}
+
+    fn on_event(&mut self, _ev: &Event) {}
+}
+
+/// A sink passing everything on to another, counting the messages.
+#[derive(Debug)]
+pub struct Counted<S> {
+    sink: S,
+    messages: usize,
+}
+
+impl<S: MessageSink> Counted<S> {
+    pub fn new(sink: S) -> Self {
+        Self { sink, messages: 0 }
+    }
+
+    /// Returns the number of messages received so far.
+    pub fn messages(&self) -> usize {
+        self.messages
+    }
+}
+
+impl<S: MessageSink> MessageSink for Counted<S> {
+    fn on_message(&mut self, msg: &Msg, from: SocketAddr) {
+        self.messages += 1;
+        self.sink.on_message(msg, from);
+    }
+
+    fn on_event(&mut self, ev: &Event) {
+        self.sink.on_event(ev);
+    }
+
+    fn set_level(&mut self, level: Level) {
+        self.sink.set_level(level);
+    }
+}
+
+/// A sink passing everything on to another, metering the rate of messages.
+///
+/// Every message is counted as received at the time the sink was created
+/// with, as it lives for a single pass over the peers.
+#[derive(Debug)]
+pub struct Metered<'a, S> {
+    sink: S,
+    throughput: &'a mut Throughput,
+    now: Instant,
+}
+
+impl<'a, S: MessageSink> Metered<'a, S> {
+    pub fn new(sink: S, throughput: &'a mut Throughput, now: Instant) -> Self {
+        Self {
+            sink,
+            throughput,
+            now,
+        }
+    }
+}
+
+impl<S: MessageSink> MessageSink for Metered<'_, S> {
+    fn on_message(&mut self, msg: &Msg, from: SocketAddr) {
+        self.throughput.record(self.now);
+        self.sink.on_message(msg, from);
+    }
+
+    fn on_event(&mut self, ev: &Event) {
+        self.sink.on_event(ev);
+    }
+}
+
+/// A sink passing everything on to another, handing peer events to every
+/// subscriber as well.
+pub struct Notifying<'a, S> {
+    sink: &'a mut S,
+    subscribers: &'a mut [Subscriber],
+}
+
+impl<'a, S: MessageSink> Notifying<'a, S> {
+    pub fn new(sink: &'a mut S, subscribers: &'a mut [Subscriber]) -> Self {
+        Self { sink, subscribers }
+    }
+}
+
+impl<S: MessageSink> MessageSink for Notifying<'_, S> {
+    fn on_message(&mut self, msg: &Msg, from: SocketAddr) {
+        self.sink.on_message(msg, from);
+    }
+
+    fn on_event(&mut self, ev: &Event) {
+        if let Event::Peer(peer_ev) = ev {
+            self.subscribers
+                .iter_mut()
+                .for_each(|subscriber| subscriber(peer_ev));
+        }
+        self.sink.on_event(ev);
+    }
+}
+
+/// A sink passing everything on to another, except for messages whose text
+/// doesn't contain `filter`, if set.
+#[derive(Debug)]
+pub struct Filtered<'a, S> {
+    sink: &'a mut S,
+    filter: Option<&'a str>,
+}
+
+impl<'a, S: MessageSink> Filtered<'a, S> {
+    pub fn new(sink: &'a mut S, filter: Option<&'a str>) -> Self {
+        Self { sink, filter }
+    }
+}
+
+impl<S: MessageSink> MessageSink for Filtered<'_, S> {
+    fn on_message(&mut self, msg: &Msg, from: SocketAddr) {
+        if self.filter.is_none_or(|filter| msg.text().contains(filter)) {
+            self.sink.on_message(msg, from);
+        }
+    }
+
+    fn on_event(&mut self, ev: &Event) {
+        self.sink.on_event(ev);
+    }
+}
+
+/// A sink passing everything on to another, collecting the messages to
+/// echo back to their sender into `echoes`, if set.
+///
+/// Messages without hops left aren't collected, so echoes, which are sent
+/// without any, aren't echoed in turn by another echo server.
+#[derive(Debug)]
+pub struct Echoing<'a, S> {
+    sink: S,
+    echoes: Option<&'a mut Vec<(Msg, SocketAddr)>>,
+}
+
+impl<'a, S: MessageSink> Echoing<'a, S> {
+    pub fn new(sink: S, echoes: Option<&'a mut Vec<(Msg, SocketAddr)>>) -> Self {
+        Self { sink, echoes }
+    }
+}
+
+impl<S: MessageSink> MessageSink for Echoing<'_, S> {
+    fn on_message(&mut self, msg: &Msg, from: SocketAddr) {
+        if let Some(echoes) = self.echoes.as_mut().filter(|_| msg.ttl() > 0) {
+            echoes.push((msg.clone(), from));
+        }
+        self.sink.on_message(msg, from);
+    }
+
+    fn on_event(&mut self, ev: &Event) {
+        self.sink.on_event(ev);
+    }
+}
+
+/// A sink passing everything on to another, except for repeated notices.
+///
+/// Used on paths where the same error can recur on every pass of the loop,
+/// so a misbehaving peer can't flood the output. Notices are limited as of
+/// the time the sink was created with.
+#[derive(Debug)]
+pub struct Throttled<'a, S> {
+    sink: &'a mut S,
+    limit: &'a mut RateLimit,
+    now: Instant,
+}
+
+impl<'a, S: MessageSink> Throttled<'a, S> {
+    pub fn new(sink: &'a mut S, limit: &'a mut RateLimit, now: Instant) -> Self {
+        Self { sink, limit, now }
+    }
+}
+
+impl<S: MessageSink> MessageSink for Throttled<'_, S> {
+    fn on_message(&mut self, msg: &Msg, from: SocketAddr) {
+        self.sink.on_message(msg, from);
+    }
+
+    fn on_event(&mut self, ev: &Event) {
+        match ev {
+            Event::Notice(notice) => {
+                if let Some(notice) = self.limit.filter(notice.clone(), self.now) {
+                    self.sink.on_event(&Event::Notice(notice));
+                }
+            }
+            ev => self.sink.on_event(ev),
+        }
+    }
+}
+
+/// A sink recording everything it receives, for tests.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct Recorder {
+    pub messages: Vec<(Msg, SocketAddr)>,
+    pub events: Vec<Event>,
+}
+
+#[cfg(test)]
+impl MessageSink for Recorder {
+    fn on_message(&mut self, msg: &Msg, from: SocketAddr) {
+        self.messages.push((msg.clone(), from));
+    }
+
+    fn on_event(&mut self, ev: &Event) {
+        self.events.push(ev.clone());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        display::Printer,
+        ratelimit::RateLimit,
+        sink::{Event, Level, MessageSink, Recorder, Stdout, Throttled},
+    };
+
+    #[test]
+    fn writes_are_only_shown_at_debug_level() {
+        let sent = Event::Sent {
+            to: "127.0.0.1:9000".parse().unwrap(),
+            bytes: 512,
+        };
+        let notice = Event::Notice(String::from("forgot 3f2a9c1e"));
+        let stdout = Stdout::new(Printer::new(false));
+
+        assert!(!stdout.shows(&sent));
+        assert!(stdout.shows(&notice));
+        assert!(stdout.with_level(Level::Debug).shows(&sent));
+        assert_eq!("warn".parse::<Level>().unwrap(), Level::Warn);
+        assert!("loud".parse::<Level>().is_err());
+    }
+
+    #[test]
+    fn level_can_be_lowered_at_runtime() {
+        let sent = Event::Sent {
+            to: "127.0.0.1:9000".parse().unwrap(),
+            bytes: 512,
+        };
+        let mut stdout = Stdout::new(Printer::new(false));
+        assert!(!stdout.shows(&sent));
+
+        stdout.set_level(Level::Debug);
+        assert!(stdout.shows(&sent));
+        stdout.set_level(Level::Warn);
+        assert!(!stdout.shows(&Event::Notice(String::from("hi"))));
+    }
+
+    #[test]
+    fn identical_notices_are_throttled() {
+        let mut recorder = Recorder::default();
+        let mut limit = RateLimit::new(Duration::from_secs(60));
+        let mut sink = Throttled::new(&mut recorder, &mut limit, Instant::now());
+
+        for _ in 0..100 {
+            sink.on_event(&Event::Notice(String::from("corrupt frame")));
+        }
+
+        assert_eq!(
+            recorder.events,
+            vec![Event::Notice(String::from("corrupt frame"))]
+        );
+    }
+}
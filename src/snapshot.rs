@@ -0,0 +1,49 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    net::SocketAddr,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{hex, msg::Msg, stats::Drops};
+
+/// The in-memory state of a node, without its live connections, for
+/// reproducing bugs and setting up tests.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The identity of every connected peer.
+    pub peers: Vec<SocketAddr>,
+    /// Every seen message, oldest first, as its frame in hex.
+    pub seen: Vec<String>,
+    /// Frames dropped on the way in, by reason.
+    pub drops: Drops,
+}
+
+impl Snapshot {
+    /// Decodes the seen messages, oldest first.
+    pub fn messages(&self) -> io::Result<Vec<Msg>> {
+        self.seen
+            .iter()
+            .map(|frame| {
+                let frame = hex::decode(frame)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                Msg::try_from(frame).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+            })
+            .collect()
+    }
+
+    /// Writes the snapshot to `path` as JSON.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut file, self)?;
+        file.write_all(b"\n")?;
+        file.flush()
+    }
+
+    /// Reads a snapshot written to `path` by [`Snapshot::save`].
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+};
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const CMD_CONNECT: u8 = 1;
+const RESERVED: u8 = 0;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+const SUCCEEDED: u8 = 0;
+
+/// Opens a TCP connection to `target` through the SOCKS5 proxy at `proxy`.
+///
+/// Only the unauthenticated method is offered. Once the CONNECT request
+/// succeeds, the returned stream is a plain tunnel to `target`.
+pub fn connect(proxy: SocketAddr, target: SocketAddr) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(proxy)?;
+
+    stream.write_all(&[VERSION, 1, NO_AUTH])?;
+    let mut choice = [0; 2];
+    stream.read_exact(&mut choice)?;
+    if choice != [VERSION, NO_AUTH] {
+        return Err(invalid("proxy refused unauthenticated access"));
+    }
+
+    stream.write_all(&request(target))?;
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != VERSION {
+        return Err(invalid("proxy replied with unknown version"));
+    }
+    if reply[1] != SUCCEEDED {
+        return Err(invalid(&format!(
+            "proxy CONNECT failed with code {}",
+            reply[1]
+        )));
+    }
+
+    // The bound address isn't needed, but must be consumed from the stream.
+    let bound_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        _ => return Err(invalid("proxy replied with unknown address type")),
+    };
+    let mut bound = vec![0; bound_len + 2];
+    stream.read_exact(&mut bound)?;
+
+    Ok(stream)
+}
+
+/// Builds the CONNECT request for `target`.
+fn request(target: SocketAddr) -> Vec<u8> {
+    let mut request = vec![VERSION, CMD_CONNECT, RESERVED];
+    match target {
+        SocketAddr::V4(addr) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+        SocketAddr::V6(addr) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+    }
+    request.extend_from_slice(&target.port().to_be_bytes());
+    request
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read, Write},
+        net::{SocketAddr, TcpListener},
+        thread::spawn,
+    };
+
+    use crate::socks;
+
+    #[test]
+    fn connect_performs_handshake() {
+        let proxy = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        let target: SocketAddr = "10.1.2.3:9000".parse().unwrap();
+
+        let server = spawn(move || {
+            let (mut stream, _) = proxy.accept().unwrap();
+
+            let mut greeting = [0; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            stream.write_all(&[5, 0]).unwrap();
+
+            let mut request = [0; 10];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+
+            let mut payload = [0; 4];
+            stream.read_exact(&mut payload).unwrap();
+
+            (greeting, request, payload)
+        });
+
+        let mut stream = socks::connect(proxy_addr, target).unwrap();
+        stream.write_all(b"ping").unwrap();
+
+        let (greeting, request, payload) = server.join().unwrap();
+        assert_eq!(greeting, [5, 1, 0]);
+        assert_eq!(request, [5, 1, 0, 1, 10, 1, 2, 3, 0x23, 0x28]);
+        assert_eq!(&payload, b"ping");
+    }
+
+    #[test]
+    fn connect_fails_on_rejected_request() {
+        let proxy = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+
+        spawn(move || {
+            let (mut stream, _) = proxy.accept().unwrap();
+            let mut buf = [0; 10];
+            stream.read_exact(&mut buf[..3]).unwrap();
+            stream.write_all(&[5, 0]).unwrap();
+            stream.read_exact(&mut buf).unwrap();
+            stream.write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+        });
+
+        assert!(socks::connect(proxy_addr, "10.1.2.3:9000".parse().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{hex, msg::Msg, queue::DedupQueue};
+
+/// Version of the state file format, bumped whenever it changes so that
+/// files written by other versions are recognized rather than misread.
+pub const VERSION: u32 = 1;
+
+/// The seen messages of a node, kept across restarts so it doesn't print
+/// and propagate again the messages it handled before.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct State {
+    version: u32,
+    /// Every seen message, oldest first, as its frame in hex.
+    seen: Vec<String>,
+}
+
+/// Writes the messages in `seen` to `path` as JSON, oldest first.
+pub fn save(path: &Path, seen: &DedupQueue<Msg>) -> io::Result<()> {
+    let state = State {
+        version: VERSION,
+        seen: seen
+            .iter()
+            .map(|msg| hex::encode(&msg.clone().into_bytes()))
+            .collect(),
+    };
+    let mut file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(&mut file, &state)?;
+    file.write_all(b"\n")?;
+    file.flush()
+}
+
+/// Reads the messages saved to `path` by [`save`], oldest first.
+///
+/// A missing file holds no messages. A file of another version, or with
+/// any message that doesn't decode, fails the whole read.
+pub fn load(path: &Path) -> io::Result<Vec<Msg>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let state: State = serde_json::from_str(&contents)?;
+    if state.version != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported version {}, expected {VERSION}", state.version),
+        ));
+    }
+
+    state
+        .seen
+        .iter()
+        .map(|frame| {
+            let frame = hex::decode(frame)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            Msg::try_from(frame).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::{msg::Msg, queue::DedupQueue, state, test::temp_path};
+
+    #[test]
+    fn saved_messages_are_still_seen_after_loading() {
+        let path = temp_path("state");
+        let mut seen = DedupQueue::new(16);
+        for text in ["hello", "world"] {
+            seen.push(Msg::new(text).unwrap());
+        }
+        state::save(&path, &seen).unwrap();
+
+        let mut reloaded = DedupQueue::new(16);
+        for msg in state::load(&path).unwrap() {
+            reloaded.push(msg);
+        }
+
+        assert!(seen.iter().all(|msg| reloaded.contains(msg)));
+        assert!(reloaded.iter().eq(seen.iter()));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn missing_file_is_empty_and_other_versions_fail() {
+        let path = temp_path("state");
+        assert!(state::load(&path).unwrap().is_empty());
+
+        fs::write(&path, "{\"version\":2,\"seen\":[]}").unwrap();
+        assert!(state::load(&path).is_err());
+        fs::write(&path, "not json").unwrap();
+        assert!(state::load(&path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
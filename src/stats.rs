@@ -0,0 +1,79 @@
+use std::{collections::BTreeMap, fmt, ops::AddAssign};
+
+use serde::{Deserialize, Serialize};
+
+/// Why a frame read from, or meant for, a peer was dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DropReason {
+    /// The message had already been seen.
+    Duplicate,
+    /// The frame couldn't be decoded into a message.
+    Malformed,
+    /// The message was past its expiry.
+    Expired,
+    /// An outbound frame was lost to injected faults.
+    InjectedLoss,
+    /// The buffer of frames held while paused was full.
+    PauseOverflow,
+    /// Not every fragment of the message arrived in time.
+    Incomplete,
+}
+
+impl DropReason {
+    pub const ALL: [DropReason; 6] = [
+        DropReason::Duplicate,
+        DropReason::Malformed,
+        DropReason::Expired,
+        DropReason::InjectedLoss,
+        DropReason::PauseOverflow,
+        DropReason::Incomplete,
+    ];
+}
+
+impl fmt::Display for DropReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DropReason::Duplicate => "duplicate",
+            DropReason::Malformed => "malformed",
+            DropReason::Expired => "expired",
+            DropReason::InjectedLoss => "injected-loss",
+            DropReason::PauseOverflow => "pause-overflow",
+            DropReason::Incomplete => "incomplete",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// How many peers a message was written to, and how many were dropped for
+/// failing the write.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Delivery {
+    pub delivered: usize,
+    pub failed: usize,
+}
+
+impl AddAssign for Delivery {
+    fn add_assign(&mut self, other: Self) {
+        self.delivered += other.delivered;
+        self.failed += other.failed;
+    }
+}
+
+/// Counts of dropped frames, by reason.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Drops {
+    counts: BTreeMap<DropReason, u64>,
+}
+
+impl Drops {
+    /// Counts one more frame dropped for `reason`.
+    pub fn record(&mut self, reason: DropReason) {
+        *self.counts.entry(reason).or_default() += 1;
+    }
+
+    /// Returns the number of frames dropped for `reason`.
+    pub fn get(&self, reason: DropReason) -> u64 {
+        self.counts.get(&reason).copied().unwrap_or(0)
+    }
+}
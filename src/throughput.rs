@@ -0,0 +1,135 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// The longest window a rate is averaged over.
+const LONGEST: Duration = Duration::from_secs(5 * 60);
+
+/// Messages per second, over a sliding window of whole seconds.
+///
+/// Messages are counted per second since the [`Throughput`] was created,
+/// keeping the counts of the last five minutes. Rates only cover seconds
+/// that have fully passed, so the second in progress doesn't drag them
+/// down, and are averaged over the time elapsed if that is shorter than
+/// the window.
+#[derive(Debug, Clone)]
+pub struct Throughput {
+    start: Instant,
+    counts: VecDeque<(u64, u64)>,
+    total: u64,
+}
+
+/// The rates of a [`Throughput`] over the last second, minute and five minutes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rates {
+    pub current: f64,
+    pub minute: f64,
+    pub five_minutes: f64,
+}
+
+impl fmt::Display for Rates {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.1}/s now, {:.1}/s over 1m, {:.1}/s over 5m",
+            self.current, self.minute, self.five_minutes
+        )
+    }
+}
+
+impl Throughput {
+    /// Creates a [`Throughput`] that hasn't counted any messages yet.
+    pub fn new(start: Instant) -> Self {
+        Self {
+            start,
+            counts: VecDeque::new(),
+            total: 0,
+        }
+    }
+
+    /// Returns the number of messages counted since the start, including
+    /// those older than any window.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Counts one more message at `now`.
+    pub fn record(&mut self, now: Instant) {
+        self.total += 1;
+        let second = self.second(now);
+        match self.counts.back_mut() {
+            Some((at, count)) if *at == second => *count += 1,
+            _ => self.counts.push_back((second, 1)),
+        }
+
+        while self
+            .counts
+            .front()
+            .is_some_and(|&(at, _)| at + LONGEST.as_secs() < second)
+        {
+            self.counts.pop_front();
+        }
+    }
+
+    /// Returns the rates as of `now`.
+    pub fn rates(&self, now: Instant) -> Rates {
+        Rates {
+            current: self.rate(Duration::from_secs(1), now),
+            minute: self.rate(Duration::from_secs(60), now),
+            five_minutes: self.rate(LONGEST, now),
+        }
+    }
+
+    /// Returns the messages per second over the `window` of whole seconds
+    /// before `now`.
+    fn rate(&self, window: Duration, now: Instant) -> f64 {
+        let second = self.second(now);
+        let seconds = window.as_secs().min(second);
+        if seconds == 0 {
+            return 0.0;
+        }
+
+        let count: u64 = self
+            .counts
+            .iter()
+            .filter(|&&(at, _)| at < second && at >= second - seconds)
+            .map(|&(_, count)| count)
+            .sum();
+        count as f64 / seconds as f64
+    }
+
+    fn second(&self, now: Instant) -> u64 {
+        now.duration_since(self.start).as_secs()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::throughput::Throughput;
+
+    #[test]
+    fn steady_rate_is_measured_over_every_window() {
+        let start = Instant::now();
+        let mut throughput = Throughput::new(start);
+
+        // Ten messages a second for two minutes.
+        for tick in 0..1200 {
+            throughput.record(start + Duration::from_millis(100 * tick));
+        }
+
+        let rates = throughput.rates(start + Duration::from_secs(120));
+        assert!((rates.current - 10.0).abs() < 0.01);
+        assert!((rates.minute - 10.0).abs() < 0.01);
+        assert!((rates.five_minutes - 10.0).abs() < 0.01);
+
+        let rates = throughput.rates(start + Duration::from_secs(150));
+        assert_eq!(rates.current, 0.0);
+        assert!((rates.minute - 5.0).abs() < 0.01);
+        assert!((rates.five_minutes - 8.0).abs() < 0.01);
+        assert_eq!(throughput.total(), 1200);
+    }
+}
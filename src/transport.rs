@@ -0,0 +1,144 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+};
+
+#[cfg(test)]
+use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+/// A connection to a peer, as far as the node uses it.
+///
+/// Peers are generic over their transport, so propagation can be tested
+/// without real sockets. Like a nonblocking [`TcpStream`], reading from a
+/// transport with nothing to read fails with [`io::ErrorKind::WouldBlock`].
+pub trait Transport: Read + Write {
+    /// Returns the address of the other end.
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+    /// Returns the address of our end.
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+    /// Closes the connection in both directions.
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+impl Transport for TcpStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::local_addr(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+/// An in-memory transport, for tests.
+///
+/// Clones share the same connection, so a test can keep one to feed bytes
+/// to, and collect bytes from, a transport handed to a peer.
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct Mock {
+    local: SocketAddr,
+    remote: SocketAddr,
+    state: Rc<RefCell<MockState>>,
+}
+
+#[cfg(test)]
+#[derive(Debug, Default)]
+struct MockState {
+    inbound: VecDeque<u8>,
+    outbound: Vec<u8>,
+    closed: bool,
+    /// Errors the next reads or writes fail with, in order.
+    failures: VecDeque<io::ErrorKind>,
+}
+
+#[cfg(test)]
+impl Mock {
+    /// Creates an open connection from `local` to `remote`.
+    pub fn new(local: SocketAddr, remote: SocketAddr) -> Self {
+        Self {
+            local,
+            remote,
+            state: Rc::default(),
+        }
+    }
+
+    /// Makes `bytes` available to read, as if the other end sent them.
+    pub fn feed(&self, bytes: &[u8]) {
+        self.state.borrow_mut().inbound.extend(bytes);
+    }
+
+    /// Takes the bytes written so far.
+    pub fn written(&self) -> Vec<u8> {
+        std::mem::take(&mut self.state.borrow_mut().outbound)
+    }
+
+    /// Makes the next read or write fail with `kind`, after any failures
+    /// set before.
+    pub fn fail(&self, kind: io::ErrorKind) {
+        self.state.borrow_mut().failures.push_back(kind);
+    }
+
+    /// Closes the connection from the other end, so reads see its end once
+    /// everything fed has been read.
+    pub fn close(&self) {
+        self.state.borrow_mut().closed = true;
+    }
+}
+
+#[cfg(test)]
+impl Read for Mock {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.borrow_mut();
+        if let Some(kind) = state.failures.pop_front() {
+            return Err(kind.into());
+        }
+        if state.inbound.is_empty() {
+            return if state.closed {
+                Ok(0)
+            } else {
+                Err(io::ErrorKind::WouldBlock.into())
+            };
+        }
+        state.inbound.read(buf)
+    }
+}
+
+#[cfg(test)]
+impl Write for Mock {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.borrow_mut();
+        if let Some(kind) = state.failures.pop_front() {
+            return Err(kind.into());
+        }
+        if state.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        state.outbound.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Transport for Mock {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.remote)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        self.close();
+        Ok(())
+    }
+}
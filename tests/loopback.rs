@@ -0,0 +1,90 @@
+use std::{
+    net::{SocketAddr, TcpListener},
+    time::{Duration, Instant},
+};
+
+use dust::{
+    Dialer, broadcast,
+    clock::{Clock, System},
+    connect,
+    fanout::Fanout,
+    listen,
+    msg::{CAPACITY, Msg},
+    peer::{Framing, Peer},
+    queue::DedupQueue,
+    receive_msgs,
+    sink::{Event, MessageSink},
+    stats::Drops,
+};
+
+/// A sink keeping the messages it receives.
+#[derive(Default)]
+struct Inbox {
+    messages: Vec<(Msg, SocketAddr)>,
+}
+
+impl MessageSink for Inbox {
+    fn on_message(&mut self, msg: &Msg, from: SocketAddr) {
+        self.messages.push((msg.clone(), from));
+    }
+
+    fn on_event(&mut self, _ev: &Event) {}
+}
+
+#[test]
+fn message_crosses_between_two_nodes() {
+    let bind = || TcpListener::bind("127.0.0.1:0").unwrap();
+    let (receiver_addr, incoming) = listen(bind(), 1, CAPACITY, Framing::Fixed).unwrap();
+    let (sender_addr, _) = listen(bind(), 2, CAPACITY, Framing::Fixed).unwrap();
+
+    let mut sender_peers = Vec::new();
+    let mut inbox = Inbox::default();
+    connect(
+        &mut sender_peers,
+        receiver_addr,
+        None,
+        Dialer {
+            listening: sender_addr,
+            nonce: 2,
+            proxy: None,
+            capacity: CAPACITY,
+            max_peers: None,
+            framing: Framing::Fixed,
+        },
+        System.now_instant(),
+        &mut None,
+        &mut inbox,
+    )
+    .unwrap();
+    let mut receiver_peers: Vec<Peer> = vec![incoming.recv().unwrap().unwrap()];
+
+    let msg = Msg::new("over loopback").unwrap();
+    let (sender_peers, delivery) = broadcast(
+        sender_peers,
+        &[msg.clone().into_bytes()],
+        &mut Fanout::default(),
+        System.now_instant(),
+        &mut inbox,
+    );
+    assert_eq!(sender_peers.len(), 1);
+    assert_eq!(delivery.delivered, 1);
+
+    let mut seen = DedupQueue::new(16);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while inbox.messages.is_empty() && Instant::now() < deadline {
+        receiver_peers = receive_msgs(
+            receiver_peers,
+            &mut seen,
+            &mut None,
+            &mut Drops::default(),
+            &mut Fanout::default(),
+            &System,
+            &mut inbox,
+        );
+    }
+
+    assert_eq!(inbox.messages.len(), 1);
+    assert_eq!(inbox.messages[0].0, msg);
+    assert_eq!(receiver_peers[0].identity(), sender_addr);
+    assert!(seen.contains(&msg));
+}
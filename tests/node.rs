@@ -0,0 +1,69 @@
+use std::{
+    cell::RefCell,
+    net::SocketAddr,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use dust::{
+    msg::Msg,
+    node::{Config, Node},
+    sink::{Event, MessageSink, PeerEvent},
+};
+
+/// A sink keeping the messages it receives.
+#[derive(Default)]
+struct Inbox {
+    messages: Vec<Msg>,
+}
+
+impl MessageSink for Inbox {
+    fn on_message(&mut self, msg: &Msg, _from: SocketAddr) {
+        self.messages.push(msg.clone());
+    }
+
+    fn on_event(&mut self, _ev: &Event) {}
+}
+
+fn bind() -> Node<Inbox> {
+    let config = Config {
+        ready_at: Some(1),
+        ..Config::default()
+    };
+    Node::bind("127.0.0.1:0".parse().unwrap(), config, Inbox::default()).unwrap()
+}
+
+#[test]
+fn embedded_nodes_connect_and_track_their_broadcasts() {
+    let mut sender = bind();
+    let mut receiver = bind();
+    assert_ne!(receiver.local_addr().port(), 0);
+
+    let connected = Rc::new(RefCell::new(Vec::new()));
+    let events = connected.clone();
+    receiver.on_peer_event(move |ev| {
+        if let PeerEvent::Connected(addr) = ev {
+            events.borrow_mut().push(*addr);
+        }
+    });
+
+    sender.connect(receiver.local_addr()).unwrap();
+    assert!(sender.wait_ready(Duration::from_secs(5)));
+    assert!(receiver.wait_ready(Duration::from_secs(5)));
+    assert_eq!(sender.connected_count(), 1);
+    assert_eq!(receiver.connected_count(), 1);
+    assert_eq!(connected.borrow().len(), 1);
+
+    let uuid = sender.broadcast("over loopback", None).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while receiver.sink().messages.is_empty() && Instant::now() < deadline {
+        sender.poll();
+        receiver.poll();
+    }
+
+    let [msg] = &receiver.sink().messages[..] else {
+        panic!("expected one message, got {:?}", receiver.sink().messages);
+    };
+    assert_eq!(msg.uuid(), uuid);
+    assert_eq!(msg.text(), "over loopback");
+}